@@ -39,9 +39,48 @@ CREATE TABLE IF NOT EXISTS "user_roles" (
 	UNIQUE ("user_id", "role_id")
 );
 
+CREATE TABLE IF NOT EXISTS "auth_settings" (
+	"name" TEXT PRIMARY KEY NOT NULL,
+	"value" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "api_keys" (
+	"id" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	"user_id" INTEGER NOT NULL REFERENCES "users" ("id"),
+	"name" TEXT NOT NULL,
+	"key_hash" TEXT NOT NULL UNIQUE,
+	"roles" TEXT,
+	"expires" TEXT,
+	"timestamp" TEXT NOT NULL,
+	UNIQUE ("user_id", "name")
+);
+
 CREATE TABLE IF NOT EXISTS "sessions" (
 	"id" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
-	"token" TEXT NOT NULL UNIQUE,
+	"token_hash" TEXT NOT NULL UNIQUE,
 	"user_id" INTEGER NOT NULL REFERENCES "users" ("id"),
-	"timestamp" TEXT NOT NULL
+	"timestamp" TEXT NOT NULL,
+	"last_seen" TEXT NOT NULL,
+	"second_factor" INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS "user_totp" (
+	"user_id" INTEGER PRIMARY KEY NOT NULL REFERENCES "users" ("id"),
+	"secret" TEXT NOT NULL,
+	"confirmed" INTEGER NOT NULL DEFAULT 0,
+	"last_step" INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS "recovery_codes" (
+	"id" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	"user_id" INTEGER NOT NULL REFERENCES "users" ("id"),
+	"code_hash" TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS "login_challenges" (
+	"id" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	"token_hash" TEXT NOT NULL UNIQUE,
+	"user_id" INTEGER NOT NULL REFERENCES "users" ("id"),
+	"timestamp" TEXT NOT NULL,
+	"attempts" INTEGER NOT NULL DEFAULT 0
 );
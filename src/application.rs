@@ -2,23 +2,34 @@
 
 use std::{
     collections::{HashMap, VecDeque},
-    future::Future,
-    pin::Pin,
+    future::{Future, IntoFuture},
+    pin::{pin, Pin},
+    time::Duration,
 };
 
+use axum::{middleware, Router};
+use tokio::{io, net::TcpListener, sync::watch};
+
 use crate::{
-    auth::Auth,
-    config::{Config, RoutePermissions},
+    auth::{self, Auth},
+    config::{Config, DatastoreConfig, RouteConfig, RoutePermissionValue, RoutePermissions},
     database::DbSchema,
-    datastore::DataStore,
+    datastore::{access::DataAccessRules, DataStore},
+    endpoints,
 };
 
 /// Represents the application
 pub struct Application {
     /// App data
     pub app_data: AppData,
+    /// Hashmap of endpoints by route path
+    pub endpoints: HashMap<String, ApplicationEndpoint>,
     /// Queue of functions to run at application shutdown
     shutdown_queue: VecDeque<ShutdownFunction>,
+    /// Changes to true when the server is shutting down
+    shutting_down: watch::Sender<bool>,
+    /// Time in-flight requests get to finish after the shutdown signal
+    shutdown_grace_period: Duration,
 }
 
 /// Contains the application data that is passed to each endpoint
@@ -28,12 +39,15 @@ pub struct AppData {
     pub database_schemas: HashMap<String, DbSchema>,
     /// Authentication system
     pub auth: Option<Auth>,
+    /// Hashmap of named datastores
+    pub datastores: HashMap<String, DataStore<serde_json::Value>>,
 }
 
 impl Application {
     /// Builds the application
     pub async fn build(config: &Config) -> Self {
         let mut shutdown_queue = VecDeque::new();
+        let (shutting_down, _) = watch::channel(false);
 
         // connect to databases
         let database_schemas = DbSchema::connect_all(&config.databases);
@@ -49,12 +63,198 @@ impl Application {
             )
         });
 
+        // regularly remove expired sessions until shutdown
+        if let Some(auth) = &auth {
+            let auth = auth.clone();
+            let purge_task = tokio::spawn(async move {
+                let mut interval = tokio::time::interval(auth.session_purge_interval());
+                loop {
+                    interval.tick().await;
+                    auth.purge_expired_sessions().await;
+                }
+            });
+            shutdown_queue.push_back(ShutdownFunction::Closure(Box::new(move || {
+                purge_task.abort()
+            })));
+        }
+
+        // start named datastores, shared by all routes that use them
+        let mut datastores = HashMap::new();
+        for (name, datastore_config) in &config.datastores {
+            let database = datastore_config.database_schema.as_ref().map(|schema| {
+                database_schemas
+                    .get(schema)
+                    .expect("Datastore database schema not found in config")
+                    .clone()
+            });
+            let datastore = DataStore::new(name, datastore_config.clone(), database).await;
+            shutdown_queue.push_back(datastore_shutdown(&datastore));
+            datastores.insert(name.clone(), datastore);
+        }
+
+        // set up route endpoints
+        let mut endpoints = HashMap::new();
+        for (path, route_config) in &config.routes {
+            let endpoint = match route_config {
+                RouteConfig::Redirect { redirect_target } => ApplicationEndpoint::Redirect {
+                    target: redirect_target.clone(),
+                },
+                RouteConfig::File {
+                    permissions,
+                    server_file_path,
+                    index_file,
+                } => ApplicationEndpoint::File {
+                    permissions: permissions.clone(),
+                    server_file_path: server_file_path.clone(),
+                    index_file: index_file.clone(),
+                },
+                RouteConfig::Data {
+                    permissions,
+                    datastore,
+                    rules,
+                    admin,
+                } => {
+                    let datastore = match datastore {
+                        Some(name) => datastores
+                            .get(name)
+                            .expect("Route datastore not found in config")
+                            .clone(),
+                        None => {
+                            // routes without a named datastore get their own in-memory datastore
+                            let datastore =
+                                DataStore::new(path, DatastoreConfig::default(), None).await;
+                            shutdown_queue.push_back(datastore_shutdown(&datastore));
+                            datastore
+                        }
+                    };
+                    ApplicationEndpoint::Data {
+                        permissions: permissions.clone(),
+                        datastore: Box::new(datastore),
+                        rules: DataAccessRules::new(rules),
+                        admin: admin.clone(),
+                    }
+                }
+                RouteConfig::Auth => ApplicationEndpoint::Auth {
+                    database_schema: auth_database_schema(config, &database_schemas),
+                },
+                RouteConfig::AuthAdmin {
+                    permissions,
+                    require_second_factor,
+                } => ApplicationEndpoint::AuthAdmin {
+                    permissions: permissions.clone(),
+                    require_second_factor: *require_second_factor,
+                    database_schema: auth_database_schema(config, &database_schemas),
+                },
+            };
+            endpoints.insert(path.clone(), endpoint);
+        }
+
         Self {
             app_data: AppData {
                 database_schemas,
                 auth,
+                datastores,
             },
+            endpoints,
             shutdown_queue,
+            shutting_down,
+            shutdown_grace_period: Duration::from_secs(config.server.shutdown_grace_period),
+        }
+    }
+
+    /// Serves the application routes until the shutdown signal completes.
+    /// New connections are no longer accepted after the signal, and in-flight requests get the grace period to finish.
+    /// Long-running requests are told the server is shutting down.
+    pub async fn serve(
+        &self,
+        listener: TcpListener,
+        signal: impl Future<Output = ()>,
+    ) -> io::Result<()> {
+        let mut shutting_down = self.shutting_down.subscribe();
+        let server = axum::serve(listener, self.router())
+            .with_graceful_shutdown(async move {
+                shutting_down.wait_for(|x| *x).await.ok();
+            })
+            .into_future();
+        let mut server = pin!(server);
+
+        tokio::select! {
+            result = &mut server => return result,
+            _ = signal => {}
+        }
+
+        self.shutting_down.send_replace(true);
+        // connections still open after the grace period are dropped
+        tokio::time::timeout(self.shutdown_grace_period, server)
+            .await
+            .unwrap_or(Ok(()))
+    }
+
+    /// Creates the router serving the endpoints at their route paths
+    pub fn router(&self) -> Router {
+        let mut router = Router::new();
+        for (path, endpoint) in &self.endpoints {
+            let mut endpoint_router = match endpoint {
+                ApplicationEndpoint::Data {
+                    permissions,
+                    datastore,
+                    rules,
+                    admin,
+                } => endpoints::data::route(
+                    *datastore.clone(),
+                    rules.clone(),
+                    permissions.read.clone(),
+                    admin.clone(),
+                    self.shutting_down.subscribe(),
+                ),
+                ApplicationEndpoint::Auth { .. } => endpoints::auth::route(
+                    self.app_data
+                        .auth
+                        .clone()
+                        .expect("Authentication config is required for auth routes"),
+                ),
+                ApplicationEndpoint::AuthAdmin {
+                    require_second_factor,
+                    ..
+                } => endpoints::auth_admin::route(
+                    self.app_data
+                        .auth
+                        .clone()
+                        .expect("Authentication config is required for auth routes"),
+                    *require_second_factor,
+                ),
+                ApplicationEndpoint::Redirect { target } => {
+                    endpoints::redirect::route(target.clone())
+                }
+                ApplicationEndpoint::File {
+                    server_file_path,
+                    index_file,
+                    ..
+                } => endpoints::file::route(server_file_path, index_file.clone()),
+            };
+
+            // check the route permissions of the identified user
+            if let Some(permissions) = endpoint.permissions() {
+                endpoint_router = endpoint_router.layer(middleware::from_fn_with_state(
+                    permissions.clone(),
+                    auth::authorize,
+                ));
+            }
+
+            // routers can't be nested at the root
+            router = match path.trim_end_matches('/') {
+                "" => router.merge(endpoint_router),
+                path => router.nest(path, endpoint_router),
+            };
+        }
+
+        // identify the user of each request by its session token
+        match &self.app_data.auth {
+            Some(auth) => router.layer(middleware::from_fn_with_state(
+                auth.clone(),
+                auth::authenticate,
+            )),
+            None => router,
         }
     }
 
@@ -82,20 +282,52 @@ pub enum ApplicationEndpoint {
     },
     Data {
         permissions: RoutePermissions,
-        key_value: DataStore<String>,
-        database_schema: DbSchema,
+        datastore: Box<DataStore<serde_json::Value>>,
+        rules: DataAccessRules,
+        admin: RoutePermissionValue,
     },
     Auth {
         database_schema: DbSchema,
     },
     AuthAdmin {
         permissions: RoutePermissions,
+        require_second_factor: bool,
         database_schema: DbSchema,
     },
 }
 
+impl ApplicationEndpoint {
+    /// Gets the read and write permissions of endpoints that have them
+    pub fn permissions(&self) -> Option<&RoutePermissions> {
+        match self {
+            ApplicationEndpoint::File { permissions, .. }
+            | ApplicationEndpoint::Data { permissions, .. }
+            | ApplicationEndpoint::AuthAdmin { permissions, .. } => Some(permissions),
+            ApplicationEndpoint::Redirect { .. } | ApplicationEndpoint::Auth { .. } => None,
+        }
+    }
+}
+
 /// Enum for application shutdown functions
 enum ShutdownFunction {
     Closure(Box<dyn FnOnce()>),
     Future(Pin<Box<dyn Future<Output = ()>>>),
 }
+
+/// Creates the shutdown function of a datastore
+fn datastore_shutdown(datastore: &DataStore<serde_json::Value>) -> ShutdownFunction {
+    let datastore = datastore.clone();
+    ShutdownFunction::Future(Box::pin(async move { datastore.shutdown().await }))
+}
+
+/// Gets the database schema of the authentication system for auth routes
+fn auth_database_schema(config: &Config, database_schemas: &HashMap<String, DbSchema>) -> DbSchema {
+    let auth_config = config
+        .authentication
+        .as_ref()
+        .expect("Authentication config is required for auth routes");
+    database_schemas
+        .get(&auth_config.database_schema)
+        .expect("Authentication database schema not found in config")
+        .clone()
+}
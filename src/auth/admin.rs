@@ -0,0 +1,281 @@
+//! User, role and session administration
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{hash_password, Auth};
+use crate::database::models::auth::AuthUser;
+
+/// Error of an administration operation
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AuthAdminError {
+    /// The user doesn't exist
+    UserNotFound,
+    /// The role doesn't exist
+    RoleNotFound,
+    /// The user has no session with the id
+    SessionNotFound,
+    /// The user has no API key with the name
+    ApiKeyNotFound,
+    /// The user doesn't have a role to pass on
+    RoleNotGranted,
+    /// The user has no second factor, or hasn't confirmed it
+    SecondFactorNotFound,
+    /// The second factor code is wrong or was already used
+    InvalidCode,
+    /// A user or role with the name already exists
+    AlreadyExists,
+}
+
+impl fmt::Display for AuthAdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthAdminError::UserNotFound => write!(f, "User not found"),
+            AuthAdminError::RoleNotFound => write!(f, "Role not found"),
+            AuthAdminError::SessionNotFound => write!(f, "Session not found"),
+            AuthAdminError::ApiKeyNotFound => write!(f, "API key not found"),
+            AuthAdminError::RoleNotGranted => write!(f, "Role not granted to user"),
+            AuthAdminError::SecondFactorNotFound => write!(f, "Second factor not found"),
+            AuthAdminError::InvalidCode => write!(f, "Invalid code"),
+            AuthAdminError::AlreadyExists => write!(f, "Already exists"),
+        }
+    }
+}
+
+impl std::error::Error for AuthAdminError {}
+
+/// User account as shown to administrators
+#[derive(Serialize, Debug)]
+pub struct UserInfo {
+    pub username: String,
+    /// Whether the user is allowed to log in
+    pub active: bool,
+    /// Whether the user has a password set
+    pub has_password: bool,
+    /// Names of the roles of the user
+    pub roles: Vec<String>,
+    /// Whether the user has a confirmed second factor
+    pub second_factor: bool,
+}
+
+/// Session as shown to administrators, without its token
+#[derive(Serialize, Debug)]
+pub struct SessionInfo {
+    pub id: i64,
+    /// Time the session was created
+    pub timestamp: DateTime<Utc>,
+    /// Time the session was last used, updated at most once a minute
+    pub last_seen: DateTime<Utc>,
+    /// Whether the user confirmed a second factor when logging in
+    pub second_factor: bool,
+}
+
+impl Auth {
+    /// Gets all users ordered by username
+    pub async fn list_users(&self) -> Vec<UserInfo> {
+        self.blocking(|auth| {
+            auth.db_schema
+                .auth_list_users()
+                .into_iter()
+                .map(|user| auth.user_info(user))
+                .collect()
+        })
+        .await
+    }
+
+    /// Gets a user
+    pub async fn get_user(&self, username: &str) -> Result<UserInfo, AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| Ok(auth.user_info(auth.find_user(&username)?)))
+            .await
+    }
+
+    /// Creates an active user with existing roles.
+    /// Users without a password can't log in until one is set.
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password: Option<&str>,
+        roles: &[String],
+    ) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        let password = password.map(String::from);
+        let roles = roles.to_vec();
+        self.blocking(move |auth| {
+            if auth.db_schema.auth_get_user(&username).is_some() {
+                return Err(AuthAdminError::AlreadyExists);
+            }
+            let existing_roles = auth.db_schema.auth_list_roles();
+            if !roles.iter().all(|role| existing_roles.contains(role)) {
+                return Err(AuthAdminError::RoleNotFound);
+            }
+
+            let password_hash = password.as_deref().map(hash_password);
+            let user_id = auth
+                .db_schema
+                .auth_create_user(&username, password_hash.as_deref());
+            for role in &roles {
+                auth.db_schema.auth_add_user_role(user_id, role);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Enables or disables a user.
+    /// Sessions of disabled users are kept but not accepted until the user is enabled again.
+    pub async fn set_user_active(
+        &self,
+        username: &str,
+        active: bool,
+    ) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            auth.db_schema.auth_set_user_active(user.id, active);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Sets or removes the password of a user, ending all of the user's sessions and revoking the user's API keys
+    pub async fn reset_password(
+        &self,
+        username: &str,
+        password: Option<&str>,
+    ) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        let password = password.map(String::from);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            let password_hash = password.as_deref().map(hash_password);
+            auth.db_schema
+                .auth_reset_password(user.id, password_hash.as_deref());
+            Ok(())
+        })
+        .await
+    }
+
+    /// Gets the names of all roles
+    pub async fn list_roles(&self) -> Vec<String> {
+        self.blocking(|auth| auth.db_schema.auth_list_roles()).await
+    }
+
+    /// Creates a role
+    pub async fn create_role(&self, name: &str) -> Result<(), AuthAdminError> {
+        let name = String::from(name);
+        self.blocking(move |auth| match auth.db_schema.auth_create_role(&name) {
+            true => Ok(()),
+            false => Err(AuthAdminError::AlreadyExists),
+        })
+        .await
+    }
+
+    /// Deletes a role and takes it from all users
+    pub async fn delete_role(&self, name: &str) -> Result<(), AuthAdminError> {
+        let name = String::from(name);
+        self.blocking(move |auth| match auth.db_schema.auth_delete_role(&name) {
+            true => Ok(()),
+            false => Err(AuthAdminError::RoleNotFound),
+        })
+        .await
+    }
+
+    /// Gives a user a role, doing nothing if the user already has it
+    pub async fn assign_role(&self, username: &str, role: &str) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        let role = String::from(role);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            if !auth.db_schema.auth_list_roles().contains(&role) {
+                return Err(AuthAdminError::RoleNotFound);
+            }
+            auth.db_schema.auth_add_user_role(user.id, &role);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Takes a role from a user, doing nothing if the user doesn't have it
+    pub async fn unassign_role(&self, username: &str, role: &str) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        let role = String::from(role);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            if !auth.db_schema.auth_list_roles().contains(&role) {
+                return Err(AuthAdminError::RoleNotFound);
+            }
+            auth.db_schema.auth_remove_user_role(user.id, &role);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Gets the sessions of a user
+    pub async fn list_sessions(&self, username: &str) -> Result<Vec<SessionInfo>, AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            Ok(auth
+                .db_schema
+                .auth_list_sessions(user.id)
+                .into_iter()
+                .map(|session| SessionInfo {
+                    id: session.id,
+                    timestamp: session.timestamp,
+                    last_seen: session.last_seen,
+                    second_factor: session.second_factor,
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// Ends a session of a user
+    pub async fn revoke_session(
+        &self,
+        username: &str,
+        session_id: i64,
+    ) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            match auth.db_schema.auth_delete_user_session(user.id, session_id) {
+                true => Ok(()),
+                false => Err(AuthAdminError::SessionNotFound),
+            }
+        })
+        .await
+    }
+
+    /// Ends all sessions of a user
+    pub async fn revoke_sessions(&self, username: &str) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            auth.db_schema.auth_delete_user_sessions(user.id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Gets a user by username
+    pub(super) fn find_user(&self, username: &str) -> Result<AuthUser, AuthAdminError> {
+        self.db_schema
+            .auth_get_user(username)
+            .ok_or(AuthAdminError::UserNotFound)
+    }
+
+    /// Adds the roles of a user to its account details
+    fn user_info(&self, user: AuthUser) -> UserInfo {
+        UserInfo {
+            roles: self.db_schema.auth_get_user_roles(user.id),
+            username: user.username,
+            active: user.active,
+            has_password: user.password_hash.is_some(),
+            second_factor: self.has_second_factor(user.id),
+        }
+    }
+}
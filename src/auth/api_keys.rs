@@ -0,0 +1,147 @@
+//! Long-lived API keys for clients that can't log in interactively
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{admin::AuthAdminError, generate_token, Auth, AuthIdentity};
+use crate::database::models::auth::AuthApiKey;
+
+/// Prefix of API keys, telling them apart from session tokens
+pub const API_KEY_PREFIX: &str = "ak_";
+
+/// API key as shown to its user and administrators, without the key itself
+#[derive(Serialize, Debug)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    /// Roles of the user the key grants, None for all of them
+    pub roles: Option<Vec<String>>,
+    /// Time the key stops working, None if it doesn't expire
+    pub expires: Option<DateTime<Utc>>,
+    /// Time the key was created
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Newly created API key, the only time the key itself is shown
+#[derive(Serialize, Debug)]
+pub struct NewApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+}
+
+impl From<AuthApiKey> for ApiKeyInfo {
+    fn from(api_key: AuthApiKey) -> Self {
+        Self {
+            name: api_key.name,
+            roles: api_key.roles,
+            expires: api_key.expires,
+            timestamp: api_key.timestamp,
+        }
+    }
+}
+
+/// Checks if a token sent with a request is an API key rather than a session token
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
+
+impl Auth {
+    /// Creates an API key for a user.
+    /// The key can only grant roles the user has, and loses roles taken from the user later.
+    /// Only the hash of the key is stored, so it can't be shown again.
+    pub async fn create_api_key(
+        &self,
+        username: &str,
+        name: &str,
+        roles: Option<&[String]>,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<NewApiKey, AuthAdminError> {
+        let username = String::from(username);
+        let name = String::from(name);
+        let roles = roles.map(|roles| roles.to_vec());
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            if let Some(roles) = &roles {
+                let user_roles = auth.db_schema.auth_get_user_roles(user.id);
+                if !roles.iter().all(|role| user_roles.contains(role)) {
+                    return Err(AuthAdminError::RoleNotGranted);
+                }
+            }
+
+            let key = format!("{}{}", API_KEY_PREFIX, generate_token());
+            if !auth.db_schema.auth_create_api_key(
+                user.id,
+                &name,
+                &auth.hash_token(&key),
+                roles.as_deref(),
+                expires,
+            ) {
+                return Err(AuthAdminError::AlreadyExists);
+            }
+
+            let api_key = auth
+                .db_schema
+                .auth_get_api_key(&auth.hash_token(&key))
+                .expect("Error occurred while reading created API key");
+            Ok(NewApiKey {
+                key,
+                info: api_key.into(),
+            })
+        })
+        .await
+    }
+
+    /// Gets the API keys of a user ordered by name
+    pub async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKeyInfo>, AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            Ok(auth
+                .db_schema
+                .auth_list_api_keys(user.id)
+                .into_iter()
+                .map(ApiKeyInfo::from)
+                .collect())
+        })
+        .await
+    }
+
+    /// Deletes an API key of a user
+    pub async fn revoke_api_key(&self, username: &str, name: &str) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        let name = String::from(name);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            match auth.db_schema.auth_delete_api_key(user.id, &name) {
+                true => Ok(()),
+                false => Err(AuthAdminError::ApiKeyNotFound),
+            }
+        })
+        .await
+    }
+
+    /// Gets the identity of the active user an API key belongs to, limited to the roles of the key.
+    /// API keys don't count as second factor, so they can't be used where one is required.
+    /// Returns None if the key doesn't exist or expired.
+    pub(super) fn identify_api_key(&self, key: &str) -> Option<AuthIdentity> {
+        let api_key = self.db_schema.auth_get_api_key(&self.hash_token(key))?;
+        if api_key.expires.is_some_and(|expires| expires <= Utc::now()) {
+            return None;
+        }
+
+        let user = self.db_schema.auth_get_user_by_id(api_key.user_id)?;
+        if !user.active {
+            return None;
+        }
+
+        let mut roles = self.db_schema.auth_get_user_roles(user.id);
+        if let Some(key_roles) = &api_key.roles {
+            roles.retain(|role| key_roles.contains(role));
+        }
+        Some(AuthIdentity {
+            username: Some(user.username),
+            roles,
+            second_factor: false,
+        })
+    }
+}
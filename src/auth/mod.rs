@@ -1,18 +1,371 @@
 //! Authentication module
 
-use crate::{config::AuthenticationConfig, database::DbSchema};
+pub mod admin;
+pub mod api_keys;
+pub mod totp;
+
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+
+use crate::{
+    config::{AuthenticationConfig, RoutePermissionValue, RoutePermissions},
+    database::DbSchema,
+    helpers::hmac::hmac_sha256,
+};
+use api_keys::is_api_key;
+
+/// Name of the cookie holding the session token
+pub const SESSION_COOKIE: &str = "session";
+
+/// Name of the header an API key can be sent in instead of the authorization header
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Number of random bytes in a session token
+const SESSION_TOKEN_BYTES: usize = 32;
+
+/// Name of the setting holding the generated session token key
+const SESSION_TOKEN_KEY_SETTING: &str = "session_token_key";
+
+/// Maximum time in seconds between updates of the time a session was last used.
+/// Shorter for idle timeouts under two minutes, so sessions don't expire while they are used.
+const SESSION_TOUCH_INTERVAL: i64 = 60;
+
+/// Hash of a random password, checked in place of the hash of users that don't exist or have no password
+static DUMMY_PASSWORD_HASH: OnceLock<String> = OnceLock::new();
+
+/// Identity of the user making a request
+#[derive(Clone, Debug, Default)]
+pub struct AuthIdentity {
+    /// Username, None if the request is not authenticated
+    pub username: Option<String>,
+    /// Roles of the user
+    pub roles: Vec<String>,
+    /// Whether the user confirmed a second factor when logging in
+    pub second_factor: bool,
+}
+
+impl AuthIdentity {
+    /// Checks if the identity is granted a route permission
+    pub fn has_permission(&self, permission: &RoutePermissionValue) -> bool {
+        match permission {
+            RoutePermissionValue::Global(allowed) => *allowed,
+            RoutePermissionValue::Roles(roles) => {
+                roles.iter().any(|role| self.roles.contains(role))
+            }
+        }
+    }
+}
+
+/// Result of a login with correct credentials
+#[derive(PartialEq, Debug)]
+pub enum LoginStep {
+    /// The user is logged in with the session token
+    Session(String),
+    /// The user has to confirm a second factor for the login challenge token
+    SecondFactor(String),
+}
 
 #[derive(Clone)]
 pub struct Auth {
     config: AuthenticationConfig,
     db_schema: DbSchema,
+    /// Key of the hashes session tokens are stored as
+    token_key: Vec<u8>,
 }
 
 impl Auth {
+    /// Sets up the authentication system, creating its tables and the default roles and users if needed
     pub fn new(config: &AuthenticationConfig, db_schema: DbSchema) -> Self {
-        Self {
+        db_schema.auth_create();
+
+        // the generated key is only used if none is configured
+        let token_key = match &config.session_token_key {
+            Some(key) => key.clone(),
+            None => {
+                db_schema.auth_get_or_create_setting(SESSION_TOKEN_KEY_SETTING, &generate_token())
+            }
+        };
+
+        let auth = Self {
             config: config.clone(),
             db_schema,
+            token_key: token_key.into_bytes(),
+        };
+        auth.db_schema
+            .auth_migrate_session_tokens(&|token| auth.hash_token(token));
+        auth.create_defaults();
+        // hashed now, so the first login of an unknown user doesn't take longer than the others
+        dummy_password_hash();
+        auth
+    }
+
+    /// Creates the default roles and users that don't exist yet.
+    /// Existing users are left unchanged, so passwords and roles changed since aren't overwritten.
+    fn create_defaults(&self) {
+        let defaults = &self.config.defaults;
+        for role in &defaults.roles {
+            self.db_schema.auth_create_role(role);
+        }
+
+        for (username, user_config) in &defaults.users {
+            if self.db_schema.auth_get_user(username).is_some() {
+                continue;
+            }
+
+            let password_hash = user_config.default_password.as_deref().map(hash_password);
+            let user_id = self
+                .db_schema
+                .auth_create_user(username, password_hash.as_deref());
+            for role in &user_config.roles {
+                self.db_schema.auth_create_role(role);
+                self.db_schema.auth_add_user_role(user_id, role);
+            }
         }
     }
+
+    /// Checks the password of an active user and starts a session, or the second login step if the user has a second factor.
+    /// Returns None if the credentials are wrong.
+    pub async fn login(&self, username: &str, password: &str) -> Option<LoginStep> {
+        let username = String::from(username);
+        let password = String::from(password);
+        self.blocking(move |auth| {
+            // a password is always checked, so the time taken doesn't reveal which users exist or are active
+            let user = auth.db_schema.auth_get_user(&username);
+            let password_hash = user.as_ref().and_then(|user| user.password_hash.as_deref());
+            let valid = verify_password(
+                &password,
+                password_hash.unwrap_or_else(|| dummy_password_hash()),
+            );
+            let user = user.filter(|user| valid && user.password_hash.is_some() && user.active)?;
+            if let Some(step) = auth.start_second_factor_login(user.id) {
+                return Some(step);
+            }
+
+            // only the client gets the token, the database only gets its hash
+            let token = generate_token();
+            auth.db_schema.auth_create_session(
+                &auth.hash_token(&token),
+                user.id,
+                Utc::now(),
+                false,
+            );
+            Some(LoginStep::Session(token))
+        })
+        .await
+    }
+
+    /// Ends the session of a token
+    pub async fn logout(&self, token: &str) {
+        let token = String::from(token);
+        self.blocking(move |auth| auth.db_schema.auth_delete_session(&auth.hash_token(&token)))
+            .await
+    }
+
+    /// Gets the identity of the active user a session token or API key belongs to and records the use of the session.
+    /// Returns None if the token doesn't belong to a session or API key, or they expired.
+    pub async fn identify(&self, token: &str) -> Option<AuthIdentity> {
+        let token = String::from(token);
+        self.blocking(move |auth| {
+            if is_api_key(&token) {
+                return auth.identify_api_key(&token);
+            }
+
+            let token_hash = auth.hash_token(&token);
+            let session = auth.db_schema.auth_get_session(&token_hash)?;
+            let now = Utc::now();
+            let (created_before, last_seen_before) = auth.session_expiry(now);
+            if session.timestamp < created_before || session.last_seen < last_seen_before {
+                auth.db_schema.auth_delete_session(&token_hash);
+                return None;
+            }
+            let touch_interval =
+                SESSION_TOUCH_INTERVAL.min(auth.config.session_idle_timeout as i64 / 2);
+            if now - session.last_seen >= Duration::seconds(touch_interval) {
+                auth.db_schema.auth_touch_session(session.id, now);
+            }
+
+            let user = auth.db_schema.auth_get_user_by_id(session.user_id)?;
+            if !user.active {
+                return None;
+            }
+
+            Some(AuthIdentity {
+                username: Some(user.username),
+                roles: auth.db_schema.auth_get_user_roles(user.id),
+                second_factor: session.second_factor,
+            })
+        })
+        .await
+    }
+
+    /// Deletes the expired sessions, API keys and login challenges.
+    /// Returns the number of deleted sessions.
+    pub async fn purge_expired_sessions(&self) -> usize {
+        self.blocking(|auth| {
+            let now = Utc::now();
+            auth.db_schema.auth_delete_expired_api_keys(now);
+            auth.purge_expired_login_challenges();
+            let (created_before, last_seen_before) = auth.session_expiry(now);
+            auth.db_schema
+                .auth_delete_expired_sessions(created_before, last_seen_before)
+        })
+        .await
+    }
+
+    /// Gets the times before which sessions must have been created or last used to be expired
+    fn session_expiry(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        (
+            now - Duration::seconds(self.config.session_lifetime as i64),
+            now - Duration::seconds(self.config.session_idle_timeout as i64),
+        )
+    }
+
+    /// Gets the time between removals of expired sessions
+    pub fn session_purge_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.session_purge_interval.max(1))
+    }
+
+    /// Hashes a session token with the token key, so tokens can't be taken from the database
+    fn hash_token(&self, token: &str) -> String {
+        hmac_sha256(&self.token_key, token.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Runs database queries and password hashing outside of the async runtime
+    async fn blocking<R: Send + 'static>(&self, f: impl FnOnce(&Auth) -> R + Send + 'static) -> R {
+        let auth = self.clone();
+        tokio::task::spawn_blocking(move || f(&auth))
+            .await
+            .expect("Error occurred while running authentication task")
+    }
+}
+
+/// Middleware that adds the identity of the session token sent with a request to the request extensions.
+/// Requests without a valid session token get an anonymous identity.
+pub async fn authenticate(State(auth): State<Auth>, mut request: Request, next: Next) -> Response {
+    let identity = match request_token(request.headers()) {
+        Some(token) => auth.identify(&token).await.unwrap_or_default(),
+        None => AuthIdentity::default(),
+    };
+    request.extensions_mut().insert(identity);
+
+    next.run(request).await
+}
+
+/// Middleware that rejects requests the identity added by `authenticate` isn't permitted to make.
+/// Requests with methods that don't change anything need the read permission, the others need the write permission.
+/// Requests without an identity are treated as anonymous.
+pub async fn authorize(
+    State(permissions): State<RoutePermissions>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let permission = match *request.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => &permissions.read,
+        _ => &permissions.write,
+    };
+
+    let identity = request.extensions().get::<AuthIdentity>();
+    let anonymous = AuthIdentity::default();
+    let identity = identity.unwrap_or(&anonymous);
+    if !identity.has_permission(permission) {
+        return deny(identity);
+    }
+
+    next.run(request).await
+}
+
+/// Middleware that rejects requests of users that didn't confirm a second factor when logging in.
+/// Requests without an identity are treated as anonymous.
+pub async fn require_second_factor(request: Request, next: Next) -> Response {
+    let identity = request.extensions().get::<AuthIdentity>();
+    match identity {
+        Some(identity) if identity.second_factor => next.run(request).await,
+        Some(AuthIdentity {
+            username: Some(_), ..
+        }) => (StatusCode::FORBIDDEN, "Second factor required").into_response(),
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Rejects a request the identity doesn't have access to.
+/// Anonymous requests need to log in first, other users are forbidden.
+pub fn deny(identity: &AuthIdentity) -> Response {
+    if identity.username.is_some() {
+        StatusCode::FORBIDDEN.into_response()
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+/// Gets the session token or API key from the authorization bearer header, the API key header or the session cookie
+pub fn request_token(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(String::from(token.trim()));
+    }
+
+    let api_key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if let Some(key) = api_key {
+        return Some(String::from(key.trim()));
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| String::from(token))
+}
+
+/// Hashes a password with argon2 and a random salt
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Error occurred while hashing password")
+        .to_string()
+}
+
+/// Gets the hash checked for users that don't exist or have no password, hashing a random password the first time
+fn dummy_password_hash() -> &'static str {
+    DUMMY_PASSWORD_HASH.get_or_init(|| hash_password(&generate_token()))
+}
+
+/// Checks a password against an argon2 hash
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Generates a random hex encoded session token
+fn generate_token() -> String {
+    let mut bytes = [0u8; SESSION_TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
@@ -0,0 +1,429 @@
+//! TOTP second factor (RFC 6238) with recovery codes
+
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::Serialize;
+
+use super::{admin::AuthAdminError, generate_token, Auth, LoginStep};
+use crate::{
+    database::models::auth::AuthTotp,
+    helpers::hmac::{constant_time_eq, hmac_sha1, hmac_sha256},
+};
+
+/// Number of random bytes in a TOTP secret, the size of a SHA-1 hash as recommended by RFC 4226
+const TOTP_SECRET_BYTES: usize = 20;
+
+/// Number of random bytes in the nonce stored with an encrypted TOTP secret
+const TOTP_SECRET_NONCE_BYTES: usize = 16;
+
+// secrets are encrypted with a single HMAC-SHA256 output as keystream
+const _: () = assert!(TOTP_SECRET_BYTES <= 32);
+
+/// Seconds a TOTP code is valid for
+const TOTP_PERIOD: i64 = 30;
+
+/// Number of digits in a TOTP code
+const TOTP_DIGITS: u32 = 6;
+
+/// Number of time steps before and after the current one whose codes are accepted, allowing for clock drift
+const TOTP_ALLOWED_DRIFT: i64 = 1;
+
+/// Number of recovery codes a user gets
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Number of random bytes in a recovery code
+const RECOVERY_CODE_BYTES: usize = 5;
+
+/// Seconds after the password check that the second factor has to be confirmed in
+const LOGIN_CHALLENGE_LIFETIME: i64 = 300;
+
+/// Number of wrong codes after which a login challenge is discarded, limiting guessing
+const LOGIN_CHALLENGE_ATTEMPTS: i64 = 5;
+
+/// Number of wrong codes in a row after which a user is locked out of code checks, across login challenges
+const TOTP_LOCKOUT_ATTEMPTS: i64 = 10;
+
+/// Seconds code checks are refused for after the last wrong code of a locked out user
+const TOTP_LOCKOUT_DURATION: i64 = 900;
+
+/// Started TOTP enrollment, to be added to an authenticator app
+#[derive(Serialize, Debug)]
+pub struct TotpEnrollment {
+    /// Base32 encoded secret, for entering by hand
+    pub secret: String,
+    /// otpauth URI of the secret, usually shown as QR code
+    pub uri: String,
+}
+
+impl Auth {
+    /// Starts the TOTP enrollment of a user, replacing an unconfirmed secret.
+    /// The secret isn't required at login until it is confirmed with a code.
+    pub async fn start_totp_enrollment(
+        &self,
+        username: &str,
+    ) -> Result<TotpEnrollment, AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            if auth
+                .db_schema
+                .auth_get_totp(user.id)
+                .is_some_and(|totp| totp.confirmed)
+            {
+                return Err(AuthAdminError::AlreadyExists);
+            }
+
+            let mut secret = [0u8; TOTP_SECRET_BYTES];
+            rand::thread_rng().fill_bytes(&mut secret);
+            auth.db_schema
+                .auth_set_totp(user.id, &auth.encrypt_totp_secret(&secret));
+
+            let secret = base32_encode(&secret);
+            let issuer = &auth.config.totp_issuer;
+            let uri = format!(
+                "otpauth://totp/{0}:{1}?secret={2}&issuer={0}&algorithm=SHA1&digits={3}&period={4}",
+                uri_encode(issuer),
+                uri_encode(&username),
+                secret,
+                TOTP_DIGITS,
+                TOTP_PERIOD
+            );
+            Ok(TotpEnrollment { secret, uri })
+        })
+        .await
+    }
+
+    /// Confirms the TOTP enrollment of a user with a code from the authenticator app.
+    /// Returns the recovery codes, which aren't shown again.
+    pub async fn confirm_totp_enrollment(
+        &self,
+        username: &str,
+        code: &str,
+    ) -> Result<Vec<String>, AuthAdminError> {
+        let username = String::from(username);
+        let code = String::from(code);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            let totp = auth
+                .db_schema
+                .auth_get_totp(user.id)
+                .ok_or(AuthAdminError::SecondFactorNotFound)?;
+            if totp.confirmed {
+                return Err(AuthAdminError::AlreadyExists);
+            }
+            if !auth.check_totp_code(&totp, &code) {
+                return Err(AuthAdminError::InvalidCode);
+            }
+
+            auth.db_schema.auth_confirm_totp(user.id);
+            Ok(auth.create_recovery_codes(user.id))
+        })
+        .await
+    }
+
+    /// Replaces the recovery codes of a user with a confirmed second factor, after checking a TOTP code.
+    /// Returns the new recovery codes.
+    pub async fn regenerate_recovery_codes(
+        &self,
+        username: &str,
+        code: &str,
+    ) -> Result<Vec<String>, AuthAdminError> {
+        let username = String::from(username);
+        let code = String::from(code);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            let totp = auth.confirmed_totp(user.id)?;
+            if !auth.check_totp_code(&totp, &code) {
+                return Err(AuthAdminError::InvalidCode);
+            }
+
+            Ok(auth.create_recovery_codes(user.id))
+        })
+        .await
+    }
+
+    /// Removes the second factor of a user, after checking a TOTP or recovery code
+    pub async fn disable_totp(&self, username: &str, code: &str) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        let code = String::from(code);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            let totp = auth.confirmed_totp(user.id)?;
+            if !auth.check_second_factor(&totp, &code) {
+                return Err(AuthAdminError::InvalidCode);
+            }
+
+            auth.db_schema.auth_delete_totp(user.id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Removes the second factor and recovery codes of a user that lost them, so the user can log in with the password only
+    pub async fn reset_second_factor(&self, username: &str) -> Result<(), AuthAdminError> {
+        let username = String::from(username);
+        self.blocking(move |auth| {
+            let user = auth.find_user(&username)?;
+            match auth.db_schema.auth_delete_totp(user.id) {
+                true => Ok(()),
+                false => Err(AuthAdminError::SecondFactorNotFound),
+            }
+        })
+        .await
+    }
+
+    /// Completes a login with the challenge from the password check and a TOTP or recovery code.
+    /// Returns the session token, or None if the challenge expired or the code is wrong.
+    pub async fn confirm_login(&self, challenge: &str, code: &str) -> Option<String> {
+        let challenge = String::from(challenge);
+        let code = String::from(code);
+        self.blocking(move |auth| {
+            let login_challenge = auth
+                .db_schema
+                .auth_get_login_challenge(&auth.hash_token(&challenge))?;
+            let created_before = Utc::now() - Duration::seconds(LOGIN_CHALLENGE_LIFETIME);
+            if login_challenge.timestamp < created_before
+                || login_challenge.attempts >= LOGIN_CHALLENGE_ATTEMPTS
+            {
+                auth.db_schema
+                    .auth_delete_login_challenge(login_challenge.id);
+                return None;
+            }
+
+            let user = auth
+                .db_schema
+                .auth_get_user_by_id(login_challenge.user_id)?;
+            let totp = auth.confirmed_totp(user.id).ok()?;
+            if !user.active || !auth.check_second_factor(&totp, &code) {
+                auth.db_schema.auth_fail_login_challenge(login_challenge.id);
+                return None;
+            }
+
+            auth.db_schema
+                .auth_delete_login_challenge(login_challenge.id);
+            let token = generate_token();
+            auth.db_schema
+                .auth_create_session(&auth.hash_token(&token), user.id, Utc::now(), true);
+            Some(token)
+        })
+        .await
+    }
+
+    /// Starts the second login step of a user with a confirmed second factor.
+    /// Returns None if the user can log in with the password only.
+    pub(super) fn start_second_factor_login(&self, user_id: i64) -> Option<LoginStep> {
+        self.confirmed_totp(user_id).ok()?;
+
+        let challenge = generate_token();
+        self.db_schema.auth_create_login_challenge(
+            &self.hash_token(&challenge),
+            user_id,
+            Utc::now(),
+        );
+        Some(LoginStep::SecondFactor(challenge))
+    }
+
+    /// Deletes the login challenges that weren't confirmed in time.
+    /// Returns the number of deleted challenges.
+    pub(super) fn purge_expired_login_challenges(&self) -> usize {
+        self.db_schema.auth_delete_expired_login_challenges(
+            Utc::now() - Duration::seconds(LOGIN_CHALLENGE_LIFETIME),
+        )
+    }
+
+    /// Checks if a user has confirmed a second factor
+    pub(super) fn has_second_factor(&self, user_id: i64) -> bool {
+        self.confirmed_totp(user_id).is_ok()
+    }
+
+    /// Gets the confirmed TOTP secret of a user
+    fn confirmed_totp(&self, user_id: i64) -> Result<AuthTotp, AuthAdminError> {
+        self.db_schema
+            .auth_get_totp(user_id)
+            .filter(|totp| totp.confirmed)
+            .ok_or(AuthAdminError::SecondFactorNotFound)
+    }
+
+    /// Checks a TOTP code or uses up a recovery code
+    fn check_second_factor(&self, totp: &AuthTotp, code: &str) -> bool {
+        self.limit_code_attempts(totp, || {
+            self.matches_totp_code(totp, code) || self.use_recovery_code(totp.user_id, code)
+        })
+    }
+
+    /// Checks a TOTP code
+    fn check_totp_code(&self, totp: &AuthTotp, code: &str) -> bool {
+        self.limit_code_attempts(totp, || self.matches_totp_code(totp, code))
+    }
+
+    /// Runs a code check unless the user is locked out, counting wrong codes per user rather than per login challenge.
+    /// After a lockout each further wrong code locks the user out again, until a correct code resets the count.
+    fn limit_code_attempts(&self, totp: &AuthTotp, check: impl FnOnce() -> bool) -> bool {
+        let locked_after = Utc::now() - Duration::seconds(TOTP_LOCKOUT_DURATION);
+        if totp.failed_attempts >= TOTP_LOCKOUT_ATTEMPTS
+            && totp
+                .last_failure
+                .is_some_and(|last_failure| last_failure > locked_after)
+        {
+            return false;
+        }
+
+        let valid = check();
+        if !valid {
+            self.db_schema.auth_fail_totp(totp.user_id, Utc::now());
+        } else if totp.failed_attempts > 0 {
+            self.db_schema.auth_reset_totp_failures(totp.user_id);
+        }
+        valid
+    }
+
+    /// Checks a TOTP code against the time steps around the current time.
+    /// Each time step is only accepted once, so an observed code can't be used again.
+    fn matches_totp_code(&self, totp: &AuthTotp, code: &str) -> bool {
+        let code = normalize_code(code);
+        let secret = self.decrypt_totp_secret(&totp.secret);
+        let current_step = Utc::now().timestamp() / TOTP_PERIOD;
+        (current_step - TOTP_ALLOWED_DRIFT..=current_step + TOTP_ALLOWED_DRIFT)
+            .filter(|step| totp.last_step.is_none_or(|last_step| *step > last_step))
+            .find(|step| constant_time_eq(totp_code(&secret, *step).as_bytes(), code.as_bytes()))
+            .is_some_and(|step| self.db_schema.auth_use_totp_step(totp.user_id, step))
+    }
+
+    /// Uses up a recovery code of a user.
+    /// The hashes are compared in constant time rather than looked up, so the timing doesn't reveal matching prefixes.
+    fn use_recovery_code(&self, user_id: i64, code: &str) -> bool {
+        let code_hash = self.hash_token(&normalize_code(code));
+        self.db_schema
+            .auth_get_recovery_codes(user_id)
+            .into_iter()
+            .find(|recovery_code| {
+                constant_time_eq(recovery_code.code_hash.as_bytes(), code_hash.as_bytes())
+            })
+            .is_some_and(|recovery_code| self.db_schema.auth_use_recovery_code(recovery_code.id))
+    }
+
+    /// Encrypts a TOTP secret for storage, so secrets can't be taken from the database without the token key.
+    /// Unlike recovery codes the secret can't be hashed, as codes are computed from it.
+    /// Changing the token key makes stored secrets unusable, like it ends sessions.
+    fn encrypt_totp_secret(&self, secret: &[u8]) -> String {
+        let mut nonce = [0u8; TOTP_SECRET_NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let encrypted: Vec<u8> = secret
+            .iter()
+            .zip(self.totp_secret_keystream(&nonce))
+            .map(|(byte, key)| byte ^ key)
+            .collect();
+        hex_encode(&[&nonce[..], &encrypted].concat())
+    }
+
+    /// Decrypts a TOTP secret encrypted by `encrypt_totp_secret`
+    fn decrypt_totp_secret(&self, encrypted: &str) -> Vec<u8> {
+        let bytes = hex_decode(encrypted);
+        let (nonce, secret) = bytes.split_at(TOTP_SECRET_NONCE_BYTES);
+        secret
+            .iter()
+            .zip(self.totp_secret_keystream(nonce))
+            .map(|(byte, key)| byte ^ key)
+            .collect()
+    }
+
+    /// Derives the keystream a TOTP secret is encrypted with from the token key and the nonce stored with the secret
+    fn totp_secret_keystream(&self, nonce: &[u8]) -> [u8; 32] {
+        hmac_sha256(&self.token_key, &[b"totp-secret:", nonce].concat())
+    }
+
+    /// Replaces the recovery codes of a user with new ones, of which only the hashes are stored.
+    /// Returns the new recovery codes.
+    fn create_recovery_codes(&self, user_id: i64) -> Vec<String> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+                rand::thread_rng().fill_bytes(&mut bytes);
+                let code = hex_encode(&bytes);
+                format!("{}-{}", &code[..5], &code[5..])
+            })
+            .collect();
+        let code_hashes: Vec<String> = codes
+            .iter()
+            .map(|code| self.hash_token(&normalize_code(code)))
+            .collect();
+        self.db_schema
+            .auth_set_recovery_codes(user_id, &code_hashes);
+        codes
+    }
+}
+
+/// Computes the TOTP code of a secret for a time step (RFC 4226 dynamic truncation)
+pub fn totp_code(secret: &[u8], step: i64) -> String {
+    let hash = hmac_sha1(secret, &step.to_be_bytes());
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(TOTP_DIGITS),
+        width = TOTP_DIGITS as usize
+    )
+}
+
+/// Removes the spaces and dashes users type codes with
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|char| !char.is_whitespace() && *char != '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Encodes bytes as lowercase hex
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes hex encoded bytes
+fn hex_decode(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .expect("Error occurred while decoding TOTP secret")
+        })
+        .collect()
+}
+
+/// Encodes bytes as unpadded base32 (RFC 4648), the format authenticator apps expect secrets in
+fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Percent-encodes a URI component
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                String::from(byte as char)
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -6,6 +6,8 @@ use std::{env, io::ErrorKind};
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 
+use crate::datastore::patterns::PathPattern;
+
 /// Object containing the application config
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
@@ -78,6 +80,11 @@ pub struct ServerConfig {
 
     /// Server port
     pub port: u16,
+
+    /// Time in seconds that in-flight requests get to finish after a shutdown signal.
+    /// If not set, the default (30) is used.
+    #[serde(default = "default_shutdown_grace_period")]
+    pub shutdown_grace_period: u64,
 }
 
 /// Database configuration
@@ -112,7 +119,7 @@ pub struct DatabaseSchemaConfig {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DatastoreConfig {
     /// Database schema for persisting data.
-    /// If not set, data is not persisted to a database and will be lost at application shutdown.
+    /// If not set, data is only kept in memory and will be lost at application shutdown.
     pub database_schema: Option<String>,
 
     /// Whether to keep history.
@@ -127,6 +134,110 @@ pub struct DatastoreConfig {
     /// Maximum number of previous values to keep for each key.
     /// Set to 0 to not keep history entries.
     pub history_max_entries: Option<u64>,
+
+    /// Hashmap of indexed value fields.
+    /// Indexes speed up queries that filter or sort by the field.
+    #[serde(default)]
+    pub indexes: HashMap<String, DatastoreIndexConfig>,
+
+    /// Whether to keep a full-text search index of the text in the current values.
+    /// If not set, the default (false) is used.
+    #[serde(default)]
+    pub full_text_search: bool,
+
+    /// List of triggers updating derived keys after values are set or deleted
+    #[serde(default)]
+    pub triggers: Vec<DatastoreTriggerConfig>,
+
+    /// Maximum number of requests waiting for the data store.
+    /// Requests are rejected as overloaded while the queue is full.
+    /// If not set, the default (1000) is used.
+    #[serde(default = "default_datastore_queue_depth")]
+    pub queue_depth: usize,
+
+    /// Time in milliseconds to wait for the data store to handle a request.
+    /// If not set, the default (30000) is used.
+    #[serde(default = "default_datastore_request_timeout")]
+    pub request_timeout: u64,
+
+    /// Maximum number of queued set and delete requests committed to the database in a single transaction.
+    /// If not set, the default (100) is used.
+    #[serde(default = "default_datastore_write_batch_max_size")]
+    pub write_batch_max_size: usize,
+
+    /// Time in milliseconds to wait for more set and delete requests before committing a write batch.
+    /// Requests already queued are always added to the batch.
+    /// If not set, the default (0) is used.
+    #[serde(default)]
+    pub write_batch_max_latency: u64,
+
+    /// Maximum visibility timeout in seconds of queue claims.
+    /// If not set, the default (43200, 12 hours) is used.
+    #[serde(default = "default_datastore_max_claim_visibility_timeout")]
+    pub max_claim_visibility_timeout: u64,
+}
+
+impl Default for DatastoreConfig {
+    /// Configuration of an in-memory data store without history
+    fn default() -> Self {
+        Self {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: default_datastore_queue_depth(),
+            request_timeout: default_datastore_request_timeout(),
+            write_batch_max_size: default_datastore_write_batch_max_size(),
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: default_datastore_max_claim_visibility_timeout(),
+        }
+    }
+}
+
+/// Data store index configuration
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DatastoreIndexConfig {
+    /// Path pattern of the keys whose values are indexed, e.g. "users/*".
+    /// Queries use the index for the children of the keys matched by the pattern without its last "*" or "{name}" segment.
+    pub path: PathPattern,
+
+    /// Path of the indexed field inside the values
+    pub field: Vec<String>,
+}
+
+/// Data store trigger configuration
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DatastoreTriggerConfig {
+    /// Path pattern of the keys whose changes run the trigger, e.g. "posts/{post}/comments/*"
+    pub path: PathPattern,
+
+    /// Path of the derived key, e.g. "posts/{post}/comment_count".
+    /// "{name}" placeholders are replaced by the keys captured by the path pattern.
+    pub target: String,
+
+    /// How the derived value is updated
+    #[serde(flatten)]
+    pub action: DatastoreTriggerAction,
+}
+
+/// Data store trigger action
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DatastoreTriggerAction {
+    /// Counts the matching keys that have a value
+    Count,
+
+    /// Sums a numeric field of the values of the matching keys
+    Sum { field: Vec<String> },
+
+    /// Copies a field of the last set value, e.g. the user that modified it
+    Copy { field: Vec<String> },
+
+    /// Stores the path, timestamp and change id of the last change
+    LastChange,
 }
 
 /// Authentication configuration
@@ -137,6 +248,31 @@ pub struct AuthenticationConfig {
 
     /// Authentication default setup
     pub defaults: AuthenticationDefaultsConfig,
+
+    /// Time in seconds after login that a session expires, no matter how it is used.
+    /// If not set, the default (2592000, 30 days) is used.
+    #[serde(default = "default_session_lifetime")]
+    pub session_lifetime: u64,
+
+    /// Time in seconds without requests after which a session expires.
+    /// If not set, the default (86400, 1 day) is used.
+    #[serde(default = "default_session_idle_timeout")]
+    pub session_idle_timeout: u64,
+
+    /// Secret key of the hashes session tokens are stored as.
+    /// Keep it outside of the database, so tokens can't be taken from database backups.
+    /// If not set, a key is generated and stored in the database.
+    pub session_token_key: Option<String>,
+
+    /// Time in seconds between removals of expired sessions from the database.
+    /// If not set, the default (3600) is used.
+    #[serde(default = "default_session_purge_interval")]
+    pub session_purge_interval: u64,
+
+    /// Issuer shown with TOTP secrets in authenticator apps.
+    /// If not set, the default ("GarnetDG") is used.
+    #[serde(default = "default_totp_issuer")]
+    pub totp_issuer: String,
 }
 
 /// Authentication defaults configuration
@@ -177,14 +313,28 @@ pub enum RouteConfig {
     /// Datastore
     Data {
         permissions: RoutePermissions,
+        /// Name of the configured datastore to serve.
+        /// If not set, the route gets its own in-memory datastore.
         datastore: Option<String>,
+        /// Access rules for paths inside the datastore, checked in order
+        #[serde(default)]
+        rules: Vec<DataAccessRuleConfig>,
+        /// Permission to export and import the datastore
+        #[serde(default = "default_admin_permission")]
+        admin: RoutePermissionValue,
     },
 
     /// Authentication endpoints
     Auth,
 
     /// Authentication management endpoints
-    AuthAdmin { permissions: RoutePermissions },
+    AuthAdmin {
+        permissions: RoutePermissions,
+        /// Whether administrators must have confirmed a second factor when logging in.
+        /// If not set, the default (true) is used.
+        #[serde(default = "default_require_second_factor")]
+        require_second_factor: bool,
+    },
 }
 
 /// Route permissions configuration
@@ -208,11 +358,45 @@ pub enum RoutePermissionValue {
     Roles(Vec<String>),
 }
 
+/// Datastore path access rule configuration
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DataAccessRuleConfig {
+    /// Path pattern of the keys the rule applies to, e.g. "users/{uid}/**"
+    pub path: PathPattern,
+
+    /// Read condition.
+    /// If not set, the read access is decided by the next matching rule.
+    pub read: Option<DataAccessCondition>,
+
+    /// Write condition.
+    /// If not set, the write access is decided by the next matching rule.
+    pub write: Option<DataAccessCondition>,
+}
+
+/// Datastore path access condition.
+/// Role and user names can contain "{name}" placeholders replaced by the keys captured by the rule path.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum DataAccessCondition {
+    /// Completely allow or deny access
+    Global(bool),
+
+    /// Only allow the listed roles, e.g. "member_{id}"
+    Roles(Vec<String>),
+
+    /// Only allow the user, e.g. "{uid}"
+    User { user: String },
+
+    /// Allow if any of the conditions allow
+    Any { any: Vec<DataAccessCondition> },
+}
+
 /// Creates the default server configuration
 fn default_server() -> ServerConfig {
     ServerConfig {
         host: String::from("0.0.0.0"),
         port: 8080,
+        shutdown_grace_period: default_shutdown_grace_period(),
     }
 }
 
@@ -240,6 +424,61 @@ fn default_routes() -> HashMap<String, RouteConfig> {
     )])
 }
 
+/// Default time of 30 seconds in-flight requests get to finish after a shutdown signal
+fn default_shutdown_grace_period() -> u64 {
+    30
+}
+
+/// Default maximum number of requests waiting for a data store thread
+fn default_datastore_queue_depth() -> usize {
+    1000
+}
+
+/// Default time in milliseconds a data store request waits for a response
+fn default_datastore_request_timeout() -> u64 {
+    30000
+}
+
+/// Default maximum number of writes committed to the database at once
+fn default_datastore_write_batch_max_size() -> usize {
+    100
+}
+
+/// Default maximum claim visibility timeout of 12 hours
+fn default_datastore_max_claim_visibility_timeout() -> u64 {
+    12 * 3600
+}
+
+/// Default session lifetime of 30 days
+fn default_session_lifetime() -> u64 {
+    30 * 24 * 3600
+}
+
+/// Default session idle timeout of 1 day
+fn default_session_idle_timeout() -> u64 {
+    24 * 3600
+}
+
+/// Default interval between removals of expired sessions
+fn default_session_purge_interval() -> u64 {
+    3600
+}
+
+/// Default issuer shown in authenticator apps
+fn default_totp_issuer() -> String {
+    String::from("GarnetDG")
+}
+
+/// Requires a second factor for administration by default
+fn default_require_second_factor() -> bool {
+    true
+}
+
+/// Denies administration permission by default
+fn default_admin_permission() -> RoutePermissionValue {
+    RoutePermissionValue::Global(false)
+}
+
 /// Defines read-only route permission for default routes
 fn default_readonly_permissions() -> RoutePermissions {
     RoutePermissions {
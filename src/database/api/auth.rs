@@ -0,0 +1,845 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    config::DatabaseSchemaConfig,
+    database::{
+        drivers::DbConnection,
+        models::auth::{
+            AuthApiKey, AuthLoginChallenge, AuthRecoveryCode, AuthSession, AuthTotp, AuthUser,
+        },
+        DbSchema,
+    },
+};
+
+pub struct AuthDatabaseConfig {
+    pub namespace: Option<String>,
+}
+
+impl AuthDatabaseConfig {
+    pub fn new(schema_config: &DatabaseSchemaConfig) -> Self {
+        Self {
+            namespace: schema_config.table_prefix.clone(),
+        }
+    }
+}
+
+impl DbSchema {
+    /// Creates the authentication tables
+    pub fn auth_create(&self) {
+        self.connection
+            .auth_create(&AuthDatabaseConfig::new(&self.config))
+    }
+
+    /// Replaces the raw session tokens of tables created before tokens were hashed with their hashes.
+    /// Returns the number of migrated sessions.
+    pub fn auth_migrate_session_tokens(&self, hash_token: &dyn Fn(&str) -> String) -> usize {
+        self.connection
+            .auth_migrate_session_tokens(&AuthDatabaseConfig::new(&self.config), hash_token)
+    }
+
+    /// Gets the value of a setting, storing the value first if the setting doesn't exist
+    pub fn auth_get_or_create_setting(&self, name: &str, value: &str) -> String {
+        self.connection.auth_get_or_create_setting(
+            &AuthDatabaseConfig::new(&self.config),
+            name,
+            value,
+        )
+    }
+
+    /// Gets a user by username
+    pub fn auth_get_user(&self, username: &str) -> Option<AuthUser> {
+        self.connection
+            .auth_get_user(&AuthDatabaseConfig::new(&self.config), username)
+    }
+
+    /// Gets a user by id
+    pub fn auth_get_user_by_id(&self, user_id: i64) -> Option<AuthUser> {
+        self.connection
+            .auth_get_user_by_id(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Creates a user, returning its id
+    pub fn auth_create_user(&self, username: &str, password_hash: Option<&str>) -> i64 {
+        self.connection.auth_create_user(
+            &AuthDatabaseConfig::new(&self.config),
+            username,
+            password_hash,
+        )
+    }
+
+    /// Gets the names of the roles of a user
+    pub fn auth_get_user_roles(&self, user_id: i64) -> Vec<String> {
+        self.connection
+            .auth_get_user_roles(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Creates a role if it doesn't exist.
+    /// Returns whether the role was created.
+    pub fn auth_create_role(&self, name: &str) -> bool {
+        self.connection
+            .auth_create_role(&AuthDatabaseConfig::new(&self.config), name)
+    }
+
+    /// Gives a user an existing role.
+    /// Returns whether the user didn't have the role before.
+    pub fn auth_add_user_role(&self, user_id: i64, role: &str) -> bool {
+        self.connection
+            .auth_add_user_role(&AuthDatabaseConfig::new(&self.config), user_id, role)
+    }
+
+    /// Creates a session for a user
+    pub fn auth_create_session(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+        second_factor: bool,
+    ) {
+        self.connection.auth_create_session(
+            &AuthDatabaseConfig::new(&self.config),
+            token_hash,
+            user_id,
+            timestamp,
+            second_factor,
+        )
+    }
+
+    /// Gets a session by token hash
+    pub fn auth_get_session(&self, token_hash: &str) -> Option<AuthSession> {
+        self.connection
+            .auth_get_session(&AuthDatabaseConfig::new(&self.config), token_hash)
+    }
+
+    /// Deletes a session by token hash
+    pub fn auth_delete_session(&self, token_hash: &str) {
+        self.connection
+            .auth_delete_session(&AuthDatabaseConfig::new(&self.config), token_hash)
+    }
+
+    /// Gets all users ordered by username
+    pub fn auth_list_users(&self) -> Vec<AuthUser> {
+        self.connection
+            .auth_list_users(&AuthDatabaseConfig::new(&self.config))
+    }
+
+    /// Sets whether a user is allowed to log in
+    pub fn auth_set_user_active(&self, user_id: i64, active: bool) {
+        self.connection.auth_set_user_active(
+            &AuthDatabaseConfig::new(&self.config),
+            user_id,
+            active,
+        )
+    }
+
+    /// Sets the password hash of a user and deletes all of the user's sessions and API keys
+    pub fn auth_reset_password(&self, user_id: i64, password_hash: Option<&str>) {
+        self.connection.auth_reset_password(
+            &AuthDatabaseConfig::new(&self.config),
+            user_id,
+            password_hash,
+        )
+    }
+
+    /// Gets the names of all roles
+    pub fn auth_list_roles(&self) -> Vec<String> {
+        self.connection
+            .auth_list_roles(&AuthDatabaseConfig::new(&self.config))
+    }
+
+    /// Deletes a role and removes it from all users.
+    /// Returns whether the role existed.
+    pub fn auth_delete_role(&self, name: &str) -> bool {
+        self.connection
+            .auth_delete_role(&AuthDatabaseConfig::new(&self.config), name)
+    }
+
+    /// Takes a role from a user.
+    /// Returns whether the user had the role.
+    pub fn auth_remove_user_role(&self, user_id: i64, role: &str) -> bool {
+        self.connection
+            .auth_remove_user_role(&AuthDatabaseConfig::new(&self.config), user_id, role)
+    }
+
+    /// Gets the sessions of a user
+    pub fn auth_list_sessions(&self, user_id: i64) -> Vec<AuthSession> {
+        self.connection
+            .auth_list_sessions(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Deletes a session of a user by id.
+    /// Returns whether the session existed.
+    pub fn auth_delete_user_session(&self, user_id: i64, session_id: i64) -> bool {
+        self.connection.auth_delete_user_session(
+            &AuthDatabaseConfig::new(&self.config),
+            user_id,
+            session_id,
+        )
+    }
+
+    /// Deletes all sessions of a user
+    pub fn auth_delete_user_sessions(&self, user_id: i64) {
+        self.connection
+            .auth_delete_user_sessions(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Sets the time a session was last used
+    pub fn auth_touch_session(&self, session_id: i64, last_seen: DateTime<Utc>) {
+        self.connection.auth_touch_session(
+            &AuthDatabaseConfig::new(&self.config),
+            session_id,
+            last_seen,
+        )
+    }
+
+    /// Deletes the sessions created or last used before the times.
+    /// Returns the number of deleted sessions.
+    pub fn auth_delete_expired_sessions(
+        &self,
+        created_before: DateTime<Utc>,
+        last_seen_before: DateTime<Utc>,
+    ) -> usize {
+        self.connection.auth_delete_expired_sessions(
+            &AuthDatabaseConfig::new(&self.config),
+            created_before,
+            last_seen_before,
+        )
+    }
+
+    /// Creates an API key for a user.
+    /// Returns false if the user already has a key with the name.
+    pub fn auth_create_api_key(
+        &self,
+        user_id: i64,
+        name: &str,
+        key_hash: &str,
+        roles: Option<&[String]>,
+        expires: Option<DateTime<Utc>>,
+    ) -> bool {
+        self.connection.auth_create_api_key(
+            &AuthDatabaseConfig::new(&self.config),
+            user_id,
+            name,
+            key_hash,
+            roles,
+            expires,
+        )
+    }
+
+    /// Gets an API key by key hash
+    pub fn auth_get_api_key(&self, key_hash: &str) -> Option<AuthApiKey> {
+        self.connection
+            .auth_get_api_key(&AuthDatabaseConfig::new(&self.config), key_hash)
+    }
+
+    /// Gets the API keys of a user ordered by name
+    pub fn auth_list_api_keys(&self, user_id: i64) -> Vec<AuthApiKey> {
+        self.connection
+            .auth_list_api_keys(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Deletes an API key of a user by name.
+    /// Returns whether the key existed.
+    pub fn auth_delete_api_key(&self, user_id: i64, name: &str) -> bool {
+        self.connection
+            .auth_delete_api_key(&AuthDatabaseConfig::new(&self.config), user_id, name)
+    }
+
+    /// Deletes the API keys that expired before the time.
+    /// Returns the number of deleted keys.
+    pub fn auth_delete_expired_api_keys(&self, expired_before: DateTime<Utc>) -> usize {
+        self.connection
+            .auth_delete_expired_api_keys(&AuthDatabaseConfig::new(&self.config), expired_before)
+    }
+
+    /// Gets the TOTP second factor of a user
+    pub fn auth_get_totp(&self, user_id: i64) -> Option<AuthTotp> {
+        self.connection
+            .auth_get_totp(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Sets an unconfirmed TOTP secret for a user, replacing an existing second factor
+    pub fn auth_set_totp(&self, user_id: i64, secret: &str) {
+        self.connection
+            .auth_set_totp(&AuthDatabaseConfig::new(&self.config), user_id, secret)
+    }
+
+    /// Confirms the TOTP secret of a user
+    pub fn auth_confirm_totp(&self, user_id: i64) {
+        self.connection
+            .auth_confirm_totp(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Records the use of a TOTP time step by a user.
+    /// Returns false if the step or a later one was used before, so codes can't be replayed.
+    pub fn auth_use_totp_step(&self, user_id: i64, step: i64) -> bool {
+        self.connection
+            .auth_use_totp_step(&AuthDatabaseConfig::new(&self.config), user_id, step)
+    }
+
+    /// Counts a wrong code sent by a user
+    pub fn auth_fail_totp(&self, user_id: i64, timestamp: DateTime<Utc>) {
+        self.connection
+            .auth_fail_totp(&AuthDatabaseConfig::new(&self.config), user_id, timestamp)
+    }
+
+    /// Resets the count of wrong codes sent by a user
+    pub fn auth_reset_totp_failures(&self, user_id: i64) {
+        self.connection
+            .auth_reset_totp_failures(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Deletes the TOTP second factor and recovery codes of a user.
+    /// Returns whether the user had a second factor.
+    pub fn auth_delete_totp(&self, user_id: i64) -> bool {
+        self.connection
+            .auth_delete_totp(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Replaces the recovery codes of a user
+    pub fn auth_set_recovery_codes(&self, user_id: i64, code_hashes: &[String]) {
+        self.connection.auth_set_recovery_codes(
+            &AuthDatabaseConfig::new(&self.config),
+            user_id,
+            code_hashes,
+        )
+    }
+
+    /// Gets the unused recovery codes of a user
+    pub fn auth_get_recovery_codes(&self, user_id: i64) -> Vec<AuthRecoveryCode> {
+        self.connection
+            .auth_get_recovery_codes(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Deletes a recovery code.
+    /// Returns false if the code was used in the meantime.
+    pub fn auth_use_recovery_code(&self, code_id: i64) -> bool {
+        self.connection
+            .auth_use_recovery_code(&AuthDatabaseConfig::new(&self.config), code_id)
+    }
+
+    /// Gets the number of unused recovery codes of a user
+    pub fn auth_count_recovery_codes(&self, user_id: i64) -> usize {
+        self.connection
+            .auth_count_recovery_codes(&AuthDatabaseConfig::new(&self.config), user_id)
+    }
+
+    /// Creates a login challenge for a user that still has to confirm a second factor
+    pub fn auth_create_login_challenge(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+    ) {
+        self.connection.auth_create_login_challenge(
+            &AuthDatabaseConfig::new(&self.config),
+            token_hash,
+            user_id,
+            timestamp,
+        )
+    }
+
+    /// Gets a login challenge by token hash
+    pub fn auth_get_login_challenge(&self, token_hash: &str) -> Option<AuthLoginChallenge> {
+        self.connection
+            .auth_get_login_challenge(&AuthDatabaseConfig::new(&self.config), token_hash)
+    }
+
+    /// Counts a failed attempt to confirm a login challenge
+    pub fn auth_fail_login_challenge(&self, challenge_id: i64) {
+        self.connection
+            .auth_fail_login_challenge(&AuthDatabaseConfig::new(&self.config), challenge_id)
+    }
+
+    /// Deletes a login challenge
+    pub fn auth_delete_login_challenge(&self, challenge_id: i64) {
+        self.connection
+            .auth_delete_login_challenge(&AuthDatabaseConfig::new(&self.config), challenge_id)
+    }
+
+    /// Deletes the login challenges created before the time.
+    /// Returns the number of deleted challenges.
+    pub fn auth_delete_expired_login_challenges(&self, created_before: DateTime<Utc>) -> usize {
+        self.connection.auth_delete_expired_login_challenges(
+            &AuthDatabaseConfig::new(&self.config),
+            created_before,
+        )
+    }
+}
+
+impl DbConnection {
+    /// Creates the authentication tables
+    pub fn auth_create(&self, config: &AuthDatabaseConfig) {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_create(config),
+        }
+    }
+
+    /// Replaces the raw session tokens of tables created before tokens were hashed with their hashes
+    pub fn auth_migrate_session_tokens(
+        &self,
+        config: &AuthDatabaseConfig,
+        hash_token: &dyn Fn(&str) -> String,
+    ) -> usize {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_migrate_session_tokens(config, hash_token)
+            }
+        }
+    }
+
+    /// Gets the value of a setting, storing the value first if the setting doesn't exist
+    pub fn auth_get_or_create_setting(
+        &self,
+        config: &AuthDatabaseConfig,
+        name: &str,
+        value: &str,
+    ) -> String {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_get_or_create_setting(config, name, value)
+            }
+        }
+    }
+
+    /// Gets a user by username
+    pub fn auth_get_user(&self, config: &AuthDatabaseConfig, username: &str) -> Option<AuthUser> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_get_user(config, username),
+        }
+    }
+
+    /// Gets a user by id
+    pub fn auth_get_user_by_id(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+    ) -> Option<AuthUser> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_get_user_by_id(config, user_id),
+        }
+    }
+
+    /// Creates a user, returning its id
+    pub fn auth_create_user(
+        &self,
+        config: &AuthDatabaseConfig,
+        username: &str,
+        password_hash: Option<&str>,
+    ) -> i64 {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_create_user(config, username, password_hash)
+            }
+        }
+    }
+
+    /// Gets the names of the roles of a user
+    pub fn auth_get_user_roles(&self, config: &AuthDatabaseConfig, user_id: i64) -> Vec<String> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_get_user_roles(config, user_id),
+        }
+    }
+
+    /// Creates a role if it doesn't exist
+    pub fn auth_create_role(&self, config: &AuthDatabaseConfig, name: &str) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_create_role(config, name),
+        }
+    }
+
+    /// Gives a user an existing role
+    pub fn auth_add_user_role(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        role: &str,
+    ) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_add_user_role(config, user_id, role)
+            }
+        }
+    }
+
+    /// Creates a session for a user
+    pub fn auth_create_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+        second_factor: bool,
+    ) {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_create_session(
+                config,
+                token_hash,
+                user_id,
+                timestamp,
+                second_factor,
+            ),
+        }
+    }
+
+    /// Gets a session by token hash
+    pub fn auth_get_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+    ) -> Option<AuthSession> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_get_session(config, token_hash),
+        }
+    }
+
+    /// Deletes a session by token hash
+    pub fn auth_delete_session(&self, config: &AuthDatabaseConfig, token_hash: &str) {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_delete_session(config, token_hash),
+        }
+    }
+
+    /// Gets all users ordered by username
+    pub fn auth_list_users(&self, config: &AuthDatabaseConfig) -> Vec<AuthUser> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_list_users(config),
+        }
+    }
+
+    /// Sets whether a user is allowed to log in
+    pub fn auth_set_user_active(&self, config: &AuthDatabaseConfig, user_id: i64, active: bool) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_set_user_active(config, user_id, active)
+            }
+        }
+    }
+
+    /// Sets the password hash of a user and deletes all of the user's sessions and API keys
+    pub fn auth_reset_password(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        password_hash: Option<&str>,
+    ) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_reset_password(config, user_id, password_hash)
+            }
+        }
+    }
+
+    /// Gets the names of all roles
+    pub fn auth_list_roles(&self, config: &AuthDatabaseConfig) -> Vec<String> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_list_roles(config),
+        }
+    }
+
+    /// Deletes a role and removes it from all users
+    pub fn auth_delete_role(&self, config: &AuthDatabaseConfig, name: &str) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_delete_role(config, name),
+        }
+    }
+
+    /// Takes a role from a user
+    pub fn auth_remove_user_role(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        role: &str,
+    ) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_remove_user_role(config, user_id, role)
+            }
+        }
+    }
+
+    /// Gets the sessions of a user
+    pub fn auth_list_sessions(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+    ) -> Vec<AuthSession> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_list_sessions(config, user_id),
+        }
+    }
+
+    /// Deletes a session of a user by id
+    pub fn auth_delete_user_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        session_id: i64,
+    ) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_user_session(config, user_id, session_id)
+            }
+        }
+    }
+
+    /// Deletes all sessions of a user
+    pub fn auth_delete_user_sessions(&self, config: &AuthDatabaseConfig, user_id: i64) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_user_sessions(config, user_id)
+            }
+        }
+    }
+
+    /// Sets the time a session was last used
+    pub fn auth_touch_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        session_id: i64,
+        last_seen: DateTime<Utc>,
+    ) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_touch_session(config, session_id, last_seen)
+            }
+        }
+    }
+
+    /// Deletes the sessions created or last used before the times
+    pub fn auth_delete_expired_sessions(
+        &self,
+        config: &AuthDatabaseConfig,
+        created_before: DateTime<Utc>,
+        last_seen_before: DateTime<Utc>,
+    ) -> usize {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_expired_sessions(config, created_before, last_seen_before)
+            }
+        }
+    }
+
+    /// Creates an API key for a user
+    pub fn auth_create_api_key(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        name: &str,
+        key_hash: &str,
+        roles: Option<&[String]>,
+        expires: Option<DateTime<Utc>>,
+    ) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_create_api_key(config, user_id, name, key_hash, roles, expires)
+            }
+        }
+    }
+
+    /// Gets an API key by key hash
+    pub fn auth_get_api_key(
+        &self,
+        config: &AuthDatabaseConfig,
+        key_hash: &str,
+    ) -> Option<AuthApiKey> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_get_api_key(config, key_hash),
+        }
+    }
+
+    /// Gets the API keys of a user ordered by name
+    pub fn auth_list_api_keys(&self, config: &AuthDatabaseConfig, user_id: i64) -> Vec<AuthApiKey> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_list_api_keys(config, user_id),
+        }
+    }
+
+    /// Deletes an API key of a user by name
+    pub fn auth_delete_api_key(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        name: &str,
+    ) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_api_key(config, user_id, name)
+            }
+        }
+    }
+
+    /// Deletes the API keys that expired before the time
+    pub fn auth_delete_expired_api_keys(
+        &self,
+        config: &AuthDatabaseConfig,
+        expired_before: DateTime<Utc>,
+    ) -> usize {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_expired_api_keys(config, expired_before)
+            }
+        }
+    }
+
+    /// Gets the TOTP second factor of a user
+    pub fn auth_get_totp(&self, config: &AuthDatabaseConfig, user_id: i64) -> Option<AuthTotp> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_get_totp(config, user_id),
+        }
+    }
+
+    /// Sets an unconfirmed TOTP secret for a user
+    pub fn auth_set_totp(&self, config: &AuthDatabaseConfig, user_id: i64, secret: &str) {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_set_totp(config, user_id, secret),
+        }
+    }
+
+    /// Confirms the TOTP secret of a user
+    pub fn auth_confirm_totp(&self, config: &AuthDatabaseConfig, user_id: i64) {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_confirm_totp(config, user_id),
+        }
+    }
+
+    /// Records the use of a TOTP time step by a user
+    pub fn auth_use_totp_step(&self, config: &AuthDatabaseConfig, user_id: i64, step: i64) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_use_totp_step(config, user_id, step)
+            }
+        }
+    }
+
+    /// Counts a wrong code sent by a user
+    pub fn auth_fail_totp(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+    ) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_fail_totp(config, user_id, timestamp)
+            }
+        }
+    }
+
+    /// Resets the count of wrong codes sent by a user
+    pub fn auth_reset_totp_failures(&self, config: &AuthDatabaseConfig, user_id: i64) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_reset_totp_failures(config, user_id)
+            }
+        }
+    }
+
+    /// Deletes the TOTP second factor and recovery codes of a user
+    pub fn auth_delete_totp(&self, config: &AuthDatabaseConfig, user_id: i64) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_delete_totp(config, user_id),
+        }
+    }
+
+    /// Replaces the recovery codes of a user
+    pub fn auth_set_recovery_codes(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        code_hashes: &[String],
+    ) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_set_recovery_codes(config, user_id, code_hashes)
+            }
+        }
+    }
+
+    /// Gets the unused recovery codes of a user
+    pub fn auth_get_recovery_codes(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+    ) -> Vec<AuthRecoveryCode> {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_get_recovery_codes(config, user_id)
+            }
+        }
+    }
+
+    /// Deletes a recovery code
+    pub fn auth_use_recovery_code(&self, config: &AuthDatabaseConfig, code_id: i64) -> bool {
+        match self {
+            DbConnection::SQLite3(connection) => connection.auth_use_recovery_code(config, code_id),
+        }
+    }
+
+    /// Gets the number of unused recovery codes of a user
+    pub fn auth_count_recovery_codes(&self, config: &AuthDatabaseConfig, user_id: i64) -> usize {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_count_recovery_codes(config, user_id)
+            }
+        }
+    }
+
+    /// Creates a login challenge for a user
+    pub fn auth_create_login_challenge(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+    ) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_create_login_challenge(config, token_hash, user_id, timestamp)
+            }
+        }
+    }
+
+    /// Gets a login challenge by token hash
+    pub fn auth_get_login_challenge(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+    ) -> Option<AuthLoginChallenge> {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_get_login_challenge(config, token_hash)
+            }
+        }
+    }
+
+    /// Counts a failed attempt to confirm a login challenge
+    pub fn auth_fail_login_challenge(&self, config: &AuthDatabaseConfig, challenge_id: i64) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_fail_login_challenge(config, challenge_id)
+            }
+        }
+    }
+
+    /// Deletes a login challenge
+    pub fn auth_delete_login_challenge(&self, config: &AuthDatabaseConfig, challenge_id: i64) {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_login_challenge(config, challenge_id)
+            }
+        }
+    }
+
+    /// Deletes the login challenges created before the time
+    pub fn auth_delete_expired_login_challenges(
+        &self,
+        config: &AuthDatabaseConfig,
+        created_before: DateTime<Utc>,
+    ) -> usize {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.auth_delete_expired_login_challenges(config, created_before)
+            }
+        }
+    }
+}
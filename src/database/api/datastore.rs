@@ -1,11 +1,17 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
-use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 use crate::{
-    config::{DatabaseSchemaConfig, DatastoreConfig},
-    database::{drivers::DbConnection, models::datastore::DatastoreValueMeta, DbSchema},
+    config::{DatabaseSchemaConfig, DatastoreConfig, DatastoreIndexConfig},
+    database::{
+        drivers::DbConnection,
+        models::datastore::{
+            DatastoreChangeMeta, DatastoreChildValueMeta, DatastoreQueryFilter, DatastoreQuerySort,
+            DatastoreSearchResult, DatastoreValueMeta, DatastoreWrite,
+        },
+        DbSchema,
+    },
 };
 
 pub struct DatastoreDatabaseConfig {
@@ -14,6 +20,8 @@ pub struct DatastoreDatabaseConfig {
     pub keep_history: bool,
     pub max_age: Option<Duration>,
     pub max_entries: Option<u64>,
+    pub indexes: HashMap<String, DatastoreIndexConfig>,
+    pub full_text_search: bool,
 }
 
 impl DatastoreDatabaseConfig {
@@ -28,6 +36,8 @@ impl DatastoreDatabaseConfig {
             keep_history: datastore_config.keep_history,
             max_age: datastore_config.history_max_age.map(Duration::from_secs),
             max_entries: datastore_config.history_max_entries,
+            indexes: datastore_config.indexes.clone(),
+            full_text_search: datastore_config.full_text_search,
         }
     }
 }
@@ -84,6 +94,34 @@ impl DbSchema {
         )
     }
 
+    /// Gets the content of a blob by its hash
+    pub fn datastore_get_blob(
+        &self,
+        store_name: &str,
+        datastore_config: &DatastoreConfig,
+        hash: &str,
+    ) -> Option<Vec<u8>> {
+        self.connection.datastore_get_blob(
+            &DatastoreDatabaseConfig::new(store_name, &self.config, datastore_config),
+            hash,
+        )
+    }
+
+    /// Stores the content of a blob by its hash
+    pub fn datastore_put_blob(
+        &self,
+        store_name: &str,
+        datastore_config: &DatastoreConfig,
+        hash: &str,
+        data: &[u8],
+    ) {
+        self.connection.datastore_put_blob(
+            &DatastoreDatabaseConfig::new(store_name, &self.config, datastore_config),
+            hash,
+            data,
+        )
+    }
+
     /// Lists child keys of a key that have values
     pub fn datastore_list(
         &self,
@@ -97,18 +135,70 @@ impl DbSchema {
         )
     }
 
-    /// Sets a value to a datastore key
-    pub fn datastore_set(
+    /// Queries the current values of the child keys of a key
+    #[allow(clippy::too_many_arguments)]
+    pub fn datastore_query(
+        &self,
+        store_name: &str,
+        datastore_config: &DatastoreConfig,
+        path: &[&str],
+        filters: &[DatastoreQueryFilter],
+        sort: Option<&DatastoreQuerySort>,
+        limit: Option<u64>,
+        offset: u64,
+    ) -> Vec<DatastoreChildValueMeta> {
+        self.connection.datastore_query(
+            &DatastoreDatabaseConfig::new(store_name, &self.config, datastore_config),
+            path,
+            filters,
+            sort,
+            limit,
+            offset,
+        )
+    }
+
+    /// Gets the metadata of the value entries of a key and its descendants inserted after a cursor, in insertion order
+    pub fn datastore_changes_since(
+        &self,
+        store_name: &str,
+        datastore_config: &DatastoreConfig,
+        path: &[&str],
+        cursor: u64,
+        limit: Option<u64>,
+    ) -> Vec<DatastoreChangeMeta> {
+        self.connection.datastore_changes_since(
+            &DatastoreDatabaseConfig::new(store_name, &self.config, datastore_config),
+            path,
+            cursor,
+            limit,
+        )
+    }
+
+    /// Searches the text of the current values of a key and its descendants
+    pub fn datastore_search(
         &self,
         store_name: &str,
         datastore_config: &DatastoreConfig,
         path: &[&str],
-        value: Option<&str>,
-    ) -> Uuid {
-        self.connection.datastore_set(
+        query: &str,
+    ) -> Vec<DatastoreSearchResult> {
+        self.connection.datastore_search(
             &DatastoreDatabaseConfig::new(store_name, &self.config, datastore_config),
             path,
-            value,
+            query,
+        )
+    }
+
+    /// Sets values to datastore keys in a single transaction, in order
+    pub fn datastore_set_batch(
+        &self,
+        store_name: &str,
+        datastore_config: &DatastoreConfig,
+        writes: &[DatastoreWrite],
+    ) {
+        self.connection.datastore_set_batch(
+            &DatastoreDatabaseConfig::new(store_name, &self.config, datastore_config),
+            writes,
         )
     }
 }
@@ -157,6 +247,24 @@ impl DbConnection {
         }
     }
 
+    /// Gets the content of a blob by its hash
+    pub fn datastore_get_blob(
+        &self,
+        config: &DatastoreDatabaseConfig,
+        hash: &str,
+    ) -> Option<Vec<u8>> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.datastore_get_blob(config, hash),
+        }
+    }
+
+    /// Stores the content of a blob by its hash
+    pub fn datastore_put_blob(&self, config: &DatastoreDatabaseConfig, hash: &str, data: &[u8]) {
+        match self {
+            DbConnection::SQLite3(connection) => connection.datastore_put_blob(config, hash, data),
+        }
+    }
+
     /// Lists child keys of a key that have values
     pub fn datastore_list(&self, config: &DatastoreDatabaseConfig, path: &[&str]) -> Vec<String> {
         match self {
@@ -164,15 +272,54 @@ impl DbConnection {
         }
     }
 
-    /// Sets a value to a datastore key
-    pub fn datastore_set(
+    /// Queries the current values of the child keys of a key
+    pub fn datastore_query(
         &self,
         config: &DatastoreDatabaseConfig,
         path: &[&str],
-        value: Option<&str>,
-    ) -> Uuid {
+        filters: &[DatastoreQueryFilter],
+        sort: Option<&DatastoreQuerySort>,
+        limit: Option<u64>,
+        offset: u64,
+    ) -> Vec<DatastoreChildValueMeta> {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.datastore_query(config, path, filters, sort, limit, offset)
+            }
+        }
+    }
+
+    /// Gets the metadata of the value entries of a key and its descendants inserted after a cursor, in insertion order
+    pub fn datastore_changes_since(
+        &self,
+        config: &DatastoreDatabaseConfig,
+        path: &[&str],
+        cursor: u64,
+        limit: Option<u64>,
+    ) -> Vec<DatastoreChangeMeta> {
+        match self {
+            DbConnection::SQLite3(connection) => {
+                connection.datastore_changes_since(config, path, cursor, limit)
+            }
+        }
+    }
+
+    /// Searches the text of the current values of a key and its descendants
+    pub fn datastore_search(
+        &self,
+        config: &DatastoreDatabaseConfig,
+        path: &[&str],
+        query: &str,
+    ) -> Vec<DatastoreSearchResult> {
+        match self {
+            DbConnection::SQLite3(connection) => connection.datastore_search(config, path, query),
+        }
+    }
+
+    /// Sets values to datastore keys in a single transaction, in order
+    pub fn datastore_set_batch(&self, config: &DatastoreDatabaseConfig, writes: &[DatastoreWrite]) {
         match self {
-            DbConnection::SQLite3(connection) => connection.datastore_set(config, path, value),
+            DbConnection::SQLite3(connection) => connection.datastore_set_batch(config, writes),
         }
     }
 }
@@ -0,0 +1,1154 @@
+//! SQLite3 Authentication database driver
+
+use crate::database::{
+    api::auth::AuthDatabaseConfig,
+    models::auth::{
+        AuthApiKey, AuthLoginChallenge, AuthRecoveryCode, AuthSession, AuthTotp, AuthUser,
+    },
+};
+
+use super::SQLite3Connection;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{named_params, OptionalExtension, Row};
+
+impl SQLite3Connection {
+    pub fn auth_create(&self, config: &AuthDatabaseConfig) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute_batch(&format!(
+            "
+CREATE TABLE IF NOT EXISTS \"{0}users\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"username\" TEXT NOT NULL UNIQUE,
+    \"password_hash\" TEXT,
+    \"active\" INTEGER NOT NULL DEFAULT 1
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}roles\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"name\" TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}user_roles\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"user_id\" INTEGER NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"role_id\" INTEGER NOT NULL REFERENCES \"{0}roles\" (\"id\"),
+    UNIQUE (\"user_id\", \"role_id\")
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}auth_settings\" (
+    \"name\" TEXT PRIMARY KEY NOT NULL,
+    \"value\" TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}api_keys\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"user_id\" INTEGER NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"name\" TEXT NOT NULL,
+    \"key_hash\" TEXT NOT NULL UNIQUE,
+    \"roles\" TEXT,
+    \"expires\" TEXT,
+    \"timestamp\" TEXT NOT NULL,
+    UNIQUE (\"user_id\", \"name\")
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}sessions\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"token_hash\" TEXT NOT NULL UNIQUE,
+    \"user_id\" INTEGER NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"timestamp\" TEXT NOT NULL,
+    \"last_seen\" TEXT NOT NULL,
+    \"second_factor\" INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}user_totp\" (
+    \"user_id\" INTEGER PRIMARY KEY NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"secret\" TEXT NOT NULL,
+    \"confirmed\" INTEGER NOT NULL DEFAULT 0,
+    \"last_step\" INTEGER,
+    \"failed_attempts\" INTEGER NOT NULL DEFAULT 0,
+    \"last_failure\" TEXT
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}recovery_codes\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"user_id\" INTEGER NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"code_hash\" TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS \"{0}login_challenges\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"token_hash\" TEXT NOT NULL UNIQUE,
+    \"user_id\" INTEGER NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"timestamp\" TEXT NOT NULL,
+    \"attempts\" INTEGER NOT NULL DEFAULT 0
+);
+            ",
+            table_prefix
+        ))
+        .unwrap_or_else(|_| {
+            panic!(
+                "An error occurred while creating authentication tables \"{0}\"",
+                table_prefix
+            )
+        });
+
+        // sessions created before last use was tracked were last seen when they were created
+        let has_last_seen: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(:table) WHERE \"name\" = 'last_seen';",
+                named_params! {":table": format!("{0}sessions", table_prefix)},
+                |row| row.get(0),
+            )
+            .expect("Error occurred while querying database");
+        if !has_last_seen {
+            conn.execute_batch(&format!(
+                "
+ALTER TABLE \"{0}sessions\" ADD COLUMN \"last_seen\" TEXT;
+UPDATE \"{0}sessions\" SET \"last_seen\" = \"timestamp\";
+                ",
+                table_prefix
+            ))
+            .unwrap_or_else(|_| {
+                panic!(
+                    "An error occurred while migrating database table \"{0}sessions\"",
+                    table_prefix
+                )
+            });
+        }
+
+        // sessions created before second factors existed didn't confirm one
+        let has_second_factor: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(:table) WHERE \"name\" = 'second_factor';",
+                named_params! {":table": format!("{0}sessions", table_prefix)},
+                |row| row.get(0),
+            )
+            .expect("Error occurred while querying database");
+        if !has_second_factor {
+            conn.execute_batch(&format!(
+                "ALTER TABLE \"{0}sessions\" ADD COLUMN \"second_factor\" INTEGER NOT NULL DEFAULT 0;",
+                table_prefix
+            ))
+            .unwrap_or_else(|_| {
+                panic!(
+                    "An error occurred while migrating database table \"{0}sessions\"",
+                    table_prefix
+                )
+            });
+        }
+    }
+
+    pub fn auth_migrate_session_tokens(
+        &self,
+        config: &AuthDatabaseConfig,
+        hash_token: &dyn Fn(&str) -> String,
+    ) -> usize {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let mut conn = self.get_connection();
+
+        // tables created before tokens were hashed have a token column
+        let has_token: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(:table) WHERE \"name\" = 'token';",
+                named_params! {":table": format!("{0}sessions", table_prefix)},
+                |row| row.get(0),
+            )
+            .expect("Error occurred while querying database");
+        if !has_token {
+            return 0;
+        }
+
+        // recreate the table with hashed tokens in place of the raw tokens
+        let transaction = conn
+            .transaction()
+            .expect("Error occurred while starting database transaction");
+        transaction
+            .execute_batch(&format!(
+                "
+CREATE TABLE \"{0}sessions_hashed\" (
+    \"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    \"token_hash\" TEXT NOT NULL UNIQUE,
+    \"user_id\" INTEGER NOT NULL REFERENCES \"{0}users\" (\"id\"),
+    \"timestamp\" TEXT NOT NULL,
+    \"last_seen\" TEXT NOT NULL,
+    \"second_factor\" INTEGER NOT NULL DEFAULT 0
+);
+                ",
+                table_prefix
+            ))
+            .unwrap_or_else(|_| {
+                panic!(
+                    "An error occurred while migrating database table \"{0}sessions\"",
+                    table_prefix
+                )
+            });
+
+        let sessions = {
+            let mut select_stmt = transaction
+                .prepare(&format!(
+                    "SELECT \"id\", \"token\", \"user_id\", \"timestamp\", \"last_seen\" FROM \"{0}sessions\";",
+                    table_prefix
+                ))
+                .expect("Error occurred while preparing database query");
+            select_stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                    ))
+                })
+                .expect("Error occurred while querying database")
+                .collect::<Result<Vec<_>, _>>()
+                .expect("Error occurred while reading database rows")
+        };
+        for (id, token, user_id, timestamp, last_seen) in &sessions {
+            transaction
+                .execute(
+                    &format!(
+                        "INSERT INTO \"{0}sessions_hashed\" (\"id\", \"token_hash\", \"user_id\", \"timestamp\", \"last_seen\") VALUES (:id, :token_hash, :user_id, :timestamp, :last_seen);",
+                        table_prefix
+                    ),
+                    named_params! {
+                        ":id": id,
+                        ":token_hash": hash_token(token),
+                        ":user_id": user_id,
+                        ":timestamp": timestamp,
+                        ":last_seen": last_seen,
+                    },
+                )
+                .expect("Error occurred while inserting session into database");
+        }
+
+        transaction
+            .execute_batch(&format!(
+                "
+DROP TABLE \"{0}sessions\";
+ALTER TABLE \"{0}sessions_hashed\" RENAME TO \"{0}sessions\";
+                ",
+                table_prefix
+            ))
+            .unwrap_or_else(|_| {
+                panic!(
+                    "An error occurred while migrating database table \"{0}sessions\"",
+                    table_prefix
+                )
+            });
+        transaction
+            .commit()
+            .expect("Error occurred while committing database transaction");
+        sessions.len()
+    }
+
+    pub fn auth_get_or_create_setting(
+        &self,
+        config: &AuthDatabaseConfig,
+        name: &str,
+        value: &str,
+    ) -> String {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO \"{0}auth_settings\" (\"name\", \"value\") VALUES (:name, :value);",
+                table_prefix
+            ),
+            named_params! {":name": name, ":value": value},
+        )
+        .expect("Error occurred while inserting setting into database");
+        conn.query_row(
+            &format!(
+                "SELECT \"value\" FROM \"{0}auth_settings\" WHERE \"name\" = :name;",
+                table_prefix
+            ),
+            named_params! {":name": name},
+            |row| row.get(0),
+        )
+        .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_get_user(&self, config: &AuthDatabaseConfig, username: &str) -> Option<AuthUser> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"username\", \"password_hash\", \"active\" FROM \"{0}users\" WHERE \"username\" = :username;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(
+                named_params! {":username": username},
+                Self::auth_user_from_row,
+            )
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_get_user_by_id(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+    ) -> Option<AuthUser> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"username\", \"password_hash\", \"active\" FROM \"{0}users\" WHERE \"id\" = :user_id;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(
+                named_params! {":user_id": user_id},
+                Self::auth_user_from_row,
+            )
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_create_user(
+        &self,
+        config: &AuthDatabaseConfig,
+        username: &str,
+        password_hash: Option<&str>,
+    ) -> i64 {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT INTO \"{0}users\" (\"username\", \"password_hash\") VALUES (:username, :password_hash);",
+                table_prefix
+            ),
+            named_params! {":username": username, ":password_hash": password_hash},
+        )
+        .expect("Error occurred while inserting user into database");
+        conn.last_insert_rowid()
+    }
+
+    pub fn auth_get_user_roles(&self, config: &AuthDatabaseConfig, user_id: i64) -> Vec<String> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"{0}roles\".\"name\" FROM \"{0}user_roles\" INNER JOIN \"{0}roles\" ON \"{0}roles\".\"id\" = \"{0}user_roles\".\"role_id\" WHERE \"{0}user_roles\".\"user_id\" = :user_id ORDER BY \"{0}roles\".\"name\";",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(named_params! {":user_id": user_id}, |row| row.get(0))
+            .expect("Error occurred while querying database")
+            .collect::<Result<Vec<String>, _>>()
+            .expect("Error occurred while reading database rows")
+    }
+
+    pub fn auth_create_role(&self, config: &AuthDatabaseConfig, name: &str) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO \"{0}roles\" (\"name\") VALUES (:name);",
+                table_prefix
+            ),
+            named_params! {":name": name},
+        )
+        .expect("Error occurred while inserting role into database")
+            > 0
+    }
+
+    pub fn auth_add_user_role(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        role: &str,
+    ) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO \"{0}user_roles\" (\"user_id\", \"role_id\") SELECT :user_id, \"id\" FROM \"{0}roles\" WHERE \"name\" = :role;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":role": role},
+        )
+        .expect("Error occurred while inserting user role into database")
+            > 0
+    }
+
+    pub fn auth_create_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+        second_factor: bool,
+    ) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT INTO \"{0}sessions\" (\"token_hash\", \"user_id\", \"timestamp\", \"last_seen\", \"second_factor\") VALUES (:token_hash, :user_id, :timestamp, :timestamp, :second_factor);",
+                table_prefix
+            ),
+            named_params! {
+                ":token_hash": token_hash,
+                ":user_id": user_id,
+                ":timestamp": timestamp,
+                ":second_factor": second_factor,
+            },
+        )
+        .expect("Error occurred while inserting session into database");
+    }
+
+    pub fn auth_get_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+    ) -> Option<AuthSession> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"user_id\", \"timestamp\", \"last_seen\", \"second_factor\" FROM \"{0}sessions\" WHERE \"token_hash\" = :token_hash;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(
+                named_params! {":token_hash": token_hash},
+                Self::auth_session_from_row,
+            )
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_delete_session(&self, config: &AuthDatabaseConfig, token_hash: &str) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}sessions\" WHERE \"token_hash\" = :token_hash;",
+                table_prefix
+            ),
+            named_params! {":token_hash": token_hash},
+        )
+        .expect("Error occurred while deleting session from database");
+    }
+
+    pub fn auth_list_users(&self, config: &AuthDatabaseConfig) -> Vec<AuthUser> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"username\", \"password_hash\", \"active\" FROM \"{0}users\" ORDER BY \"username\";",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map([], Self::auth_user_from_row)
+            .expect("Error occurred while querying database")
+            .collect::<Result<Vec<AuthUser>, _>>()
+            .expect("Error occurred while reading database rows")
+    }
+
+    pub fn auth_set_user_active(&self, config: &AuthDatabaseConfig, user_id: i64, active: bool) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}users\" SET \"active\" = :active WHERE \"id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":active": active},
+        )
+        .expect("Error occurred while updating user in database");
+    }
+
+    pub fn auth_reset_password(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        password_hash: Option<&str>,
+    ) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let mut conn = self.get_connection();
+        let transaction = conn
+            .transaction()
+            .expect("Error occurred while starting database transaction");
+
+        transaction
+            .execute(
+                &format!(
+                    "UPDATE \"{0}users\" SET \"password_hash\" = :password_hash WHERE \"id\" = :user_id;",
+                    table_prefix
+                ),
+                named_params! {":user_id": user_id, ":password_hash": password_hash},
+            )
+            .expect("Error occurred while updating user in database");
+        transaction
+            .execute(
+                &format!(
+                    "DELETE FROM \"{0}sessions\" WHERE \"user_id\" = :user_id;",
+                    table_prefix
+                ),
+                named_params! {":user_id": user_id},
+            )
+            .expect("Error occurred while deleting sessions from database");
+        transaction
+            .execute(
+                &format!(
+                    "DELETE FROM \"{0}api_keys\" WHERE \"user_id\" = :user_id;",
+                    table_prefix
+                ),
+                named_params! {":user_id": user_id},
+            )
+            .expect("Error occurred while deleting API keys from database");
+
+        transaction
+            .commit()
+            .expect("Error occurred while committing database transaction");
+    }
+
+    pub fn auth_list_roles(&self, config: &AuthDatabaseConfig) -> Vec<String> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"name\" FROM \"{0}roles\" ORDER BY \"name\";",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map([], |row| row.get(0))
+            .expect("Error occurred while querying database")
+            .collect::<Result<Vec<String>, _>>()
+            .expect("Error occurred while reading database rows")
+    }
+
+    pub fn auth_delete_role(&self, config: &AuthDatabaseConfig, name: &str) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let mut conn = self.get_connection();
+        let transaction = conn
+            .transaction()
+            .expect("Error occurred while starting database transaction");
+
+        transaction
+            .execute(
+                &format!(
+                    "DELETE FROM \"{0}user_roles\" WHERE \"role_id\" IN (SELECT \"id\" FROM \"{0}roles\" WHERE \"name\" = :name);",
+                    table_prefix
+                ),
+                named_params! {":name": name},
+            )
+            .expect("Error occurred while deleting user roles from database");
+        let deleted = transaction
+            .execute(
+                &format!(
+                    "DELETE FROM \"{0}roles\" WHERE \"name\" = :name;",
+                    table_prefix
+                ),
+                named_params! {":name": name},
+            )
+            .expect("Error occurred while deleting role from database");
+
+        transaction
+            .commit()
+            .expect("Error occurred while committing database transaction");
+        deleted > 0
+    }
+
+    pub fn auth_remove_user_role(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        role: &str,
+    ) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}user_roles\" WHERE \"user_id\" = :user_id AND \"role_id\" IN (SELECT \"id\" FROM \"{0}roles\" WHERE \"name\" = :role);",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":role": role},
+        )
+        .expect("Error occurred while deleting user role from database")
+            > 0
+    }
+
+    pub fn auth_list_sessions(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+    ) -> Vec<AuthSession> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"user_id\", \"timestamp\", \"last_seen\", \"second_factor\" FROM \"{0}sessions\" WHERE \"user_id\" = :user_id ORDER BY \"id\";",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(
+                named_params! {":user_id": user_id},
+                Self::auth_session_from_row,
+            )
+            .expect("Error occurred while querying database")
+            .collect::<Result<Vec<AuthSession>, _>>()
+            .expect("Error occurred while reading database rows")
+    }
+
+    pub fn auth_delete_user_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        session_id: i64,
+    ) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}sessions\" WHERE \"id\" = :session_id AND \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":session_id": session_id},
+        )
+        .expect("Error occurred while deleting session from database")
+            > 0
+    }
+
+    pub fn auth_delete_user_sessions(&self, config: &AuthDatabaseConfig, user_id: i64) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}sessions\" WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id},
+        )
+        .expect("Error occurred while deleting sessions from database");
+    }
+
+    pub fn auth_touch_session(
+        &self,
+        config: &AuthDatabaseConfig,
+        session_id: i64,
+        last_seen: DateTime<Utc>,
+    ) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}sessions\" SET \"last_seen\" = :last_seen WHERE \"id\" = :session_id;",
+                table_prefix
+            ),
+            named_params! {":session_id": session_id, ":last_seen": last_seen},
+        )
+        .expect("Error occurred while updating session in database");
+    }
+
+    pub fn auth_delete_expired_sessions(
+        &self,
+        config: &AuthDatabaseConfig,
+        created_before: DateTime<Utc>,
+        last_seen_before: DateTime<Utc>,
+    ) -> usize {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}sessions\" WHERE \"timestamp\" < :created_before OR \"last_seen\" < :last_seen_before;",
+                table_prefix
+            ),
+            named_params! {":created_before": created_before, ":last_seen_before": last_seen_before},
+        )
+        .expect("Error occurred while deleting sessions from database")
+    }
+
+    pub fn auth_create_api_key(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        name: &str,
+        key_hash: &str,
+        roles: Option<&[String]>,
+        expires: Option<DateTime<Utc>>,
+    ) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let roles = roles.map(|roles| {
+            serde_json::to_string(roles).expect("Error occurred while serializing API key roles")
+        });
+        conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO \"{0}api_keys\" (\"user_id\", \"name\", \"key_hash\", \"roles\", \"expires\", \"timestamp\") VALUES (:user_id, :name, :key_hash, :roles, :expires, :timestamp);",
+                table_prefix
+            ),
+            named_params! {
+                ":user_id": user_id,
+                ":name": name,
+                ":key_hash": key_hash,
+                ":roles": roles,
+                ":expires": expires,
+                ":timestamp": Utc::now(),
+            },
+        )
+        .expect("Error occurred while inserting API key into database")
+            > 0
+    }
+
+    pub fn auth_get_api_key(
+        &self,
+        config: &AuthDatabaseConfig,
+        key_hash: &str,
+    ) -> Option<AuthApiKey> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"user_id\", \"name\", \"roles\", \"expires\", \"timestamp\" FROM \"{0}api_keys\" WHERE \"key_hash\" = :key_hash;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(
+                named_params! {":key_hash": key_hash},
+                Self::auth_api_key_from_row,
+            )
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_list_api_keys(&self, config: &AuthDatabaseConfig, user_id: i64) -> Vec<AuthApiKey> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"user_id\", \"name\", \"roles\", \"expires\", \"timestamp\" FROM \"{0}api_keys\" WHERE \"user_id\" = :user_id ORDER BY \"name\";",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(
+                named_params! {":user_id": user_id},
+                Self::auth_api_key_from_row,
+            )
+            .expect("Error occurred while querying database")
+            .collect::<Result<Vec<AuthApiKey>, _>>()
+            .expect("Error occurred while reading database rows")
+    }
+
+    pub fn auth_delete_api_key(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        name: &str,
+    ) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}api_keys\" WHERE \"user_id\" = :user_id AND \"name\" = :name;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":name": name},
+        )
+        .expect("Error occurred while deleting API key from database")
+            > 0
+    }
+
+    pub fn auth_delete_expired_api_keys(
+        &self,
+        config: &AuthDatabaseConfig,
+        expired_before: DateTime<Utc>,
+    ) -> usize {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}api_keys\" WHERE \"expires\" < :expired_before;",
+                table_prefix
+            ),
+            named_params! {":expired_before": expired_before},
+        )
+        .expect("Error occurred while deleting API keys from database")
+    }
+
+    pub fn auth_get_totp(&self, config: &AuthDatabaseConfig, user_id: i64) -> Option<AuthTotp> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"user_id\", \"secret\", \"confirmed\", \"last_step\", \"failed_attempts\", \"last_failure\" FROM \"{0}user_totp\" WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(named_params! {":user_id": user_id}, |row| {
+                Ok(AuthTotp {
+                    user_id: row.get(0)?,
+                    secret: row.get(1)?,
+                    confirmed: row.get(2)?,
+                    last_step: row.get(3)?,
+                    failed_attempts: row.get(4)?,
+                    last_failure: row.get(5)?,
+                })
+            })
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_set_totp(&self, config: &AuthDatabaseConfig, user_id: i64, secret: &str) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO \"{0}user_totp\" (\"user_id\", \"secret\", \"confirmed\", \"last_step\") VALUES (:user_id, :secret, 0, NULL);",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":secret": secret},
+        )
+        .expect("Error occurred while inserting TOTP secret into database");
+    }
+
+    pub fn auth_confirm_totp(&self, config: &AuthDatabaseConfig, user_id: i64) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}user_totp\" SET \"confirmed\" = 1 WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id},
+        )
+        .expect("Error occurred while updating TOTP secret in database");
+    }
+
+    pub fn auth_use_totp_step(&self, config: &AuthDatabaseConfig, user_id: i64, step: i64) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}user_totp\" SET \"last_step\" = :step WHERE \"user_id\" = :user_id AND IFNULL(\"last_step\" < :step, 1);",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":step": step},
+        )
+        .expect("Error occurred while updating TOTP secret in database")
+            > 0
+    }
+
+    pub fn auth_fail_totp(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+    ) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}user_totp\" SET \"failed_attempts\" = \"failed_attempts\" + 1, \"last_failure\" = :timestamp WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id, ":timestamp": timestamp},
+        )
+        .expect("Error occurred while updating TOTP secret in database");
+    }
+
+    pub fn auth_reset_totp_failures(&self, config: &AuthDatabaseConfig, user_id: i64) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}user_totp\" SET \"failed_attempts\" = 0, \"last_failure\" = NULL WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id},
+        )
+        .expect("Error occurred while updating TOTP secret in database");
+    }
+
+    pub fn auth_delete_totp(&self, config: &AuthDatabaseConfig, user_id: i64) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}recovery_codes\" WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id},
+        )
+        .expect("Error occurred while deleting recovery codes from database");
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}user_totp\" WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id},
+        )
+        .expect("Error occurred while deleting TOTP secret from database")
+            > 0
+    }
+
+    pub fn auth_set_recovery_codes(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+        code_hashes: &[String],
+    ) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let mut conn = self.get_connection();
+
+        let transaction = conn
+            .transaction()
+            .expect("Error occurred while starting database transaction");
+        transaction
+            .execute(
+                &format!(
+                    "DELETE FROM \"{0}recovery_codes\" WHERE \"user_id\" = :user_id;",
+                    table_prefix
+                ),
+                named_params! {":user_id": user_id},
+            )
+            .expect("Error occurred while deleting recovery codes from database");
+        for code_hash in code_hashes {
+            transaction
+                .execute(
+                    &format!(
+                        "INSERT INTO \"{0}recovery_codes\" (\"user_id\", \"code_hash\") VALUES (:user_id, :code_hash);",
+                        table_prefix
+                    ),
+                    named_params! {":user_id": user_id, ":code_hash": code_hash},
+                )
+                .expect("Error occurred while inserting recovery code into database");
+        }
+        transaction
+            .commit()
+            .expect("Error occurred while committing database transaction");
+    }
+
+    pub fn auth_get_recovery_codes(
+        &self,
+        config: &AuthDatabaseConfig,
+        user_id: i64,
+    ) -> Vec<AuthRecoveryCode> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"code_hash\" FROM \"{0}recovery_codes\" WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(named_params! {":user_id": user_id}, |row| {
+                Ok(AuthRecoveryCode {
+                    id: row.get(0)?,
+                    code_hash: row.get(1)?,
+                })
+            })
+            .expect("Error occurred while querying database")
+            .collect::<Result<Vec<AuthRecoveryCode>, _>>()
+            .expect("Error occurred while reading database rows")
+    }
+
+    pub fn auth_use_recovery_code(&self, config: &AuthDatabaseConfig, code_id: i64) -> bool {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}recovery_codes\" WHERE \"id\" = :code_id;",
+                table_prefix
+            ),
+            named_params! {":code_id": code_id},
+        )
+        .expect("Error occurred while deleting recovery code from database")
+            > 0
+    }
+
+    pub fn auth_count_recovery_codes(&self, config: &AuthDatabaseConfig, user_id: i64) -> usize {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM \"{0}recovery_codes\" WHERE \"user_id\" = :user_id;",
+                table_prefix
+            ),
+            named_params! {":user_id": user_id},
+            |row| row.get(0),
+        )
+        .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_create_login_challenge(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+        user_id: i64,
+        timestamp: DateTime<Utc>,
+    ) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "INSERT INTO \"{0}login_challenges\" (\"token_hash\", \"user_id\", \"timestamp\") VALUES (:token_hash, :user_id, :timestamp);",
+                table_prefix
+            ),
+            named_params! {":token_hash": token_hash, ":user_id": user_id, ":timestamp": timestamp},
+        )
+        .expect("Error occurred while inserting login challenge into database");
+    }
+
+    pub fn auth_get_login_challenge(
+        &self,
+        config: &AuthDatabaseConfig,
+        token_hash: &str,
+    ) -> Option<AuthLoginChallenge> {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"user_id\", \"timestamp\", \"attempts\" FROM \"{0}login_challenges\" WHERE \"token_hash\" = :token_hash;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(named_params! {":token_hash": token_hash}, |row| {
+                Ok(AuthLoginChallenge {
+                    id: row.get(0)?,
+                    user_id: row.get(1)?,
+                    timestamp: row.get(2)?,
+                    attempts: row.get(3)?,
+                })
+            })
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn auth_fail_login_challenge(&self, config: &AuthDatabaseConfig, challenge_id: i64) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "UPDATE \"{0}login_challenges\" SET \"attempts\" = \"attempts\" + 1 WHERE \"id\" = :challenge_id;",
+                table_prefix
+            ),
+            named_params! {":challenge_id": challenge_id},
+        )
+        .expect("Error occurred while updating login challenge in database");
+    }
+
+    pub fn auth_delete_login_challenge(&self, config: &AuthDatabaseConfig, challenge_id: i64) {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}login_challenges\" WHERE \"id\" = :challenge_id;",
+                table_prefix
+            ),
+            named_params! {":challenge_id": challenge_id},
+        )
+        .expect("Error occurred while deleting login challenge from database");
+    }
+
+    pub fn auth_delete_expired_login_challenges(
+        &self,
+        config: &AuthDatabaseConfig,
+        created_before: DateTime<Utc>,
+    ) -> usize {
+        let table_prefix = Self::auth_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}login_challenges\" WHERE \"timestamp\" < :created_before;",
+                table_prefix
+            ),
+            named_params! {":created_before": created_before},
+        )
+        .expect("Error occurred while deleting login challenges from database")
+    }
+
+    fn auth_get_table_prefix(config: &AuthDatabaseConfig) -> String {
+        Self::get_table_prefix(config.namespace.as_deref(), None)
+    }
+
+    fn auth_user_from_row(row: &Row) -> rusqlite::Result<AuthUser> {
+        Ok(AuthUser {
+            id: row.get(0)?,
+            username: row.get(1)?,
+            password_hash: row.get(2)?,
+            active: row.get(3)?,
+        })
+    }
+
+    fn auth_session_from_row(row: &Row) -> rusqlite::Result<AuthSession> {
+        Ok(AuthSession {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            timestamp: row.get(2)?,
+            last_seen: row.get(3)?,
+            second_factor: row.get(4)?,
+        })
+    }
+
+    fn auth_api_key_from_row(row: &Row) -> rusqlite::Result<AuthApiKey> {
+        let roles: Option<String> = row.get(3)?;
+        Ok(AuthApiKey {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            name: row.get(2)?,
+            roles: roles.map(|roles| {
+                serde_json::from_str(&roles).expect("Error occurred while parsing API key roles")
+            }),
+            expires: row.get(4)?,
+            timestamp: row.get(5)?,
+        })
+    }
+}
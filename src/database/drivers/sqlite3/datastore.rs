@@ -1,23 +1,32 @@
 //! SQLite3 Datastore database driver
 
-use std::time::Duration;
-
 use crate::{
-    config::DatastoreConfig,
-    database::{api::datastore::DatastoreDatabaseConfig, models::datastore::DatastoreValueMeta},
+    config::DatastoreIndexConfig,
+    database::{
+        api::datastore::DatastoreDatabaseConfig,
+        models::datastore::{
+            DatastoreChangeMeta, DatastoreChildValueMeta, DatastoreQueryFilter,
+            DatastoreQueryOperation, DatastoreQuerySort, DatastoreSearchResult, DatastoreValueMeta,
+            DatastoreWrite,
+        },
+    },
+    datastore::{blobs::DATASTORE_BLOB_KEY, patterns::PatternSegment},
+    helpers::html::escape_html,
 };
 
 use super::SQLite3Connection;
 
-use chrono::{DateTime, Utc};
-use r2d2::PooledConnection;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{named_params, OptionalExtension};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use rusqlite::{named_params, types::Value as SqlValue, Connection, OptionalExtension, Row, ToSql};
 use uuid::Uuid;
 
-type DBConnection = PooledConnection<SqliteConnectionManager>;
+type DBConnection = Connection;
 
 impl SQLite3Connection {
+    /// Private use characters marking the start and end of matched terms in search snippets until the text is escaped
+    const SNIPPET_MARK_START: char = '\u{E000}';
+    const SNIPPET_MARK_END: char = '\u{E001}';
+
     pub fn datastore_create(&self, config: &DatastoreDatabaseConfig) {
         let table_prefix = Self::datastore_get_table_prefix(config);
         let conn = self.get_connection();
@@ -41,9 +50,60 @@ CREATE TABLE IF NOT EXISTS \"{0}datastore_values\" (
 );
 CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__tree_node_id\" ON \"{0}datastore_values\" (\"tree_node_id\");
 CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}datastore_values\" (\"timestamp\");
+
+CREATE TABLE IF NOT EXISTS \"{0}datastore_blobs\" (
+    \"hash\" TEXT PRIMARY KEY NOT NULL,
+    \"data\" BLOB NOT NULL
+);
             ",
             table_prefix))
         .unwrap_or_else(|_| panic!("An error occurred while creating database tables \"{0}\"", table_prefix));
+
+        // remove blobs no longer referenced by any value
+        conn.execute(
+            &format!(
+                "DELETE FROM \"{0}datastore_blobs\" WHERE \"hash\" NOT IN (SELECT json_extract(\"value\", :hash_path) FROM \"{0}datastore_values\" WHERE json_extract(\"value\", :hash_path) IS NOT NULL);",
+                table_prefix
+            ),
+            named_params! {":hash_path": Self::datastore_json_path(&[String::from(DATASTORE_BLOB_KEY), String::from("hash")])},
+        )
+        .expect("Error occurred while removing unused blobs from database");
+
+        if config.full_text_search {
+            conn.execute_batch(&format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS \"{0}datastore_search\" USING fts5(\"text\");",
+                table_prefix
+            ))
+            .unwrap_or_else(|_| panic!("An error occurred while creating database search table \"{0}datastore_search\"", table_prefix));
+
+            // rebuild the search index in case values were set while it was disabled
+            self.datastore_search_rebuild(&conn, &table_prefix);
+        }
+
+        for (index_name, index_config) in &config.indexes {
+            let index_table = Self::datastore_get_index_table(&table_prefix, index_name);
+
+            conn.execute_batch(&format!(
+                "
+CREATE TABLE IF NOT EXISTS \"{1}\" (
+    \"tree_node_id\" INTEGER PRIMARY KEY NOT NULL REFERENCES \"{0}datastore_tree\" (\"id\"),
+    \"parent_id\" INTEGER REFERENCES \"{0}datastore_tree\" (\"id\"),
+    \"value\"
+);
+CREATE INDEX IF NOT EXISTS \"{0}index_{1}__parent_id__value\" ON \"{1}\" (\"parent_id\", \"value\");
+                ",
+                table_prefix, index_table
+            ))
+            .unwrap_or_else(|_| {
+                panic!(
+                    "An error occurred while creating database index table \"{0}\"",
+                    index_table
+                )
+            });
+
+            // rebuild the index in case values were set before the index was configured or the indexed field changed
+            self.datastore_index_rebuild(&conn, &table_prefix, &index_table, index_config);
+        }
     }
 
     pub fn datastore_get_current(
@@ -54,7 +114,21 @@ CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}data
         let table_prefix = Self::datastore_get_table_prefix(config);
         let conn = self.get_connection();
 
-        todo!()
+        let node_id = self.datastore_key_get(&conn, &table_prefix, path, None)?;
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"change_id\", \"timestamp\" FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id ORDER BY \"id\" DESC LIMIT 1;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(
+                named_params! {":node_id": node_id},
+                Self::datastore_value_meta_from_row,
+            )
+            .optional()
+            .expect("Error occurred while querying database")
     }
 
     pub fn datastore_get_history(
@@ -66,7 +140,26 @@ CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}data
         let table_prefix = Self::datastore_get_table_prefix(config);
         let conn = self.get_connection();
 
-        todo!()
+        let node_id = match self.datastore_key_get(&conn, &table_prefix, path, None) {
+            Some(node_id) => node_id,
+            None => return Vec::new(),
+        };
+
+        // if the last change id is no longer in the history, all entries are returned
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"id\", \"change_id\", \"timestamp\" FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id AND \"id\" > IFNULL((SELECT \"id\" FROM \"{0}datastore_values\" WHERE \"change_id\" = :last_change_id), 0) ORDER BY \"id\" ASC;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(
+                named_params! {":node_id": node_id, ":last_change_id": last_change_id},
+                Self::datastore_value_meta_from_row,
+            )
+            .expect("Error occurred while querying database")
+            .collect::<Result<_, _>>()
+            .expect("Error occurred while querying database")
     }
 
     pub fn datastore_get_value(
@@ -91,56 +184,748 @@ CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}data
         result.flatten()
     }
 
+    pub fn datastore_get_blob(
+        &self,
+        config: &DatastoreDatabaseConfig,
+        hash: &str,
+    ) -> Option<Vec<u8>> {
+        let table_prefix = Self::datastore_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "SELECT \"data\" FROM \"{0}datastore_blobs\" WHERE \"hash\" = :hash;",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_row(named_params! {":hash": hash}, |row| row.get(0))
+            .optional()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn datastore_put_blob(&self, config: &DatastoreDatabaseConfig, hash: &str, data: &[u8]) {
+        let table_prefix = Self::datastore_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        // identical content is only stored once
+        let mut insert_stmt = conn
+            .prepare_cached(&format!(
+                "INSERT OR IGNORE INTO \"{0}datastore_blobs\" (\"hash\", \"data\") VALUES (:hash, :data);",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        insert_stmt
+            .execute(named_params! {":hash": hash, ":data": data})
+            .expect("Error occurred while inserting into database");
+    }
+
     pub fn datastore_list(&self, config: &DatastoreDatabaseConfig, path: &[&str]) -> Vec<String> {
         let table_prefix = Self::datastore_get_table_prefix(config);
         let conn = self.get_connection();
 
-        todo!()
+        let node_id = match self.datastore_key_get(&conn, &table_prefix, path, None) {
+            Some(node_id) => node_id,
+            None => return Vec::new(),
+        };
+
+        // find all children that have a value or have a descendant with a value
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "
+WITH RECURSIVE \"descendants\" (\"child_key\", \"id\") AS (
+    SELECT \"key\", \"id\" FROM \"{0}datastore_tree\" WHERE \"parent_id\" IS :node_id
+    UNION ALL
+    SELECT \"descendants\".\"child_key\", \"{0}datastore_tree\".\"id\" FROM \"{0}datastore_tree\" JOIN \"descendants\" ON \"{0}datastore_tree\".\"parent_id\" = \"descendants\".\"id\"
+)
+SELECT DISTINCT \"child_key\" FROM \"descendants\"
+WHERE (SELECT \"value\" IS NOT NULL FROM \"{0}datastore_values\" WHERE \"tree_node_id\" = \"descendants\".\"id\" ORDER BY \"id\" DESC LIMIT 1)
+ORDER BY \"child_key\";
+                ",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(named_params! {":node_id": node_id}, |row| row.get(0))
+            .expect("Error occurred while querying database")
+            .collect::<Result<_, _>>()
+            .expect("Error occurred while querying database")
     }
 
-    pub fn datastore_set(
+    pub fn datastore_query(
         &self,
         config: &DatastoreDatabaseConfig,
         path: &[&str],
-        value: Option<&str>,
-    ) -> Uuid {
-        let timestamp = Utc::now();
-        let change_id = Uuid::new_v4();
+        filters: &[DatastoreQueryFilter],
+        sort: Option<&DatastoreQuerySort>,
+        limit: Option<u64>,
+        offset: u64,
+    ) -> Vec<DatastoreChildValueMeta> {
+        let table_prefix = Self::datastore_get_table_prefix(config);
+        let conn = self.get_connection();
+
+        let node_id = match self.datastore_key_get(&conn, &table_prefix, path, None) {
+            Some(node_id) => node_id,
+            None => return Vec::new(),
+        };
+
+        let mut params: Vec<(String, SqlValue)> = vec![
+            (String::from(":node_id"), SqlValue::from(node_id)),
+            (
+                String::from(":limit"),
+                SqlValue::from(limit.map_or(-1, |x| x as i64)),
+            ),
+            (String::from(":offset"), SqlValue::from(offset as i64)),
+        ];
+
+        // indexes used by the query mapped to their table aliases
+        let mut index_aliases: Vec<(String, String)> = Vec::new();
+        let mut index_alias = |field: &[String]| -> Option<String> {
+            let index_table = Self::datastore_find_index(config, &table_prefix, path, field)?;
+            if let Some((_, alias)) = index_aliases.iter().find(|(x, _)| *x == index_table) {
+                Some(alias.clone())
+            } else {
+                let alias = format!("index_{}", index_aliases.len());
+                index_aliases.push((index_table, alias.clone()));
+                Some(alias)
+            }
+        };
+
+        // build filter conditions
+        let mut conditions = String::new();
+        for (i, filter) in filters.iter().enumerate() {
+            let field_param = format!(":field_{}", i);
+            let value_param = format!(":value_{}", i);
+
+            let value = match &filter.operation {
+                DatastoreQueryOperation::Eq(value)
+                | DatastoreQueryOperation::Lt(value)
+                | DatastoreQueryOperation::Le(value)
+                | DatastoreQueryOperation::Gt(value)
+                | DatastoreQueryOperation::Ge(value)
+                | DatastoreQueryOperation::Contains(value) => value,
+            };
+
+            // null and containment checks need the JSON value rather than the extracted field stored in indexes
+            let index = match filter.operation {
+                DatastoreQueryOperation::Eq(serde_json::Value::Null)
+                | DatastoreQueryOperation::Contains(_) => None,
+                _ => index_alias(&filter.field),
+            };
+            let field = if let Some(alias) = &index {
+                format!("\"{}\".\"value\"", alias)
+            } else {
+                params.push((
+                    field_param.clone(),
+                    SqlValue::from(Self::datastore_json_path(&filter.field)),
+                ));
+                format!("json_extract(\"values\".\"value\", {})", field_param)
+            };
+
+            let condition = match &filter.operation {
+                DatastoreQueryOperation::Eq(serde_json::Value::Null) => {
+                    format!("json_type(\"values\".\"value\", {}) = 'null'", field_param)
+                }
+                DatastoreQueryOperation::Eq(_) => format!("{} = {}", field, value_param),
+                DatastoreQueryOperation::Lt(_) => format!("{} < {}", field, value_param),
+                DatastoreQueryOperation::Le(_) => format!("{} <= {}", field, value_param),
+                DatastoreQueryOperation::Gt(_) => format!("{} > {}", field, value_param),
+                DatastoreQueryOperation::Ge(_) => format!("{} >= {}", field, value_param),
+                DatastoreQueryOperation::Contains(_) => format!(
+                    "(CASE json_type(\"values\".\"value\", {0}) WHEN 'array' THEN EXISTS (SELECT 1 FROM json_each(\"values\".\"value\", {0}) WHERE json_each.\"value\" = {1}) WHEN 'text' THEN instr({2}, {1}) > 0 ELSE 0 END)",
+                    field_param, value_param, field
+                ),
+            };
+            conditions.push_str(" AND ");
+            conditions.push_str(&condition);
+
+            if !matches!(
+                filter.operation,
+                DatastoreQueryOperation::Eq(serde_json::Value::Null)
+            ) {
+                params.push((value_param, Self::datastore_json_to_sql(value)));
+            }
+        }
+
+        // build sort order, always sorting by key last so that pages are stable
+        let order = if let Some(sort) = sort {
+            let field = if let Some(alias) = index_alias(&sort.field) {
+                format!("\"{}\".\"value\"", alias)
+            } else {
+                params.push((
+                    String::from(":sort_field"),
+                    SqlValue::from(Self::datastore_json_path(&sort.field)),
+                ));
+                String::from("json_extract(\"values\".\"value\", :sort_field)")
+            };
+            format!(
+                "{} {}, \"tree\".\"key\" ASC",
+                field,
+                if sort.descending { "DESC" } else { "ASC" }
+            )
+        } else {
+            String::from("\"tree\".\"key\" ASC")
+        };
+
+        // index tables contain a row for every child with a value, so they can be inner joined
+        let mut joins = String::new();
+        for (index_table, alias) in &index_aliases {
+            joins.push_str(&format!(
+                "\nJOIN \"{0}\" AS \"{1}\" ON \"{1}\".\"tree_node_id\" = \"tree\".\"id\" AND \"{1}\".\"parent_id\" IS :node_id",
+                index_table, alias
+            ));
+        }
+
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "
+SELECT \"tree\".\"key\", \"values\".\"id\", \"values\".\"change_id\", \"values\".\"timestamp\"
+FROM \"{0}datastore_tree\" AS \"tree\"{3}
+JOIN \"{0}datastore_values\" AS \"values\" ON \"values\".\"id\" = (SELECT MAX(\"id\") FROM \"{0}datastore_values\" WHERE \"tree_node_id\" = \"tree\".\"id\")
+WHERE \"tree\".\"parent_id\" IS :node_id AND \"values\".\"value\" IS NOT NULL{1}
+ORDER BY {2}
+LIMIT :limit OFFSET :offset;
+                ",
+                table_prefix, conditions, order, joins
+            ))
+            .expect("Error occurred while preparing database query");
 
+        let params: Vec<(&str, &dyn ToSql)> = params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value as &dyn ToSql))
+            .collect();
+        select_stmt
+            .query_map(params.as_slice(), |row| {
+                Ok(DatastoreChildValueMeta {
+                    key: row.get(0)?,
+                    value: DatastoreValueMeta {
+                        id: row.get(1)?,
+                        change_id: row.get(2)?,
+                        timestamp: row.get(3)?,
+                    },
+                })
+            })
+            .expect("Error occurred while querying database")
+            .collect::<Result<_, _>>()
+            .expect("Error occurred while querying database")
+    }
+
+    pub fn datastore_changes_since(
+        &self,
+        config: &DatastoreDatabaseConfig,
+        path: &[&str],
+        cursor: u64,
+        limit: Option<u64>,
+    ) -> Vec<DatastoreChangeMeta> {
         let table_prefix = Self::datastore_get_table_prefix(config);
         let conn = self.get_connection();
 
-        let node_id = self.datastore_key_get_or_create(&conn, &table_prefix, path, None);
+        let node_id = match self.datastore_key_get(&conn, &table_prefix, path, None) {
+            Some(node_id) => node_id,
+            None => return Vec::new(),
+        };
 
-        let mut insert_stmt = conn
+        // the value ids are autoincremented, so they follow the order the changes happened in
+        let mut select_stmt = conn
             .prepare_cached(&format!(
-                "INSERT INTO \"{0}datastore_values\" (\"tree_node_id\", \"change_id\", \"timestamp\", \"value\") VALUES (:node_id, :change_id, :timestamp, :value));",
+                "
+WITH RECURSIVE \"descendants\" (\"id\") AS (
+    SELECT :node_id
+    UNION ALL
+    SELECT \"{0}datastore_tree\".\"id\" FROM \"{0}datastore_tree\" JOIN \"descendants\" ON \"{0}datastore_tree\".\"parent_id\" IS \"descendants\".\"id\"
+)
+SELECT \"id\", \"change_id\", \"timestamp\", \"tree_node_id\" FROM \"{0}datastore_values\"
+WHERE \"id\" > :cursor AND IFNULL(\"tree_node_id\", 0) IN (SELECT IFNULL(\"id\", 0) FROM \"descendants\")
+ORDER BY \"id\"
+LIMIT :limit;
+                ",
                 table_prefix
             ))
             .expect("Error occurred while preparing database query");
-        insert_stmt
-            .execute(named_params! {":node_id": node_id, ":change_id": change_id, ":timestamp": timestamp, "value": value})
-            .expect("Error occurred while inserting into database");
+        let changes: Vec<(DatastoreValueMeta, Option<i64>)> = select_stmt
+            .query_map(
+                named_params! {":node_id": node_id, ":cursor": cursor as i64, ":limit": limit.map_or(-1, |x| x as i64)},
+                |row| Ok((Self::datastore_value_meta_from_row(row)?, row.get(3)?)),
+            )
+            .expect("Error occurred while querying database")
+            .collect::<Result<_, _>>()
+            .expect("Error occurred while querying database");
 
-        change_id
+        changes
+            .into_iter()
+            .map(|(value, node_id)| DatastoreChangeMeta {
+                path: self.datastore_key_path(&conn, &table_prefix, node_id),
+                value,
+            })
+            .collect()
     }
 
-    pub fn datastore_cleanup(&self, config: &DatastoreDatabaseConfig, path: &[&str]) {
-        let timestamp = Utc::now();
+    pub fn datastore_search(
+        &self,
+        config: &DatastoreDatabaseConfig,
+        path: &[&str],
+        query: &str,
+    ) -> Vec<DatastoreSearchResult> {
+        if !config.full_text_search {
+            return Vec::new();
+        }
 
         let table_prefix = Self::datastore_get_table_prefix(config);
         let conn = self.get_connection();
 
+        let node_id = match self.datastore_key_get(&conn, &table_prefix, path, None) {
+            Some(node_id) => node_id,
+            None => return Vec::new(),
+        };
+
+        let query = Self::datastore_search_query(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        // search index rows are keyed by tree node id, with the root key stored as 0
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "
+WITH RECURSIVE \"descendants\" (\"id\") AS (
+    SELECT :node_id
+    UNION ALL
+    SELECT \"{0}datastore_tree\".\"id\" FROM \"{0}datastore_tree\" JOIN \"descendants\" ON \"{0}datastore_tree\".\"parent_id\" IS \"descendants\".\"id\"
+)
+SELECT \"rowid\", snippet(\"{0}datastore_search\", 0, :mark_start, :mark_end, '...', 16), \"rank\"
+FROM \"{0}datastore_search\"
+WHERE \"{0}datastore_search\" MATCH :query AND \"rowid\" IN (SELECT IFNULL(\"id\", 0) FROM \"descendants\")
+ORDER BY \"rank\";
+                ",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        let matches: Vec<(i64, String, f64)> = select_stmt
+            .query_map(
+                named_params! {
+                    ":node_id": node_id,
+                    ":query": query,
+                    ":mark_start": Self::SNIPPET_MARK_START.to_string(),
+                    ":mark_end": Self::SNIPPET_MARK_END.to_string(),
+                },
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .expect("Error occurred while querying database")
+            .collect::<Result<_, _>>()
+            .expect("Error occurred while querying database");
+
+        matches
+            .into_iter()
+            .map(|(node_id, snippet, rank)| DatastoreSearchResult {
+                path: self.datastore_key_path(
+                    &conn,
+                    &table_prefix,
+                    if node_id == 0 { None } else { Some(node_id) },
+                ),
+                // the text is escaped before the markers become markup, so only the highlighting is HTML
+                snippet: escape_html(&snippet)
+                    .replace(Self::SNIPPET_MARK_START, "<mark>")
+                    .replace(Self::SNIPPET_MARK_END, "</mark>"),
+                rank,
+            })
+            .collect()
+    }
+
+    pub fn datastore_set_batch(&self, config: &DatastoreDatabaseConfig, writes: &[DatastoreWrite]) {
+        let table_prefix = Self::datastore_get_table_prefix(config);
+        let mut conn = self.get_connection();
+        let transaction = conn
+            .transaction()
+            .expect("Error occurred while starting database transaction");
+
+        for write in writes {
+            let path: Vec<&str> = write.path.iter().map(|x| x.as_str()).collect();
+            self.datastore_set_in_transaction(
+                &transaction,
+                config,
+                &table_prefix,
+                &path,
+                write.value.as_deref(),
+                write.change_id,
+                write.timestamp,
+            );
+        }
+
+        transaction
+            .commit()
+            .expect("Error occurred while committing database transaction");
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn datastore_set_in_transaction(
+        &self,
+        transaction: &DBConnection,
+        config: &DatastoreDatabaseConfig,
+        table_prefix: &str,
+        path: &[&str],
+        value: Option<&str>,
+        change_id: Uuid,
+        timestamp: DateTime<Utc>,
+    ) {
+        let node_id = self.datastore_key_get_or_create(transaction, table_prefix, path, None);
+
+        let id = {
+            let mut insert_stmt = transaction
+                .prepare_cached(&format!(
+                    "INSERT INTO \"{0}datastore_values\" (\"tree_node_id\", \"change_id\", \"timestamp\", \"value\") VALUES (:node_id, :change_id, :timestamp, :value);",
+                    table_prefix
+                ))
+                .expect("Error occurred while preparing database query");
+            insert_stmt
+                .insert(named_params! {":node_id": node_id, ":change_id": change_id, ":timestamp": timestamp, ":value": value})
+                .expect("Error occurred while inserting into database")
+        };
+
+        self.datastore_cleanup_node(transaction, config, table_prefix, node_id, id);
+
+        if config.full_text_search {
+            self.datastore_search_update(transaction, table_prefix, node_id, value);
+        }
+
+        // update the indexes that include this key
+        if let Some(node_id) = node_id {
+            for (index_name, index_config) in &config.indexes {
+                if index_config.path.matches(path) {
+                    let index_table = Self::datastore_get_index_table(table_prefix, index_name);
+                    self.datastore_index_update(
+                        transaction,
+                        table_prefix,
+                        &index_table,
+                        index_config,
+                        node_id,
+                        value,
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn datastore_cleanup(&self, config: &DatastoreDatabaseConfig, path: &[&str]) {
+        let table_prefix = Self::datastore_get_table_prefix(config);
+        let conn = self.get_connection();
+
         let node_id = self.datastore_key_get(&conn, &table_prefix, path, None);
         if let Some(node_id) = node_id {
-            if config.keep_history {
-                // delete all but the latest entry older than the max age
-            } else {
-                // delete all but latest value
+            let latest_id: Option<i64> = conn
+                .prepare_cached(&format!(
+                    "SELECT MAX(\"id\") FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id;",
+                    table_prefix
+                ))
+                .expect("Error occurred while preparing database query")
+                .query_row(named_params! {":node_id": node_id}, |row| row.get(0))
+                .expect("Error occurred while querying database");
+
+            if let Some(latest_id) = latest_id {
+                self.datastore_cleanup_node(&conn, config, &table_prefix, node_id, latest_id);
             }
         }
     }
 
+    /// Removes the history entries of a key that are no longer kept, never removing the latest entry
+    fn datastore_cleanup_node(
+        &self,
+        conn: &DBConnection,
+        config: &DatastoreDatabaseConfig,
+        table_prefix: &str,
+        node_id: Option<i64>,
+        latest_id: i64,
+    ) {
+        // blobs referenced by the removed entries are deleted if nothing else references them
+        let mut removed_blobs: Vec<String> = Vec::new();
+        let hash_path =
+            Self::datastore_json_path(&[String::from(DATASTORE_BLOB_KEY), String::from("hash")]);
+
+        if config.keep_history {
+            // delete all but the latest entry older than the max age
+            if let Some(max_age) = config.max_age {
+                let max_age = ChronoDuration::from_std(max_age).unwrap_or(ChronoDuration::MAX);
+                let cutoff = Utc::now().checked_sub_signed(max_age);
+                if let Some(cutoff) = cutoff {
+                    conn.prepare_cached(&format!(
+                        "DELETE FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id AND \"id\" < :latest_id AND \"timestamp\" < :cutoff RETURNING CAST(json_extract(\"value\", :hash_path) AS TEXT);",
+                        table_prefix
+                    ))
+                    .expect("Error occurred while preparing database query")
+                    .query_map(named_params! {":node_id": node_id, ":latest_id": latest_id, ":cutoff": cutoff, ":hash_path": hash_path}, |row| row.get::<_, Option<String>>(0))
+                    .expect("Error occurred while deleting from database")
+                    .for_each(|hash| removed_blobs.extend(hash.expect("Error occurred while deleting from database")));
+                }
+            }
+
+            // delete all but the latest entry and the configured number of previous entries
+            if let Some(max_entries) = config.max_entries {
+                conn.prepare_cached(&format!(
+                    "DELETE FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id AND \"id\" < :latest_id AND \"id\" NOT IN (SELECT \"id\" FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id ORDER BY \"id\" DESC LIMIT :keep) RETURNING CAST(json_extract(\"value\", :hash_path) AS TEXT);",
+                    table_prefix
+                ))
+                .expect("Error occurred while preparing database query")
+                .query_map(named_params! {":node_id": node_id, ":latest_id": latest_id, ":keep": max_entries.saturating_add(1).min(i64::MAX as u64) as i64, ":hash_path": hash_path}, |row| row.get::<_, Option<String>>(0))
+                .expect("Error occurred while deleting from database")
+                .for_each(|hash| removed_blobs.extend(hash.expect("Error occurred while deleting from database")));
+            }
+        } else {
+            // delete all but latest value
+            conn.prepare_cached(&format!(
+                "DELETE FROM \"{0}datastore_values\" WHERE \"tree_node_id\" IS :node_id AND \"id\" < :latest_id RETURNING CAST(json_extract(\"value\", :hash_path) AS TEXT);",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query")
+            .query_map(named_params! {":node_id": node_id, ":latest_id": latest_id, ":hash_path": hash_path}, |row| row.get::<_, Option<String>>(0))
+            .expect("Error occurred while deleting from database")
+            .for_each(|hash| removed_blobs.extend(hash.expect("Error occurred while deleting from database")));
+        }
+
+        removed_blobs.sort();
+        removed_blobs.dedup();
+        for hash in removed_blobs {
+            conn.prepare_cached(&format!(
+                "DELETE FROM \"{0}datastore_blobs\" WHERE \"hash\" = :hash AND NOT EXISTS (SELECT 1 FROM \"{0}datastore_values\" WHERE json_extract(\"value\", :hash_path) = :hash);",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query")
+            .execute(named_params! {":hash": hash, ":hash_path": hash_path})
+            .expect("Error occurred while removing unused blob from database");
+        }
+    }
+
+    /// Updates the search index entry of a key after its value was set
+    fn datastore_search_update(
+        &self,
+        conn: &DBConnection,
+        table_prefix: &str,
+        node_id: Option<i64>,
+        value: Option<&str>,
+    ) {
+        conn.prepare_cached(&format!(
+            "DELETE FROM \"{0}datastore_search\" WHERE \"rowid\" = IFNULL(:node_id, 0);",
+            table_prefix
+        ))
+        .expect("Error occurred while preparing database query")
+        .execute(named_params! {":node_id": node_id})
+        .expect("Error occurred while deleting from database");
+
+        // only the strings in the value are indexed
+        if value.is_some() {
+            conn.prepare_cached(&format!(
+                "INSERT INTO \"{0}datastore_search\" (\"rowid\", \"text\") SELECT IFNULL(:node_id, 0), group_concat(\"value\", ' ') FROM json_tree(:value) WHERE \"type\" = 'text';",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query")
+            .execute(named_params! {":node_id": node_id, ":value": value})
+            .expect("Error occurred while inserting into database");
+        }
+    }
+
+    /// Recreates all entries of the search index from the current values
+    fn datastore_search_rebuild(&self, conn: &DBConnection, table_prefix: &str) {
+        conn.execute_batch(&format!(
+            "
+BEGIN;
+DELETE FROM \"{0}datastore_search\";
+INSERT INTO \"{0}datastore_search\" (\"rowid\", \"text\")
+SELECT IFNULL(\"values\".\"tree_node_id\", 0), (SELECT group_concat(\"value\", ' ') FROM json_tree(\"values\".\"value\") WHERE \"type\" = 'text')
+FROM \"{0}datastore_values\" AS \"values\"
+WHERE \"values\".\"id\" IN (SELECT MAX(\"id\") FROM \"{0}datastore_values\" GROUP BY \"tree_node_id\") AND \"values\".\"value\" IS NOT NULL;
+COMMIT;
+            ",
+            table_prefix
+        ))
+        .expect("Error occurred while rebuilding search index");
+    }
+
+    /// Converts a user search query into an FTS5 query.
+    /// Each word is matched as a literal term, with a trailing "*" matching words with that prefix.
+    fn datastore_search_query(query: &str) -> String {
+        query
+            .split_whitespace()
+            .filter_map(|word| {
+                let (word, prefix) = match word.strip_suffix('*') {
+                    Some(word) => (word, "*"),
+                    None => (word, ""),
+                };
+                if word.is_empty() {
+                    None
+                } else {
+                    Some(format!("\"{}\"{}", word.replace('"', "\"\""), prefix))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Gets the path of a tree node
+    fn datastore_key_path(
+        &self,
+        conn: &DBConnection,
+        table_prefix: &str,
+        node_id: Option<i64>,
+    ) -> Vec<String> {
+        let mut select_stmt = conn
+            .prepare_cached(&format!(
+                "
+WITH RECURSIVE \"ancestors\" (\"id\", \"parent_id\", \"key\", \"depth\") AS (
+    SELECT \"id\", \"parent_id\", \"key\", 0 FROM \"{0}datastore_tree\" WHERE \"id\" = :node_id
+    UNION ALL
+    SELECT \"{0}datastore_tree\".\"id\", \"{0}datastore_tree\".\"parent_id\", \"{0}datastore_tree\".\"key\", \"ancestors\".\"depth\" + 1 FROM \"{0}datastore_tree\" JOIN \"ancestors\" ON \"{0}datastore_tree\".\"id\" = \"ancestors\".\"parent_id\"
+)
+SELECT \"key\" FROM \"ancestors\" ORDER BY \"depth\" DESC;
+                ",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+        select_stmt
+            .query_map(named_params! {":node_id": node_id}, |row| row.get(0))
+            .expect("Error occurred while querying database")
+            .collect::<Result<_, _>>()
+            .expect("Error occurred while querying database")
+    }
+
+    /// Updates the index entry of a key after its value was set
+    fn datastore_index_update(
+        &self,
+        conn: &DBConnection,
+        table_prefix: &str,
+        index_table: &str,
+        index_config: &DatastoreIndexConfig,
+        node_id: i64,
+        value: Option<&str>,
+    ) {
+        if value.is_some() {
+            conn.prepare_cached(&format!(
+                "INSERT OR REPLACE INTO \"{1}\" (\"tree_node_id\", \"parent_id\", \"value\") SELECT \"id\", \"parent_id\", json_extract(:value, :field) FROM \"{0}datastore_tree\" WHERE \"id\" = :node_id;",
+                table_prefix, index_table
+            ))
+            .expect("Error occurred while preparing database query")
+            .execute(named_params! {":node_id": node_id, ":value": value, ":field": Self::datastore_json_path(&index_config.field)})
+            .expect("Error occurred while updating database");
+        } else {
+            // deleted values are not included in the index
+            conn.prepare_cached(&format!(
+                "DELETE FROM \"{0}\" WHERE \"tree_node_id\" = :node_id;",
+                index_table
+            ))
+            .expect("Error occurred while preparing database query")
+            .execute(named_params! {":node_id": node_id})
+            .expect("Error occurred while deleting from database");
+        }
+    }
+
+    /// Recreates all entries of an index from the current values
+    fn datastore_index_rebuild(
+        &self,
+        conn: &DBConnection,
+        table_prefix: &str,
+        index_table: &str,
+        index_config: &DatastoreIndexConfig,
+    ) {
+        let transaction = conn
+            .unchecked_transaction()
+            .expect("Error occurred while starting database transaction");
+
+        transaction
+            .execute(&format!("DELETE FROM \"{0}\";", index_table), [])
+            .expect("Error occurred while deleting from database");
+
+        // find the nodes matching the index path pattern one level at a time
+        let mut node_ids: Vec<Option<i64>> = vec![None];
+        for segment in index_config.path.segments() {
+            let key = match segment {
+                PatternSegment::Key(key) => Some(key.as_str()),
+                PatternSegment::Capture(_) | PatternSegment::Any => None,
+                PatternSegment::Rest => {
+                    // "**" matches the nodes themselves and all their descendants
+                    let mut descendant_ids = node_ids.clone();
+                    while !node_ids.is_empty() {
+                        node_ids = Self::datastore_child_node_ids(
+                            &transaction,
+                            table_prefix,
+                            &node_ids,
+                            None,
+                        );
+                        descendant_ids.extend(&node_ids);
+                    }
+                    node_ids = descendant_ids;
+                    break;
+                }
+            };
+            node_ids = Self::datastore_child_node_ids(&transaction, table_prefix, &node_ids, key);
+        }
+
+        for node_id in node_ids.into_iter().flatten() {
+            transaction
+                .prepare_cached(&format!(
+                    "INSERT INTO \"{1}\" (\"tree_node_id\", \"parent_id\", \"value\") SELECT \"tree\".\"id\", \"tree\".\"parent_id\", json_extract(\"values\".\"value\", :field) FROM \"{0}datastore_tree\" AS \"tree\" JOIN \"{0}datastore_values\" AS \"values\" ON \"values\".\"id\" = (SELECT MAX(\"id\") FROM \"{0}datastore_values\" WHERE \"tree_node_id\" = \"tree\".\"id\") WHERE \"tree\".\"id\" = :node_id AND \"values\".\"value\" IS NOT NULL;",
+                    table_prefix, index_table
+                ))
+                .expect("Error occurred while preparing database query")
+                .execute(named_params! {":node_id": node_id, ":field": Self::datastore_json_path(&index_config.field)})
+                .expect("Error occurred while inserting into database");
+        }
+
+        transaction
+            .commit()
+            .expect("Error occurred while committing database transaction");
+    }
+
+    /// Gets the ids of the child nodes of the parent nodes, limited to the key if given
+    fn datastore_child_node_ids(
+        transaction: &DBConnection,
+        table_prefix: &str,
+        parent_ids: &[Option<i64>],
+        key: Option<&str>,
+    ) -> Vec<Option<i64>> {
+        let mut select_stmt = transaction
+            .prepare_cached(&format!(
+                "SELECT \"id\" FROM \"{0}datastore_tree\" WHERE \"parent_id\" IS :parent_id AND (:key IS NULL OR \"key\" = :key);",
+                table_prefix
+            ))
+            .expect("Error occurred while preparing database query");
+
+        let mut child_ids = Vec::new();
+        for parent_id in parent_ids {
+            let ids = select_stmt
+                .query_map(
+                    named_params! {":parent_id": parent_id, ":key": key},
+                    |row| row.get(0),
+                )
+                .expect("Error occurred while querying database")
+                .collect::<Result<Vec<Option<i64>>, _>>()
+                .expect("Error occurred while querying database");
+            child_ids.extend(ids);
+        }
+        child_ids
+    }
+
+    /// Finds the table of an index that covers the field of every child of a path
+    fn datastore_find_index(
+        config: &DatastoreDatabaseConfig,
+        table_prefix: &str,
+        path: &[&str],
+        field: &[String],
+    ) -> Option<String> {
+        config
+            .indexes
+            .iter()
+            .find(|(_, index_config)| {
+                index_config.field == field && index_config.path.matches_children(path)
+            })
+            .map(|(index_name, _)| Self::datastore_get_index_table(table_prefix, index_name))
+    }
+
+    fn datastore_get_index_table(table_prefix: &str, index_name: &str) -> String {
+        format!(
+            "{}datastore_index{}{}",
+            table_prefix,
+            Self::TABLE_NAME_SEPARATOR,
+            Self::sanitize_table_name(index_name)
+        )
+    }
+
     fn datastore_get_table_prefix(config: &DatastoreDatabaseConfig) -> String {
         Self::get_table_prefix(
             config.namespace.as_deref(),
@@ -148,6 +933,44 @@ CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}data
         )
     }
 
+    fn datastore_value_meta_from_row(row: &Row) -> rusqlite::Result<DatastoreValueMeta> {
+        Ok(DatastoreValueMeta {
+            id: row.get(0)?,
+            change_id: row.get(1)?,
+            timestamp: row.get(2)?,
+        })
+    }
+
+    /// Converts a field path into an SQLite JSON path.
+    /// Quoted keys end at the first `"` even after a backslash, so quotes are written as unicode escapes.
+    fn datastore_json_path(field: &[String]) -> String {
+        let mut json_path = String::from("$");
+        for key in field {
+            let key = key.replace('\\', "\\\\").replace('"', "\\u0022");
+            json_path.push_str(&format!(".\"{}\"", key));
+        }
+        json_path
+    }
+
+    /// Converts a JSON value into the SQL value that `json_extract` would return for it
+    fn datastore_json_to_sql(value: &serde_json::Value) -> SqlValue {
+        match value {
+            serde_json::Value::Null => SqlValue::Null,
+            serde_json::Value::Bool(value) => SqlValue::Integer(*value as i64),
+            serde_json::Value::Number(value) => {
+                if let Some(value) = value.as_i64() {
+                    SqlValue::Integer(value)
+                } else {
+                    SqlValue::Real(value.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            serde_json::Value::String(value) => SqlValue::Text(value.clone()),
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                SqlValue::Text(value.to_string())
+            }
+        }
+    }
+
     fn datastore_key_get(
         &self,
         conn: &DBConnection,
@@ -157,7 +980,7 @@ CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}data
     ) -> Option<Option<i64>> {
         if !path.is_empty() {
             let mut select_stmt = conn
-                .prepare_cached(&format!("SELECT \"id\" FROM \"{0}datastore_tree\" WHERE \"parent_id\" = :parent_id AND \"key\" = :key;", table_prefix))
+                .prepare_cached(&format!("SELECT \"id\" FROM \"{0}datastore_tree\" WHERE \"parent_id\" IS :parent_id AND \"key\" = :key;", table_prefix))
                 .expect("Error occurred while preparing database query");
             let id_result: Option<i64> = select_stmt
                 .query_row(
@@ -186,7 +1009,7 @@ CREATE INDEX IF NOT EXISTS \"{0}index_datastore_values__timestamp\" ON \"{0}data
     ) -> Option<i64> {
         if !path.is_empty() {
             let mut select_stmt = conn
-                .prepare_cached(&format!("SELECT \"id\" FROM \"{0}datastore_tree\" WHERE \"parent_id\" = :parent_id AND \"key\" = :key;", table_prefix))
+                .prepare_cached(&format!("SELECT \"id\" FROM \"{0}datastore_tree\" WHERE \"parent_id\" IS :parent_id AND \"key\" = :key;", table_prefix))
                 .expect("Error occurred while preparing database query");
             let id_result: Option<i64> = select_stmt
                 .query_row(
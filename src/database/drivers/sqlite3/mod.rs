@@ -1,5 +1,6 @@
 //! SQLite3 database driver
 
+pub mod auth;
 pub mod datastore;
 
 use super::DbDriver;
@@ -20,7 +21,12 @@ impl DbDriver for SQLite3Connection {
             DatabaseConnectionConfig::SQLite3 { database } => {
                 let manager = SqliteConnectionManager::file(database)
                     .with_init(|c| c.execute_batch("PRAGMA busy_timeout = 60000; PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA foreign_keys = 1; PRAGMA auto_vacuum = INCREMENTAL; PRAGMA recursive_triggers = 1;"));
-                let pool = r2d2::Pool::new(manager).expect("Could not connect to SQLite3 database");
+                // each connection to an in-memory database gets its own database, so only allow one
+                let max_size = if database == ":memory:" { 1 } else { 10 };
+                let pool = r2d2::Pool::builder()
+                    .max_size(max_size)
+                    .build(manager)
+                    .expect("Could not connect to SQLite3 database");
                 Self { pool }
             }
         }
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+
+/// User account
+pub struct AuthUser {
+    pub id: i64,
+    pub username: String,
+    /// Argon2 hash of the password, None if the user can't log in with a password
+    pub password_hash: Option<String>,
+    /// Whether the user is allowed to log in
+    pub active: bool,
+}
+
+/// API key of a user
+pub struct AuthApiKey {
+    pub id: i64,
+    pub user_id: i64,
+    /// Name of the key, unique for the user
+    pub name: String,
+    /// Roles of the user the key grants, None for all of them
+    pub roles: Option<Vec<String>>,
+    /// Time the key stops working, None if it doesn't expire
+    pub expires: Option<DateTime<Utc>>,
+    /// Time the key was created
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Login session of a user
+pub struct AuthSession {
+    pub id: i64,
+    pub user_id: i64,
+    /// Time the session was created
+    pub timestamp: DateTime<Utc>,
+    /// Time the session was last used
+    pub last_seen: DateTime<Utc>,
+    /// Whether the user confirmed a second factor when logging in
+    pub second_factor: bool,
+}
+
+/// TOTP second factor of a user
+pub struct AuthTotp {
+    pub user_id: i64,
+    /// Hex encoded nonce and shared secret encrypted with the token key
+    pub secret: String,
+    /// Whether the user confirmed the secret with a code, unconfirmed secrets aren't required at login
+    pub confirmed: bool,
+    /// Last time step a code was accepted for
+    pub last_step: Option<i64>,
+    /// Number of wrong codes sent since the last correct one, across login challenges
+    pub failed_attempts: i64,
+    /// Time the last wrong code was sent
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+/// Unused recovery code of a user
+pub struct AuthRecoveryCode {
+    pub id: i64,
+    /// Hash of the code with the token key
+    pub code_hash: String,
+}
+
+/// Pending login of a user that still has to confirm a second factor
+pub struct AuthLoginChallenge {
+    pub id: i64,
+    pub user_id: i64,
+    /// Time the password was checked
+    pub timestamp: DateTime<Utc>,
+    /// Number of wrong codes sent for the challenge
+    pub attempts: i64,
+}
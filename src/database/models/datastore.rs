@@ -1,8 +1,82 @@
 use chrono::{DateTime, Utc};
+use serde::Serialize;
 use uuid::Uuid;
 
+/// Metadata of a datastore value entry
 pub struct DatastoreValueMeta {
-    id: u64,
-    change_id: Uuid,
-    timestamp: DateTime<Utc>,
+    pub id: u64,
+    pub change_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Value entry written to a datastore key
+pub struct DatastoreWrite {
+    /// Path of the key
+    pub path: Vec<String>,
+    /// Serialized value, None if the value is deleted
+    pub value: Option<String>,
+    pub change_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Metadata of the current value of a child key
+pub struct DatastoreChildValueMeta {
+    /// Key of the child
+    pub key: String,
+    /// Metadata of the current value of the child
+    pub value: DatastoreValueMeta,
+}
+
+/// Metadata of a value entry of any key, in the order the changes happened
+pub struct DatastoreChangeMeta {
+    /// Path of the key that changed
+    pub path: Vec<String>,
+    /// Metadata of the value entry
+    pub value: DatastoreValueMeta,
+}
+
+/// Filter on a field of the values being queried
+#[derive(Clone, Debug)]
+pub struct DatastoreQueryFilter {
+    /// Path of the field inside the value (empty for the value itself)
+    pub field: Vec<String>,
+    /// Comparison to apply to the field
+    pub operation: DatastoreQueryOperation,
+}
+
+/// Comparison used by a query filter
+#[derive(Clone, Debug)]
+pub enum DatastoreQueryOperation {
+    /// Field is equal to the value
+    Eq(serde_json::Value),
+    /// Field is less than the value
+    Lt(serde_json::Value),
+    /// Field is less than or equal to the value
+    Le(serde_json::Value),
+    /// Field is greater than the value
+    Gt(serde_json::Value),
+    /// Field is greater than or equal to the value
+    Ge(serde_json::Value),
+    /// Field is a string containing the value as a substring, or an array containing the value as an element
+    Contains(serde_json::Value),
+}
+
+/// Sort order of a query
+#[derive(Clone, Debug)]
+pub struct DatastoreQuerySort {
+    /// Path of the field inside the value to sort by (empty for the value itself)
+    pub field: Vec<String>,
+    /// Whether to sort in descending order
+    pub descending: bool,
+}
+
+/// Full-text search match
+#[derive(Clone, Debug, Serialize)]
+pub struct DatastoreSearchResult {
+    /// Path of the matching key
+    pub path: Vec<String>,
+    /// HTML excerpt of the matching text with the matched terms in `<mark>` elements, the text itself is escaped
+    pub snippet: String,
+    /// Relevance of the match (lower is more relevant)
+    pub rank: f64,
 }
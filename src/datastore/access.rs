@@ -0,0 +1,93 @@
+//! Path-level access rules for datastores
+
+use std::collections::HashMap;
+
+use super::patterns::{expand_template, PathPattern};
+use crate::{
+    auth::AuthIdentity,
+    config::{DataAccessCondition, DataAccessRuleConfig},
+};
+
+/// Access rules for the paths of a datastore.
+/// The first matching rule with a condition for the access type decides.
+/// If no rule decides, access is only limited by the route permissions.
+#[derive(Clone, Debug, Default)]
+pub struct DataAccessRules {
+    rules: Vec<DataAccessRule>,
+}
+
+/// Access rule for a path pattern
+#[derive(Clone, Debug)]
+struct DataAccessRule {
+    /// Path pattern
+    pattern: PathPattern,
+    /// Read condition
+    read: Option<DataAccessCondition>,
+    /// Write condition
+    write: Option<DataAccessCondition>,
+}
+
+impl DataAccessRules {
+    /// Parses the rules from the route configuration
+    pub fn new(config: &[DataAccessRuleConfig]) -> Self {
+        Self {
+            rules: config
+                .iter()
+                .map(|rule| DataAccessRule {
+                    pattern: rule.path.clone(),
+                    read: rule.read.clone(),
+                    write: rule.write.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Checks if the identity can read the value of a path
+    pub fn can_read(&self, path: &[&str], identity: &AuthIdentity) -> bool {
+        self.check(path, identity, |rule| rule.read.as_ref())
+    }
+
+    /// Checks if the identity can change the value of a path
+    pub fn can_write(&self, path: &[&str], identity: &AuthIdentity) -> bool {
+        self.check(path, identity, |rule| rule.write.as_ref())
+    }
+
+    /// Evaluates the condition of the first matching rule that has one
+    fn check(
+        &self,
+        path: &[&str],
+        identity: &AuthIdentity,
+        condition: impl Fn(&DataAccessRule) -> Option<&DataAccessCondition>,
+    ) -> bool {
+        for rule in &self.rules {
+            let Some(condition) = condition(rule) else {
+                continue;
+            };
+            if let Some(captures) = rule.pattern.captures(path) {
+                return allows(condition, &captures, identity);
+            }
+        }
+        true
+    }
+}
+
+/// Evaluates a condition for an identity
+fn allows(
+    condition: &DataAccessCondition,
+    captures: &HashMap<String, &str>,
+    identity: &AuthIdentity,
+) -> bool {
+    match condition {
+        DataAccessCondition::Global(allowed) => *allowed,
+        DataAccessCondition::Roles(roles) => roles.iter().any(|role| {
+            let role = expand_template(role, captures);
+            identity.roles.contains(&role)
+        }),
+        DataAccessCondition::User { user } => {
+            identity.username.as_deref() == Some(expand_template(user, captures).as_str())
+        }
+        DataAccessCondition::Any { any } => any
+            .iter()
+            .any(|condition| allows(condition, captures, identity)),
+    }
+}
@@ -0,0 +1,67 @@
+//! Binary values stored by content hash
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Key of the blob reference inside a value referencing a blob
+pub const DATASTORE_BLOB_KEY: &str = "$blob";
+
+/// Reference to a blob stored outside of the value.
+/// Values referencing a blob are stored as `{"$blob": reference}`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BlobReference {
+    /// Hex-encoded SHA-256 hash of the content
+    pub hash: String,
+    /// MIME type of the content
+    pub content_type: String,
+    /// Size of the content in bytes
+    pub size: u64,
+}
+
+/// Value referencing a blob
+#[derive(Serialize, Deserialize)]
+struct BlobValue {
+    #[serde(rename = "$blob")]
+    blob: BlobReference,
+}
+
+/// Checks if a value contains the blob reference key anywhere.
+/// Only blob writes may store references, so blobs can't be read through paths they weren't written to.
+pub fn contains_blob_key(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.contains_key(DATASTORE_BLOB_KEY) || map.values().any(contains_blob_key)
+        }
+        serde_json::Value::Array(items) => items.iter().any(contains_blob_key),
+        _ => false,
+    }
+}
+
+impl BlobReference {
+    /// Creates the reference to some content
+    pub fn new(content_type: &str, data: &[u8]) -> Self {
+        Self {
+            hash: format!("{:x}", Sha256::digest(data)),
+            content_type: String::from(content_type),
+            size: data.len() as u64,
+        }
+    }
+
+    /// Gets the blob reference of a value, None if the value doesn't reference a blob
+    pub fn from_value(value: serde_json::Value) -> Option<Self> {
+        serde_json::from_value::<BlobValue>(value)
+            .ok()
+            .map(|value| value.blob)
+    }
+
+    /// Gets the blob reference of a value of the data store
+    pub fn from_stored<T: Serialize>(value: &T) -> Option<Self> {
+        Self::from_value(serde_json::to_value(value).ok()?)
+    }
+
+    /// Converts the reference into the value stored in the data store
+    pub fn to_value(&self) -> serde_json::Value {
+        serde_json::to_value(BlobValue { blob: self.clone() })
+            .expect("Error occurred while serializing blob reference")
+    }
+}
@@ -0,0 +1,119 @@
+//! Conflict-free replicated data types for collaboratively edited values
+
+use serde::{Deserialize, Serialize};
+
+/// Identifier of a character in a collaboratively edited text.
+/// The counter must be greater than the counters of all characters the replica has seen.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct TextElementId {
+    /// Lamport timestamp of the insertion
+    pub counter: u64,
+    /// Id of the replica that inserted the character, unique per client
+    pub replica: String,
+}
+
+/// Operation on a collaboratively edited text
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextOperation {
+    /// Inserts a character after another character (or at the start if there is none)
+    Insert {
+        id: TextElementId,
+        after: Option<TextElementId>,
+        character: char,
+    },
+    /// Deletes a character
+    Delete { id: TextElementId },
+}
+
+/// Collaboratively edited text using a replicated growable array (RGA).
+/// Operations from different replicas can be applied in any causal order and result in the same text.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct CrdtText {
+    /// Characters in text order, including deleted characters
+    elements: Vec<TextElement>,
+    /// Operations applied by the last change
+    #[serde(default)]
+    pub operations: Vec<TextOperation>,
+}
+
+/// Character of a collaboratively edited text
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct TextElement {
+    id: TextElementId,
+    character: char,
+    #[serde(default)]
+    deleted: bool,
+}
+
+impl CrdtText {
+    /// Gets the current text
+    pub fn text(&self) -> String {
+        self.elements
+            .iter()
+            .filter(|element| !element.deleted)
+            .map(|element| element.character)
+            .collect()
+    }
+
+    /// Gets the largest counter used by any character, which replicas must exceed when inserting
+    pub fn max_counter(&self) -> u64 {
+        self.elements
+            .iter()
+            .map(|element| element.id.counter)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Applies an operation.
+    /// Applying an operation that was already applied has no effect.
+    /// Returns false if the operation refers to a character that doesn't exist.
+    pub fn apply(&mut self, operation: &TextOperation) -> bool {
+        match operation {
+            TextOperation::Insert {
+                id,
+                after,
+                character,
+            } => {
+                if self.position(id).is_some() {
+                    return true;
+                }
+
+                let mut index = match after {
+                    Some(after) => match self.position(after) {
+                        Some(position) => position + 1,
+                        None => return false,
+                    },
+                    None => 0,
+                };
+
+                // concurrent insertions after the same character are ordered by descending id
+                while index < self.elements.len() && self.elements[index].id > *id {
+                    index += 1;
+                }
+
+                self.elements.insert(
+                    index,
+                    TextElement {
+                        id: id.clone(),
+                        character: *character,
+                        deleted: false,
+                    },
+                );
+                true
+            }
+            TextOperation::Delete { id } => match self.position(id) {
+                Some(position) => {
+                    self.elements[position].deleted = true;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Finds the index of a character
+    fn position(&self, id: &TextElementId) -> Option<usize> {
+        self.elements.iter().position(|element| element.id == *id)
+    }
+}
@@ -0,0 +1,26 @@
+//! Hooks enforcing rules on data store writes
+
+use super::Value;
+
+/// Callbacks run on the data store thread around every set and delete.
+/// Writes made by triggers to derived keys don't run hooks.
+/// Hooks run in the order they were registered with `DataStore::new_with_hooks`.
+pub trait DataStoreHook<T>: Send {
+    /// Called before a value is set.
+    /// Returns the value to set, or the reason the write is rejected.
+    fn before_set(&mut self, _path: &[String], value: T) -> Result<T, String> {
+        Ok(value)
+    }
+
+    /// Called after a value was set
+    fn after_set(&mut self, _value: &Value<T>) {}
+
+    /// Called before a value is deleted.
+    /// Returns the reason the delete is rejected if it isn't allowed.
+    fn before_delete(&mut self, _path: &[String]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called after a value was deleted
+    fn after_delete(&mut self, _value: &Value<T>) {}
+}
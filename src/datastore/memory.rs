@@ -0,0 +1,606 @@
+//! In-memory storage for data stores that aren't persisted to a database
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+};
+
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{blobs::BlobReference, Value};
+use crate::{
+    config::DatastoreConfig,
+    database::models::datastore::{
+        DatastoreQueryFilter, DatastoreQueryOperation, DatastoreQuerySort, DatastoreSearchResult,
+    },
+    helpers::html::escape_html,
+};
+
+/// Number of tokens in a search result snippet
+const SNIPPET_TOKENS: usize = 16;
+/// BM25 term frequency saturation used for search ranking
+const BM25_K1: f64 = 1.2;
+/// BM25 document length normalization used for search ranking
+const BM25_B: f64 = 0.75;
+
+/// Tree of values kept in memory.
+/// Behaves like the database storage, including history and full-text search.
+pub(super) struct MemoryStorage<T> {
+    /// Root key of the tree
+    root: MemoryNode<T>,
+    /// Binary content by hash
+    blobs: HashMap<String, Vec<u8>>,
+    /// Id of the last stored value entry, used to order changes
+    last_id: u64,
+}
+
+/// Key in the tree
+struct MemoryNode<T> {
+    /// Subkeys ordered by key
+    children: BTreeMap<String, MemoryNode<T>>,
+    /// Value entries with their ids, from oldest to current
+    history: VecDeque<(u64, Arc<Value<T>>)>,
+}
+
+/// Text of a value being searched
+struct SearchDocument<'a, T> {
+    /// Value the text is from
+    value: &'a Arc<Value<T>>,
+    /// Strings in the value
+    text: String,
+    /// Lowercase tokens of the text
+    tokens: Vec<String>,
+}
+
+impl<T> Default for MemoryNode<T> {
+    fn default() -> Self {
+        Self {
+            children: BTreeMap::new(),
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> MemoryNode<T> {
+    /// Gets the current value, None if it was never set or the history was cleared
+    fn current(&self) -> Option<&Arc<Value<T>>> {
+        self.history.back().map(|(_, value)| value)
+    }
+
+    /// Checks if the key or any of its subkeys has a value set
+    fn has_value(&self) -> bool {
+        self.current().is_some_and(|value| value.value.is_some())
+            || self.children.values().any(|child| child.has_value())
+    }
+
+    /// Collects the current values of the key and its subkeys
+    fn collect_current<'a>(&'a self, values: &mut Vec<&'a Arc<Value<T>>>) {
+        values.extend(self.current().filter(|value| value.value.is_some()));
+        for child in self.children.values() {
+            child.collect_current(values);
+        }
+    }
+
+    /// Checks if any value entry of the key or its subkeys references a blob
+    fn references_blob(&self, hash: &str) -> bool
+    where
+        T: Serialize,
+    {
+        self.history.iter().any(|(_, value)| {
+            value
+                .value
+                .as_ref()
+                .and_then(BlobReference::from_stored)
+                .is_some_and(|reference| reference.hash == hash)
+        }) || self
+            .children
+            .values()
+            .any(|child| child.references_blob(hash))
+    }
+
+    /// Collects the value entries of the key and its subkeys made after a cursor
+    fn collect_changes(&self, cursor: u64, changes: &mut Vec<(u64, Arc<Value<T>>)>) {
+        changes.extend(
+            self.history
+                .iter()
+                .filter(|(id, _)| *id > cursor)
+                .map(|(id, value)| (*id, Arc::clone(value))),
+        );
+        for child in self.children.values() {
+            child.collect_changes(cursor, changes);
+        }
+    }
+}
+
+impl<T: Serialize> MemoryStorage<T> {
+    pub fn new() -> Self {
+        Self {
+            root: MemoryNode::default(),
+            blobs: HashMap::new(),
+            last_id: 0,
+        }
+    }
+
+    /// Gets the current value of a key
+    pub fn get_current(&self, path: &[String]) -> Option<Arc<Value<T>>> {
+        self.node(path)?.current().cloned()
+    }
+
+    /// Gets the current and previous values of a key.
+    /// If the last change id is set and in the history, only the values after it are returned.
+    pub fn get_history(&self, path: &[String], last_change_id: Option<Uuid>) -> Vec<Arc<Value<T>>> {
+        let Some(node) = self.node(path) else {
+            return Vec::new();
+        };
+
+        let skip = last_change_id
+            .and_then(|last_change_id| {
+                node.history
+                    .iter()
+                    .position(|(_, value)| value.change_id == last_change_id)
+            })
+            .map_or(0, |position| position + 1);
+        node.history
+            .iter()
+            .skip(skip)
+            .map(|(_, value)| Arc::clone(value))
+            .collect()
+    }
+
+    /// Lists the subkeys that have values set or have subkeys with values set
+    pub fn list(&self, path: &[String]) -> Vec<String> {
+        match self.node(path) {
+            Some(node) => node
+                .children
+                .iter()
+                .filter(|(_, child)| child.has_value())
+                .map(|(key, _)| key.clone())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Filters, sorts and paginates the current values of the subkeys of a key
+    pub fn query(
+        &self,
+        path: &[String],
+        filters: &[DatastoreQueryFilter],
+        sort: Option<&DatastoreQuerySort>,
+        limit: Option<u64>,
+        offset: u64,
+    ) -> Vec<Arc<Value<T>>> {
+        let Some(node) = self.node(path) else {
+            return Vec::new();
+        };
+
+        // values are compared as JSON
+        let mut matches: Vec<(&String, &Arc<Value<T>>, serde_json::Value)> = node
+            .children
+            .iter()
+            .filter_map(|(key, child)| {
+                let value = child.current()?;
+                let json = serde_json::to_value(value.value.as_ref()?).ok()?;
+                Some((key, value, json))
+            })
+            .filter(|(_, _, json)| filters.iter().all(|filter| filter_matches(filter, json)))
+            .collect();
+
+        // always sort by key last so that pages are stable
+        if let Some(sort) = sort {
+            matches.sort_by(|(a_key, _, a), (b_key, _, b)| {
+                let order = compare_for_sort(get_field(a, &sort.field), get_field(b, &sort.field));
+                let order = if sort.descending {
+                    order.reverse()
+                } else {
+                    order
+                };
+                order.then_with(|| a_key.cmp(b_key))
+            });
+        }
+
+        matches
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.map_or(usize::MAX, |x| x as usize))
+            .map(|(_, value, _)| Arc::clone(value))
+            .collect()
+    }
+
+    /// Gets the value entries of a key and its subkeys made after a cursor, with their cursors.
+    /// The entries are in the order the changes happened.
+    pub fn changes_since(
+        &self,
+        path: &[String],
+        cursor: u64,
+        limit: Option<u64>,
+    ) -> Vec<(u64, Arc<Value<T>>)> {
+        let Some(node) = self.node(path) else {
+            return Vec::new();
+        };
+
+        let mut changes = Vec::new();
+        node.collect_changes(cursor, &mut changes);
+        changes.sort_by_key(|(id, _)| *id);
+        changes.truncate(limit.map_or(usize::MAX, |x| x as usize));
+        changes
+    }
+
+    /// Searches the text of the current values of a key and its subkeys.
+    /// Results are ordered from most to least relevant.
+    pub fn search(
+        &self,
+        config: &DatastoreConfig,
+        path: &[String],
+        query: &str,
+    ) -> Vec<DatastoreSearchResult> {
+        if !config.full_text_search {
+            return Vec::new();
+        }
+        let Some(node) = self.node(path) else {
+            return Vec::new();
+        };
+
+        // each word of the query is matched as a literal term, with a trailing "*" matching words with that prefix
+        let terms: Vec<(Vec<String>, bool)> = query
+            .split_whitespace()
+            .filter_map(|word| {
+                let (word, prefix) = match word.strip_suffix('*') {
+                    Some(word) => (word, true),
+                    None => (word, false),
+                };
+                let tokens: Vec<String> = tokenize(word).into_iter().map(|x| x.1).collect();
+                if tokens.is_empty() {
+                    None
+                } else {
+                    Some((tokens, prefix))
+                }
+            })
+            .collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let mut values = Vec::new();
+        node.collect_current(&mut values);
+        let documents: Vec<SearchDocument<T>> = values
+            .into_iter()
+            .filter_map(|value| {
+                let json = serde_json::to_value(value.value.as_ref()?).ok()?;
+                let mut strings = Vec::new();
+                collect_strings(&json, &mut strings);
+                let text = strings.join(" ");
+                let tokens = tokenize(&text)
+                    .into_iter()
+                    .map(|(_, token)| token)
+                    .collect();
+                Some(SearchDocument {
+                    value,
+                    text,
+                    tokens,
+                })
+            })
+            .collect();
+
+        // positions of each term in each document
+        let positions: Vec<Vec<Vec<usize>>> = documents
+            .iter()
+            .map(|document| {
+                terms
+                    .iter()
+                    .map(|(term, prefix)| term_positions(&document.tokens, term, *prefix))
+                    .collect()
+            })
+            .collect();
+
+        // relevance is scored with BM25 like the database search index
+        let total = documents.len() as f64;
+        let average_length =
+            documents.iter().map(|x| x.tokens.len()).sum::<usize>() as f64 / total.max(1.0);
+        let idfs: Vec<f64> = (0..terms.len())
+            .map(|i| {
+                let containing = positions.iter().filter(|x| !x[i].is_empty()).count() as f64;
+                ((total - containing + 0.5) / (containing + 0.5))
+                    .ln()
+                    .max(1e-6)
+            })
+            .collect();
+
+        let mut results: Vec<DatastoreSearchResult> = documents
+            .iter()
+            .zip(&positions)
+            .filter(|(_, positions)| positions.iter().all(|x| !x.is_empty()))
+            .map(|(document, positions)| {
+                let length = document.tokens.len() as f64 / average_length.max(1.0);
+                let mut matched = vec![false; document.tokens.len()];
+                let mut score = 0.0;
+                for ((term, _), (positions, idf)) in terms.iter().zip(positions.iter().zip(&idfs)) {
+                    let frequency = positions.len() as f64;
+                    score += idf * frequency * (BM25_K1 + 1.0)
+                        / (frequency + BM25_K1 * (1.0 - BM25_B + BM25_B * length));
+                    for position in positions {
+                        matched[*position..*position + term.len()].fill(true);
+                    }
+                }
+
+                DatastoreSearchResult {
+                    path: document.value.path.clone(),
+                    snippet: snippet(&document.text, &matched),
+                    rank: -score,
+                }
+            })
+            .collect();
+
+        results.sort_by(|a, b| a.rank.partial_cmp(&b.rank).unwrap_or(Ordering::Equal));
+        results
+    }
+
+    /// Stores value entries in order, removing the entries the history settings don't keep
+    pub fn set_batch(&mut self, config: &DatastoreConfig, values: &[Arc<Value<T>>]) {
+        // blobs referenced by the removed entries are deleted if nothing else references them
+        let mut removed_blobs = Vec::new();
+        for value in values {
+            self.last_id += 1;
+            let id = self.last_id;
+
+            let node = value.path.iter().fold(&mut self.root, |node, key| {
+                node.children.entry(key.clone()).or_default()
+            });
+            node.history.push_back((id, Arc::clone(value)));
+
+            // the current entry is always kept
+            let previous = node.history.len() - 1;
+            let keep = if config.keep_history {
+                let mut keep = previous;
+                if let Some(max_entries) = config.history_max_entries {
+                    keep = keep.min(max_entries as usize);
+                }
+                if let Some(cutoff) = config.history_max_age.and_then(|max_age| {
+                    let max_age = ChronoDuration::from_std(std::time::Duration::from_secs(max_age))
+                        .unwrap_or(ChronoDuration::MAX);
+                    Utc::now().checked_sub_signed(max_age)
+                }) {
+                    let recent = node
+                        .history
+                        .iter()
+                        .take(previous)
+                        .filter(|(_, value)| value.timestamp >= cutoff)
+                        .count();
+                    keep = keep.min(recent);
+                }
+                keep
+            } else {
+                0
+            };
+            removed_blobs.extend(
+                node.history
+                    .drain(..previous - keep)
+                    .filter_map(|(_, value)| {
+                        value
+                            .value
+                            .as_ref()
+                            .and_then(BlobReference::from_stored)
+                            .map(|reference| reference.hash)
+                    }),
+            );
+        }
+
+        for hash in removed_blobs {
+            self.delete_unused_blob(&hash);
+        }
+    }
+
+    /// Gets binary content by its hash
+    pub fn get_blob(&self, hash: &str) -> Option<Vec<u8>> {
+        self.blobs.get(hash).cloned()
+    }
+
+    /// Stores binary content by its hash
+    pub fn put_blob(&mut self, hash: &str, data: &[u8]) {
+        self.blobs
+            .entry(String::from(hash))
+            .or_insert_with(|| data.to_vec());
+    }
+
+    /// Deletes binary content if no value, including history entries, references it anymore
+    fn delete_unused_blob(&mut self, hash: &str) {
+        if !self.root.references_blob(hash) {
+            self.blobs.remove(hash);
+        }
+    }
+
+    /// Gets the node of a key
+    fn node(&self, path: &[String]) -> Option<&MemoryNode<T>> {
+        path.iter()
+            .try_fold(&self.root, |node, key| node.children.get(key))
+    }
+}
+
+/// Gets a field inside a value, None if it doesn't exist
+fn get_field<'a>(value: &'a serde_json::Value, field: &[String]) -> Option<&'a serde_json::Value> {
+    field.iter().try_fold(value, |value, key| value.get(key))
+}
+
+/// Checks if a value matches a query filter
+fn filter_matches(filter: &DatastoreQueryFilter, value: &serde_json::Value) -> bool {
+    let field = get_field(value, &filter.field);
+    match &filter.operation {
+        DatastoreQueryOperation::Eq(serde_json::Value::Null) => {
+            field == Some(&serde_json::Value::Null)
+        }
+        DatastoreQueryOperation::Eq(value) => compare(field, value) == Some(Ordering::Equal),
+        DatastoreQueryOperation::Lt(value) => compare(field, value) == Some(Ordering::Less),
+        DatastoreQueryOperation::Le(value) => {
+            matches!(
+                compare(field, value),
+                Some(Ordering::Less | Ordering::Equal)
+            )
+        }
+        DatastoreQueryOperation::Gt(value) => compare(field, value) == Some(Ordering::Greater),
+        DatastoreQueryOperation::Ge(value) => matches!(
+            compare(field, value),
+            Some(Ordering::Greater | Ordering::Equal)
+        ),
+        DatastoreQueryOperation::Contains(value) => match field {
+            Some(serde_json::Value::Array(items)) => items
+                .iter()
+                .any(|item| compare(Some(item), value) == Some(Ordering::Equal)),
+            Some(serde_json::Value::String(text)) => match scalar(value) {
+                Scalar::Text(value) => text.contains(value.as_str()),
+                Scalar::Integer(_) | Scalar::Real(_) => text.contains(&value.to_string()),
+                Scalar::Null => false,
+            },
+            _ => false,
+        },
+    }
+}
+
+/// Value as compared by the database, where booleans are integers and arrays and objects are JSON text
+enum Scalar {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+/// Converts a JSON value into the scalar the database compares
+fn scalar(value: &serde_json::Value) -> Scalar {
+    match value {
+        serde_json::Value::Null => Scalar::Null,
+        serde_json::Value::Bool(value) => Scalar::Integer(*value as i64),
+        serde_json::Value::Number(value) => match value.as_i64() {
+            Some(value) => Scalar::Integer(value),
+            None => Scalar::Real(value.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(value) => Scalar::Text(value.clone()),
+        serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+            Scalar::Text(value.to_string())
+        }
+    }
+}
+
+/// Compares a field to a value like the database does.
+/// Returns None if either is null or missing, since such comparisons never match.
+fn compare(field: Option<&serde_json::Value>, value: &serde_json::Value) -> Option<Ordering> {
+    match (scalar(field?), scalar(value)) {
+        (Scalar::Null, _) | (_, Scalar::Null) => None,
+        (a, b) => Some(compare_scalars(&a, &b)),
+    }
+}
+
+/// Orders fields for sorting like the database does, with missing and null fields first
+fn compare_for_sort(a: Option<&serde_json::Value>, b: Option<&serde_json::Value>) -> Ordering {
+    let a = a.map_or(Scalar::Null, scalar);
+    let b = b.map_or(Scalar::Null, scalar);
+    compare_scalars(&a, &b)
+}
+
+/// Orders scalars, with null before numbers before text
+fn compare_scalars(a: &Scalar, b: &Scalar) -> Ordering {
+    let number = |x: &Scalar| match x {
+        Scalar::Integer(x) => Some(*x as f64),
+        Scalar::Real(x) => Some(*x),
+        _ => None,
+    };
+    match (a, b) {
+        (Scalar::Null, Scalar::Null) => Ordering::Equal,
+        (Scalar::Null, _) => Ordering::Less,
+        (_, Scalar::Null) => Ordering::Greater,
+        (Scalar::Integer(a), Scalar::Integer(b)) => a.cmp(b),
+        (Scalar::Text(a), Scalar::Text(b)) => a.cmp(b),
+        (Scalar::Text(_), _) => Ordering::Greater,
+        (_, Scalar::Text(_)) => Ordering::Less,
+        _ => number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal),
+    }
+}
+
+/// Collects the strings inside a value, which is the text that is searched
+fn collect_strings<'a>(value: &'a serde_json::Value, strings: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::String(value) => strings.push(value),
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_strings(item, strings);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for field in fields.values() {
+                collect_strings(field, strings);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Splits text into lowercase alphanumeric tokens with their byte spans
+fn tokenize(text: &str) -> Vec<((usize, usize), String)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, character) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, character.is_alphanumeric()) {
+            (None, true) => start = Some(index),
+            (Some(token_start), false) => {
+                tokens.push((
+                    (token_start, index),
+                    text[token_start..index].to_lowercase(),
+                ));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// Finds the token positions where a term starts.
+/// The tokens of a term must follow each other, and the last one may be a prefix.
+fn term_positions(tokens: &[String], term: &[String], prefix: bool) -> Vec<usize> {
+    if tokens.len() < term.len() {
+        return Vec::new();
+    }
+    (0..=tokens.len() - term.len())
+        .filter(|start| {
+            term.iter().enumerate().all(|(i, token)| {
+                if prefix && i == term.len() - 1 {
+                    tokens[start + i].starts_with(token.as_str())
+                } else {
+                    tokens[start + i] == *token
+                }
+            })
+        })
+        .collect()
+}
+
+/// Creates an HTML excerpt of the text around the first match with the matched tokens highlighted.
+/// The text is escaped, so only the highlighting is markup.
+fn snippet(text: &str, matched: &[bool]) -> String {
+    let spans: Vec<(usize, usize)> = tokenize(text).into_iter().map(|(span, _)| span).collect();
+    let first_match = matched.iter().position(|x| *x).unwrap_or(0);
+    let start = first_match.min(spans.len().saturating_sub(SNIPPET_TOKENS));
+    let end = (start + SNIPPET_TOKENS).min(spans.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str("...");
+    }
+    let mut position = spans.get(start).map_or(0, |span| span.0);
+    for (index, span) in spans.iter().enumerate().take(end).skip(start) {
+        snippet.push_str(&escape_html(&text[position..span.0]));
+        if matched[index] {
+            snippet.push_str("<mark>");
+            snippet.push_str(&escape_html(&text[span.0..span.1]));
+            snippet.push_str("</mark>");
+        } else {
+            snippet.push_str(&escape_html(&text[span.0..span.1]));
+        }
+        position = span.1;
+    }
+    if end < spans.len() {
+        snippet.push_str("...");
+    } else {
+        snippet.push_str(&escape_html(&text[position..]));
+    }
+    snippet
+}
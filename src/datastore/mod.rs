@@ -1,25 +1,48 @@
 //! History-Tracking Change-Subscribable Tree-Based Key-Value Data Store
 
+pub mod access;
+pub mod blobs;
+pub mod crdt;
+pub mod hooks;
+mod memory;
+pub mod patterns;
+mod triggers;
+
 use std::{
-    collections::HashMap,
-    future::Future,
-    rc::{Rc, Weak},
+    collections::{HashMap, HashSet},
+    fmt,
+    rc::Rc,
     sync::{
-        mpsc::{self, RecvTimeoutError},
-        Arc, Mutex, Weak as AWeak,
+        mpsc::{self, RecvTimeoutError, TrySendError},
+        Arc, Mutex,
     },
     thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
 use chrono::{DateTime, Utc};
-use serde::{de::DeserializeOwned, Serialize};
-use tokio::sync::{mpsc as mpsc_async, oneshot as oneshot_async};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    sync::{mpsc as mpsc_async, oneshot as oneshot_async},
+};
 use uuid::Uuid;
 
+use self::{
+    blobs::{contains_blob_key, BlobReference, DATASTORE_BLOB_KEY},
+    crdt::{CrdtText, TextOperation},
+    hooks::DataStoreHook,
+    memory::MemoryStorage,
+};
 use crate::{
     config::DatastoreConfig,
-    database::DbSchema,
+    database::{
+        models::datastore::{
+            DatastoreQueryFilter, DatastoreQuerySort, DatastoreSearchResult, DatastoreValueMeta,
+            DatastoreWrite,
+        },
+        DbSchema,
+    },
     helpers::{
         sync_async::{MPSCSender, OneshotSender},
         tlru_cache::TLRUCache,
@@ -28,14 +51,16 @@ use crate::{
 
 const ITEM_CACHE_MAX_ITEMS: usize = 1000;
 const ITEM_CACHE_MAX_ACCESS_AGE: Duration = Duration::from_secs(3600);
+/// Number of imported records sent to the data store thread at once
+const IMPORT_CHUNK_RECORDS: usize = 1000;
+/// Time after which putting back an expired claim is tried again if a hook rejected it
+const CLAIM_RELEASE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 /// Data store object
 #[derive(Clone)]
 pub struct DataStore<T> {
-    name: String,
     config: DatastoreConfig,
-    database: DbSchema,
-    mpsc_channel_sender: mpsc::Sender<DataStoreRequest<T>>,
+    mpsc_channel_sender: mpsc::SyncSender<DataStoreRequest<T>>,
     join_handle: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
@@ -43,28 +68,43 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
     /// Sets up a new data store.
     /// Once the data store is done being used, the `shutdown` function must be called.
     pub async fn new(name: &str, config: DatastoreConfig, database: Option<DbSchema>) -> Self {
-        // we require a database for organizing the data
-        // create an in-memory one if none is set
-        let database = if let Some(database) = database {
-            database
-        } else {
-            DbSchema::new_memory()
-        };
+        Self::new_with_hooks(name, config, database, Vec::new()).await
+    }
 
-        // create database schema
-        database.datastore_create(name, &config);
+    /// Sets up a new data store that runs the hooks around every write.
+    /// Once the data store is done being used, the `shutdown` function must be called.
+    pub async fn new_with_hooks(
+        name: &str,
+        config: DatastoreConfig,
+        database: Option<DbSchema>,
+        hooks: Vec<Box<dyn DataStoreHook<T>>>,
+    ) -> Self {
+        // values are only kept in memory if no database is set
+        let storage = match &database {
+            Some(database) => {
+                // create database schema
+                database.datastore_create(name, &config);
+                DataStoreStorage::Database(database.clone())
+            }
+            None => DataStoreStorage::Memory(MemoryStorage::new()),
+        };
 
         // oneshot used to get the request channel from the spawned thread
         let (spawn_tx, spawn_rx) = oneshot_async::channel();
 
+        // copies of the datastore settings used by the thread
+        let thread_name = String::from(name);
+        let thread_config = config.clone();
+        let queue_depth = config.queue_depth;
+
         // customize thread name to datastore name
         let thread_builder = thread::Builder::new().name(String::from(name));
 
         // spawn datastore thread
         let join_handle = thread_builder
             .spawn(move || {
-                // create MPSC channel that requests will be sent with
-                let (tx, rx) = mpsc::channel();
+                // create bounded MPSC channel that requests will be sent with
+                let (tx, rx) = mpsc::sync_channel(queue_depth);
                 // send the request channel back to the parent thread
                 spawn_tx
                     .send(tx)
@@ -73,28 +113,38 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                 // will contain the response channel for shutdown request
                 let mut shutdown_response: Option<OneshotSender<()>> = None;
 
-                // mapping of subscription ids to subscriptions
-                let subscriptions_by_id: HashMap<Uuid, Rc<SubscriptionRecord<T>>> = HashMap::new();
-                // mapping of subscription paths to subscriptions
-                let subscriptions_by_path: HashMap<Vec<String>, Rc<SubscriptionRecord<T>>> =
-                    HashMap::new();
-
-                // TLRU cache of deserialized items to allow more efficient handling of large values
-                let value_cache_by_change_id: TLRUCache<Uuid, Arc<Value<T>>> = TLRUCache::new(
-                    Some(ITEM_CACHE_MAX_ITEMS),
-                    None,
-                    Some(ITEM_CACHE_MAX_ACCESS_AGE),
-                );
+                // state owned by the thread
+                let mut state = DataStoreThread::new(thread_name, thread_config, storage, hooks);
 
                 // thread loop
                 loop {
                     // run maintenance tasks before loop
+                    state.value_cache_by_change_id.gc(false, None);
+                    state.release_expired_claims();
 
                     // contains the timeout to allow tasks to run occasionally
-                    let recv_timeout = Duration::from_millis(1000);
+                    // wakes up earlier if a claim expires or the write batch must be committed before then
+                    let recv_timeout = state
+                        .next_claim_expiry()
+                        .into_iter()
+                        .chain(state.batch_deadline())
+                        .min()
+                        .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+                        .unwrap_or(Duration::MAX)
+                        .min(Duration::from_millis(1000));
 
                     // wait for request
-                    match rx.recv_timeout(recv_timeout) {
+                    let request = rx.recv_timeout(recv_timeout);
+
+                    // sets and deletes join the write batch, everything else sees the committed writes
+                    if !matches!(
+                        request,
+                        Ok(DataStoreRequest::Set { .. } | DataStoreRequest::Delete { .. })
+                    ) {
+                        state.commit_writes();
+                    }
+
+                    match request {
                         Ok(request) => match request {
                             DataStoreRequest::Get {
                                 path,
@@ -102,7 +152,8 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                                 response_channel,
                             } => {
                                 // get values after last change id in chronological order
-                                todo!()
+                                let values = state.get_history(&path, last_change_id);
+                                response_channel.send(values).ok();
                             }
 
                             DataStoreRequest::GetCurrent {
@@ -110,7 +161,11 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                                 response_channel,
                             } => {
                                 // get latest value
-                                todo!()
+                                let value = match state.get_current(&path) {
+                                    Some(value) => value,
+                                    None => Arc::new(Value::unset(path)),
+                                };
+                                response_channel.send(value).ok();
                             }
 
                             DataStoreRequest::List {
@@ -118,7 +173,78 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                                 response_channel,
                             } => {
                                 // list subkeys that have values set or have subkeys with values set
-                                todo!()
+                                let keys = state.list(&path);
+                                response_channel.send(keys).ok();
+                            }
+
+                            DataStoreRequest::Query {
+                                path,
+                                filters,
+                                sort,
+                                limit,
+                                cursor,
+                                response_channel,
+                            } => {
+                                // filter, sort and paginate the current values of the subkeys
+                                let offset = cursor.unwrap_or(0);
+                                let values =
+                                    state.query(&path, &filters, sort.as_ref(), limit, offset);
+
+                                // there may be more results if the page is full
+                                let next_cursor = match limit {
+                                    Some(limit) if values.len() as u64 == limit && limit > 0 => {
+                                        Some(offset + limit)
+                                    }
+                                    _ => None,
+                                };
+
+                                response_channel
+                                    .send(QueryResult {
+                                        values,
+                                        next_cursor,
+                                    })
+                                    .ok();
+                            }
+
+                            DataStoreRequest::ChangesSince {
+                                path,
+                                cursor,
+                                limit,
+                                response_channel,
+                            } => {
+                                // get the changes of the key and its subkeys in the order they happened
+                                let result = state.changes_since(&path, cursor, limit);
+                                response_channel.send(result).ok();
+                            }
+
+                            DataStoreRequest::Export {
+                                path,
+                                include_history,
+                                response_channel,
+                            } => {
+                                // get the value entries of the key and its subkeys
+                                let values = state.export(&path, include_history);
+                                response_channel.send(values).ok();
+                            }
+
+                            DataStoreRequest::Import {
+                                values,
+                                response_channel,
+                            } => {
+                                // store the value entries as they are
+                                let imported = state.import(values);
+                                state.commit_writes();
+                                response_channel.send(imported).ok();
+                            }
+
+                            DataStoreRequest::Search {
+                                path,
+                                query,
+                                response_channel,
+                            } => {
+                                // search the text of the values of the key and its subkeys
+                                let results = state.search(&path, &query);
+                                response_channel.send(results).ok();
                             }
 
                             DataStoreRequest::Set {
@@ -126,16 +252,93 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                                 value,
                                 response_channel,
                             } => {
-                                // set value
-                                todo!()
+                                // set value, responding once the write batch is committed
+                                let result = state
+                                    .store_value(path, Some(value))
+                                    .map(|value| value.change_id);
+                                state.add_to_batch(response_channel, result);
+                            }
+
+                            DataStoreRequest::SetIfCurrent {
+                                path,
+                                value,
+                                expected_change_id,
+                                response_channel,
+                            } => {
+                                // set value only if nothing changed since the expected change
+                                let result =
+                                    state.store_value_if_current(path, value, expected_change_id);
+                                state.commit_writes();
+                                response_channel.send(result).ok();
+                            }
+
+                            DataStoreRequest::SetBlob {
+                                path,
+                                content_type,
+                                data,
+                                response_channel,
+                            } => {
+                                // store the content by hash and set the value to the reference
+                                let result = state.store_blob(path, &content_type, &data);
+                                state.commit_writes();
+                                response_channel.send(result).ok();
+                            }
+
+                            DataStoreRequest::GetBlob {
+                                path,
+                                response_channel,
+                            } => {
+                                // load the content referenced by the current value
+                                let blob = state.load_blob(&path);
+                                response_channel.send(blob).ok();
+                            }
+
+                            DataStoreRequest::Update {
+                                path,
+                                update,
+                                response_channel,
+                            } => {
+                                // set value computed from the current value
+                                let value = state.update_value(path, update);
+                                state.commit_writes();
+                                response_channel.send(value).ok();
                             }
 
                             DataStoreRequest::Delete {
                                 path,
                                 response_channel,
                             } => {
-                                // set value to none
-                                todo!()
+                                // set value to none, responding once the write batch is committed
+                                let result =
+                                    state.store_value(path, None).map(|value| value.change_id);
+                                state.add_to_batch(response_channel, result);
+                            }
+
+                            DataStoreRequest::Claim {
+                                path,
+                                claim,
+                                visibility_timeout,
+                                response_channel,
+                            } => {
+                                // take part of the value until the claim is completed or expires
+                                let claim_id = state.claim(path, claim, visibility_timeout);
+                                state.commit_writes();
+                                response_channel.send(claim_id).ok();
+                            }
+
+                            DataStoreRequest::CompleteClaim {
+                                path,
+                                claim_id,
+                                response_channel,
+                            } => {
+                                // forget the claim so it is never released
+                                // claims can only be completed through the path they were made on
+                                let completed = state
+                                    .claims
+                                    .get(&claim_id)
+                                    .is_some_and(|claim| claim.path == path)
+                                    && state.claims.remove(&claim_id).is_some();
+                                response_channel.send(completed).ok();
                             }
 
                             DataStoreRequest::Subscribe {
@@ -143,8 +346,9 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                                 notification_channel,
                                 response_channel,
                             } => {
-                                // ???
-                                todo!()
+                                // add to subscription list
+                                let subscription_id = state.subscribe(path, notification_channel);
+                                response_channel.send(subscription_id).ok();
                             }
 
                             DataStoreRequest::Unsubscribe {
@@ -152,15 +356,16 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                                 response_channel,
                             } => {
                                 // remove from subscription list
-                                todo!()
+                                state.unsubscribe(subscription_id);
+                                if let Some(response_channel) = response_channel {
+                                    response_channel.send(()).ok();
+                                }
                             }
 
                             // handles ping requests
                             DataStoreRequest::Ping { response_channel } => {
                                 if let Some(response_channel) = response_channel {
-                                    response_channel
-                                        .send(())
-                                        .expect("Error occurred while replying to data store ping");
+                                    response_channel.send(()).ok();
                                 }
                             }
 
@@ -179,14 +384,25 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
                             }
                         },
                     }
+
+                    // a steady stream of writes never times out the wait, so the latency is checked after each request
+                    if state.batch_full()
+                        || state
+                            .batch_deadline()
+                            .is_some_and(|deadline| deadline <= Instant::now())
+                    {
+                        state.commit_writes();
+                    }
                 }
 
                 // clean up and ensure database changes are committed
 
+                // return claimed items so they aren't lost
+                state.release_all_claims();
+                state.commit_writes();
+
                 if let Some(response_channel) = shutdown_response {
-                    response_channel.send(()).expect(
-                        "Error occurred while acknowledging data store thread shutdown command",
-                    );
+                    response_channel.send(()).ok();
                 }
             })
             .expect("Failed to spawn data store thread");
@@ -198,142 +414,562 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + 'static> DataStore<T> {
 
         // return datastore object
         Self {
-            name: String::from(name),
             config,
-            database,
             mpsc_channel_sender: tx,
             join_handle: Arc::new(Mutex::new(Some(join_handle))),
         }
     }
 
-    pub async fn get_all(&self, path: &[&str], last_change_id: Option<Uuid>) -> Vec<Arc<Value<T>>> {
-        let tx = self.mpsc_channel_sender.clone();
-
-        let (response_tx, response_rx) = oneshot_async::channel();
-
-        tx.send(DataStoreRequest::Get {
+    pub async fn get_all(
+        &self,
+        path: &[&str],
+        last_change_id: Option<Uuid>,
+    ) -> Result<Vec<Arc<Value<T>>>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Get {
             path: path.iter().map(|x| String::from(*x)).collect(),
             last_change_id,
-            response_channel: OneshotSender::Async(response_tx),
+            response_channel,
         })
-        .expect("Error occurred while sending get all request to data store");
+        .await
+    }
 
-        response_rx
-            .await
-            .expect("Error occurred while receiving get all response from data store")
+    pub async fn get_current(&self, path: &[&str]) -> Result<Arc<Value<T>>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::GetCurrent {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            response_channel,
+        })
+        .await
     }
 
-    pub async fn get_current(&self, path: &[&str]) -> Arc<Value<T>> {
-        let tx = self.mpsc_channel_sender.clone();
+    pub async fn list(&self, path: &[&str]) -> Result<Vec<String>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::List {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            response_channel,
+        })
+        .await
+    }
 
-        let (response_tx, response_rx) = oneshot_async::channel();
+    /// Queries the current values of the subkeys of a path.
+    /// Values are filtered by all of the filters, sorted (by key if no sort is provided) and paginated.
+    /// The cursor of the next page is returned if there may be more values.
+    pub async fn query(
+        &self,
+        path: &[&str],
+        filters: &[DatastoreQueryFilter],
+        sort: Option<DatastoreQuerySort>,
+        limit: Option<u64>,
+        cursor: Option<u64>,
+    ) -> Result<QueryResult<T>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Query {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            filters: filters.to_vec(),
+            sort,
+            limit,
+            cursor,
+            response_channel,
+        })
+        .await
+    }
 
-        tx.send(DataStoreRequest::GetCurrent {
+    /// Gets the changes of a path and its subkeys made after a cursor, in the order they happened.
+    /// Start with a cursor of 0 and pass the returned cursor to get the following changes.
+    /// Only the changes still in the history are returned, so keys without history only return their latest change.
+    pub async fn changes_since(
+        &self,
+        path: &[&str],
+        cursor: u64,
+        limit: Option<u64>,
+    ) -> Result<ChangesResult<T>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::ChangesSince {
             path: path.iter().map(|x| String::from(*x)).collect(),
-            response_channel: OneshotSender::Async(response_tx),
+            cursor,
+            limit,
+            response_channel,
         })
-        .expect("Error occurred while sending get current request to data store");
+        .await
+    }
 
-        response_rx
-            .await
-            .expect("Error occurred while receiving get current response from data store")
+    /// Searches the text of the current values of a path and its subkeys.
+    /// Results are ordered from most to least relevant.
+    /// Always returns no results if full-text search is not enabled for the data store.
+    pub async fn search(
+        &self,
+        path: &[&str],
+        query: &str,
+    ) -> Result<Vec<DatastoreSearchResult>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Search {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            query: String::from(query),
+            response_channel,
+        })
+        .await
     }
 
-    pub async fn list(&self, path: &[&str]) -> Vec<String> {
-        let tx = self.mpsc_channel_sender.clone();
+    /// Sets the value of a path.
+    /// Returns the change id, or the error if the write failed or a hook rejected it.
+    pub async fn set(&self, path: &[&str], value: T) -> Result<Uuid, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Set {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            value,
+            response_channel: Some(response_channel),
+        })
+        .await?
+    }
 
-        let (response_tx, response_rx) = oneshot_async::channel();
+    /// Deletes the value of a path.
+    /// Returns the change id, or the error if the delete failed or a hook rejected it.
+    pub async fn delete(&self, path: &[&str]) -> Result<Uuid, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Delete {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            response_channel: Some(response_channel),
+        })
+        .await?
+    }
 
-        tx.send(DataStoreRequest::List {
+    /// Sets (or deletes if None) the value of a path if its current change id is the expected one.
+    /// The change id of a path that was never set is the nil uuid.
+    /// Returns the new change id, or the current value if it was changed in the meantime or the reason a hook rejected the write.
+    pub async fn set_if_current(
+        &self,
+        path: &[&str],
+        value: Option<T>,
+        expected_change_id: Uuid,
+    ) -> Result<Uuid, SetIfCurrentError<T>> {
+        self.request(|response_channel| DataStoreRequest::SetIfCurrent {
             path: path.iter().map(|x| String::from(*x)).collect(),
-            response_channel: OneshotSender::Async(response_tx),
+            value,
+            expected_change_id,
+            response_channel,
         })
-        .expect("Error occurred while sending list request to data store");
+        .await?
+    }
 
-        response_rx
-            .await
-            .expect("Error occurred while receiving list response from data store")
+    /// Sets the value of a path to a reference to binary content.
+    /// The content is stored once per data store no matter how many values reference it.
+    /// Returns the change id, or the reason the write was rejected.
+    pub async fn set_blob(
+        &self,
+        path: &[&str],
+        content_type: &str,
+        data: Vec<u8>,
+    ) -> Result<Uuid, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::SetBlob {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            content_type: String::from(content_type),
+            data,
+            response_channel,
+        })
+        .await?
     }
 
-    pub async fn set(&self, path: &[&str], value: T) -> Uuid {
-        let tx = self.mpsc_channel_sender.clone();
+    /// Gets the binary content referenced by the current value of a path.
+    /// Returns None if the value doesn't reference a blob.
+    pub async fn get_blob(
+        &self,
+        path: &[&str],
+    ) -> Result<Option<(BlobReference, Vec<u8>)>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::GetBlob {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            response_channel,
+        })
+        .await
+    }
 
-        let (response_tx, response_rx) = oneshot_async::channel();
+    /// Sets a value computed from the current value of a path.
+    /// The update function is run on the data store thread, so no other changes can happen between reading and setting the value.
+    /// If the update function returns None or a hook rejects the new value, the value is not changed.
+    /// Returns the new value if it was changed.
+    pub async fn update(
+        &self,
+        path: &[&str],
+        update: impl FnOnce(Option<&T>) -> Option<T> + Send + 'static,
+    ) -> Result<Option<Arc<Value<T>>>, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Update {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            update: Box::new(update),
+            response_channel,
+        })
+        .await?
+    }
+
+    /// Takes part of the value of a path until the claim is completed with `complete_claim`.
+    /// The claim function returns the new value and a function that puts the claimed part back into the value.
+    /// The claimed part is put back if the claim isn't completed before the visibility timeout or the data store shuts down.
+    /// Returns the claim id, or None if the claim function returned None.
+    /// Visibility timeouts above the configured maximum are rejected as invalid.
+    pub async fn claim_with(
+        &self,
+        path: &[&str],
+        visibility_timeout: Duration,
+        claim: impl FnOnce(Option<&T>) -> Option<(T, ReleaseFunction<T>)> + Send + 'static,
+    ) -> Result<Option<Uuid>, DataStoreError> {
+        if visibility_timeout > Duration::from_secs(self.config.max_claim_visibility_timeout) {
+            return Err(DataStoreError::InvalidData(format!(
+                "Visibility timeout above maximum of {} seconds",
+                self.config.max_claim_visibility_timeout
+            )));
+        }
 
-        tx.send(DataStoreRequest::Set {
+        self.request(|response_channel| DataStoreRequest::Claim {
             path: path.iter().map(|x| String::from(*x)).collect(),
-            value,
-            response_channel: Some(OneshotSender::Async(response_tx)),
+            claim: Box::new(claim),
+            visibility_timeout,
+            response_channel,
         })
-        .expect("Error occurred while sending set request to data store");
+        .await?
+    }
 
-        response_rx
-            .await
-            .expect("Error occurred while receiving set response from data store")
+    /// Completes a claim on the value of a path so the claimed part is never put back.
+    /// Returns false if the claim already expired or was completed, or was made on another path.
+    pub async fn complete_claim(
+        &self,
+        path: &[&str],
+        claim_id: Uuid,
+    ) -> Result<bool, DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::CompleteClaim {
+            path: path.iter().map(|x| String::from(*x)).collect(),
+            claim_id,
+            response_channel,
+        })
+        .await
     }
 
-    pub async fn delete(&self, path: &[&str]) -> Uuid {
-        let tx = self.mpsc_channel_sender.clone();
+    /// Subscribes to changes of the value of a path.
+    /// The subscription is cancelled when it is dropped.
+    pub async fn subscribe(&self, path: &[&str]) -> Result<Subscription<T>, DataStoreError> {
+        let (notification_tx, notification_rx) = mpsc_async::unbounded_channel();
 
-        let (response_tx, response_rx) = oneshot_async::channel();
+        let id = self
+            .request(|response_channel| DataStoreRequest::Subscribe {
+                path: path.iter().map(|x| String::from(*x)).collect(),
+                notification_channel: MPSCSender::Async(notification_tx),
+                response_channel,
+            })
+            .await?;
 
-        tx.send(DataStoreRequest::Delete {
-            path: path.iter().map(|x| String::from(*x)).collect(),
-            response_channel: Some(OneshotSender::Async(response_tx)),
+        Ok(Subscription {
+            id,
+            notification_channel: notification_rx,
+            datastore_channel: self.mpsc_channel_sender.clone(),
         })
-        .expect("Error occurred while sending delete request to data store");
+    }
 
-        response_rx
-            .await
-            .expect("Error occurred while receiving delete response from data store")
+    /// Exports the values of a path and its subkeys as JSON Lines, one `Value` record per line.
+    /// With history, all value entries (including deletions) are exported in the order the changes happened.
+    /// Without history, only the current values that are set are exported.
+    /// Binary content referenced by blob values is not exported.
+    pub async fn export(
+        &self,
+        path: &[&str],
+        include_history: bool,
+    ) -> Result<String, DataStoreError> {
+        let values = self
+            .request(|response_channel| DataStoreRequest::Export {
+                path: path.iter().map(|x| String::from(*x)).collect(),
+                include_history,
+                response_channel,
+            })
+            .await?;
+
+        let mut lines = String::new();
+        for value in values {
+            lines.push_str(
+                &serde_json::to_string(&*value)
+                    .expect("Error occurred while serializing data store value"),
+            );
+            lines.push('\n');
+        }
+        Ok(lines)
     }
 
-    pub async fn subscribe(&self, path: &[&str]) -> Subscription<T> {
-        todo!()
+    /// Imports JSON Lines records written by `export`, keeping their change ids and timestamps.
+    /// Records are stored in order after the existing values, so the last record of a path becomes its current value.
+    /// Records with change ids that are already stored are skipped.
+    /// Hooks and triggers don't run for imported records.
+    /// Imported records may keep `$blob` references, since import requires the admin permission and must restore exports.
+    /// All records are read before any is stored, so nothing is imported if a record is invalid.
+    /// Returns the number of imported records.
+    pub async fn import(&self, reader: impl AsyncBufRead + Unpin) -> Result<u64, DataStoreError> {
+        let mut records = Vec::new();
+        let mut lines = reader.lines();
+        let mut line_number = 0;
+        loop {
+            line_number += 1;
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(error) => return Err(DataStoreError::InvalidData(error.to_string())),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Value<T> = serde_json::from_str(&line).map_err(|error| {
+                DataStoreError::InvalidData(format!("Line {}: {}", line_number, error))
+            })?;
+            records.push(record);
+        }
+
+        let mut imported = 0;
+        while !records.is_empty() {
+            let rest = records.split_off(records.len().min(IMPORT_CHUNK_RECORDS));
+            let chunk = std::mem::replace(&mut records, rest);
+            imported += self
+                .request(|response_channel| DataStoreRequest::Import {
+                    values: chunk,
+                    response_channel,
+                })
+                .await?;
+        }
+        Ok(imported)
     }
 
     /// Sends a ping and waits for a repsonse.
     /// Can be used to find current latency of the data store's request queue.
-    pub async fn ping(&self) {
-        let tx = self.mpsc_channel_sender.clone();
+    pub async fn ping(&self) -> Result<(), DataStoreError> {
+        self.request(|response_channel| DataStoreRequest::Ping {
+            response_channel: Some(response_channel),
+        })
+        .await
+    }
 
-        let (ping_tx, ping_rx) = oneshot_async::channel();
+    /// Stops the data store thread once the requests queued before are handled and the pending writes are committed.
+    /// Requests sent afterwards fail as stopped.
+    pub async fn shutdown(&self) {
+        let (response_tx, response_rx) = oneshot_async::channel();
+        let tx = self.mpsc_channel_sender.clone();
 
-        tx.send(DataStoreRequest::Ping {
-            response_channel: Some(OneshotSender::Async(ping_tx)),
+        // wait for space in the request queue without blocking the async runtime
+        let sent = tokio::task::spawn_blocking(move || {
+            tx.send(DataStoreRequest::Shutdown {
+                response_channel: Some(OneshotSender::Async(response_tx)),
+            })
+            .is_ok()
         })
-        .expect("Error occurred while sending data store ping");
+        .await
+        .unwrap_or(false);
 
-        ping_rx
-            .await
-            .expect("Error occurred while receiving ping reply from data store");
+        if sent {
+            response_rx.await.ok();
+        }
+    }
+
+    /// Sends a request to the data store thread and waits for the response.
+    /// Fails without waiting if the request queue is full.
+    async fn request<R>(
+        &self,
+        request: impl FnOnce(OneshotSender<R>) -> DataStoreRequest<T>,
+    ) -> Result<R, DataStoreError> {
+        let (response_tx, response_rx) = oneshot_async::channel();
+
+        self.mpsc_channel_sender
+            .try_send(request(OneshotSender::Async(response_tx)))
+            .map_err(|error| match error {
+                TrySendError::Full(_) => DataStoreError::Overloaded,
+                TrySendError::Disconnected(_) => DataStoreError::Stopped,
+            })?;
+
+        match tokio::time::timeout(
+            Duration::from_millis(self.config.request_timeout),
+            response_rx,
+        )
+        .await
+        {
+            Ok(Ok(response)) => Ok(response),
+            // the response channel is dropped without a response if the thread stopped
+            Ok(Err(_)) => Err(DataStoreError::Stopped),
+            Err(_) => Err(DataStoreError::Timeout),
+        }
     }
 }
 
-impl<T> Drop for DataStore<T> {
-    fn drop(&mut self) {
-        // If we're the last clone, then we can shut down the thread
-        if Arc::strong_count(&self.join_handle) == 1 {
-            let tx = self.mpsc_channel_sender.clone();
+impl DataStore<serde_json::Value> {
+    /// Atomically adds to the number stored at a path, treating an unset value as 0.
+    /// Integers stay integers unless the result overflows or either number is a float.
+    /// Returns the new value, or None if the current value is not a number.
+    pub async fn increment(
+        &self,
+        path: &[&str],
+        delta: serde_json::Number,
+    ) -> Result<Option<Arc<Value<serde_json::Value>>>, DataStoreError> {
+        self.update(path, move |current| {
+            let current = match current {
+                Some(serde_json::Value::Number(current)) => current.clone(),
+                Some(_) => return None,
+                None => serde_json::Number::from(0),
+            };
+
+            Some(serde_json::Value::Number(add_numbers(&current, &delta)?))
+        })
+        .await
+    }
 
-            let (response_tx, response_rx) = mpsc::channel();
+    /// Atomically appends an item to the list stored at a path, treating an unset value as an empty list.
+    /// Returns the new value, or None if the current value is not a list.
+    pub async fn push(
+        &self,
+        path: &[&str],
+        item: serde_json::Value,
+    ) -> Result<Option<Arc<Value<serde_json::Value>>>, DataStoreError> {
+        self.update(path, move |current| {
+            let mut items = list_items(current)?;
+            items.push(item);
+            Some(serde_json::Value::Array(items))
+        })
+        .await
+    }
 
-            tx.send(DataStoreRequest::Shutdown {
-                response_channel: Some(OneshotSender::Sync(response_tx)),
+    /// Atomically removes the last item of the list stored at a path.
+    /// Returns the removed item, or None if the list is empty or the current value is not a list.
+    pub async fn pop(&self, path: &[&str]) -> Result<Option<serde_json::Value>, DataStoreError> {
+        self.update_list_taking(path, |items| items.pop()).await
+    }
+
+    /// Atomically inserts an item into the list stored at a path, treating an unset value as an empty list.
+    /// Returns the new value, or None if the index is past the end of the list or the current value is not a list.
+    pub async fn insert_at(
+        &self,
+        path: &[&str],
+        index: usize,
+        item: serde_json::Value,
+    ) -> Result<Option<Arc<Value<serde_json::Value>>>, DataStoreError> {
+        self.update(path, move |current| {
+            let mut items = list_items(current)?;
+            if index > items.len() {
+                return None;
+            }
+            items.insert(index, item);
+            Some(serde_json::Value::Array(items))
+        })
+        .await
+    }
+
+    /// Atomically removes an item from the list stored at a path.
+    /// Returns the removed item, or None if the index is past the end of the list or the current value is not a list.
+    pub async fn remove_at(
+        &self,
+        path: &[&str],
+        index: usize,
+    ) -> Result<Option<serde_json::Value>, DataStoreError> {
+        self.update_list_taking(path, move |items| {
+            if index < items.len() {
+                Some(items.remove(index))
+            } else {
+                None
+            }
+        })
+        .await
+    }
+
+    /// Atomically takes the first item of the list stored at a path to work on.
+    /// The item is put back at the start of the list if the claim isn't completed with `complete_claim` before the visibility timeout.
+    /// Returns the claim, or None if the list is empty or the current value is not a list.
+    pub async fn claim(
+        &self,
+        path: &[&str],
+        visibility_timeout: Duration,
+    ) -> Result<Option<QueueClaim>, DataStoreError> {
+        let claimed = Arc::new(Mutex::new(None));
+        let claimed_item = Arc::clone(&claimed);
+
+        let claim_id = self
+            .claim_with(path, visibility_timeout, move |current| {
+                let mut items = list_items(current)?;
+                if items.is_empty() {
+                    return None;
+                }
+                let item = items.remove(0);
+                *claimed_item.lock().unwrap() = Some(item.clone());
+
+                let release: ReleaseFunction<serde_json::Value> = Box::new(move |current| {
+                    let mut items = list_items(current)?;
+                    items.insert(0, item.clone());
+                    Some(serde_json::Value::Array(items))
+                });
+                Some((serde_json::Value::Array(items), release))
             })
-            .ok();
+            .await?;
+
+        let item = claimed.lock().unwrap().take();
+        Ok(claim_id.zip(item).map(|(id, item)| QueueClaim { id, item }))
+    }
 
-            // wait for shutdown
-            response_rx.recv().ok();
+    /// Atomically applies operations to the collaboratively edited text stored at a path, treating an unset value as an empty text.
+    /// The stored value is a serialized `CrdtText` containing the operations applied by the change, so subscribers receive the operations.
+    /// Returns the new value, or None if the current value is not a text or an operation refers to a character that doesn't exist.
+    pub async fn apply_text_operations(
+        &self,
+        path: &[&str],
+        operations: Vec<TextOperation>,
+    ) -> Result<Option<Arc<Value<serde_json::Value>>>, DataStoreError> {
+        self.update(path, move |current| {
+            let mut text: CrdtText = match current {
+                Some(current) => serde_json::from_value(current.clone()).ok()?,
+                None => CrdtText::default(),
+            };
+
+            if !operations.iter().all(|operation| text.apply(operation)) {
+                return None;
+            }
+            text.operations = operations;
+
+            Some(serde_json::to_value(text).expect("Error occurred while serializing text"))
+        })
+        .await
+    }
 
-            // wait for thread to exit
-            self.join_handle
-                .lock()
-                .unwrap()
-                .take()
-                .unwrap()
-                .join()
-                .expect("Error: datastore thread panicked");
+    /// Atomically removes an item from the list stored at a path using the provided function.
+    /// Returns the removed item, or None if nothing was removed.
+    async fn update_list_taking(
+        &self,
+        path: &[&str],
+        take: impl FnOnce(&mut Vec<serde_json::Value>) -> Option<serde_json::Value> + Send + 'static,
+    ) -> Result<Option<serde_json::Value>, DataStoreError> {
+        let taken = Arc::new(Mutex::new(None));
+        let taken_item = Arc::clone(&taken);
+
+        self.update(path, move |current| {
+            let mut items = list_items(current)?;
+            let item = take(&mut items)?;
+            *taken_item.lock().unwrap() = Some(item);
+            Some(serde_json::Value::Array(items))
+        })
+        .await?;
+
+        let item = taken.lock().unwrap().take();
+        Ok(item)
+    }
+}
+
+/// Adds two numbers.
+/// Integers stay integers unless the result overflows or either number is a float.
+fn add_numbers(a: &serde_json::Number, b: &serde_json::Number) -> Option<serde_json::Number> {
+    match (a.as_i64(), b.as_i64()) {
+        (Some(a), Some(b)) if a.checked_add(b).is_some() => Some((a + b).into()),
+        _ => serde_json::Number::from_f64(a.as_f64()? + b.as_f64()?),
+    }
+}
+
+/// Gets the items of a list value, treating an unset value as an empty list.
+/// Returns None if the value is not a list.
+fn list_items(value: Option<&serde_json::Value>) -> Option<Vec<serde_json::Value>> {
+    match value {
+        Some(serde_json::Value::Array(items)) => Some(items.clone()),
+        Some(_) => None,
+        None => Some(Vec::new()),
+    }
+}
+
+impl<T> Drop for DataStore<T> {
+    fn drop(&mut self) {
+        // If we're the last clone, then we can shut down the thread
+        // Dropping can happen on an async task, so this doesn't wait for the thread; `shutdown` does
+        if Arc::strong_count(&self.join_handle) == 1 {
+            // if the queue is full, the thread still stops once it handled the queue and all senders are dropped
+            self.mpsc_channel_sender
+                .try_send(DataStoreRequest::Shutdown {
+                    response_channel: None,
+                })
+                .ok();
         }
     }
 }
@@ -366,22 +1002,124 @@ enum DataStoreRequest<T> {
         response_channel: OneshotSender<Vec<String>>,
     },
 
+    /// Queries the current values of sub-keys of a path
+    Query {
+        /// Path to query the sub-keys of
+        path: Vec<String>,
+        /// Filters that all values must match
+        filters: Vec<DatastoreQueryFilter>,
+        /// Sort order
+        sort: Option<DatastoreQuerySort>,
+        /// Maximum number of values to return
+        limit: Option<u64>,
+        /// Cursor returned by the previous page
+        cursor: Option<u64>,
+        /// Response channel (sends the page of values)
+        response_channel: OneshotSender<QueryResult<T>>,
+    },
+
+    /// Gets the value entries of a path and its sub-keys inserted after a cursor
+    ChangesSince {
+        /// Path to get the changes of
+        path: Vec<String>,
+        /// Cursor returned by the previous request, 0 to get all changes
+        cursor: u64,
+        /// Maximum number of changes to return
+        limit: Option<u64>,
+        /// Response channel (sends the changes)
+        response_channel: OneshotSender<ChangesResult<T>>,
+    },
+
+    /// Searches the text of values of a path and its sub-keys
+    Search {
+        /// Path to search
+        path: Vec<String>,
+        /// Search query
+        query: String,
+        /// Response channel (sends the matches)
+        response_channel: OneshotSender<Vec<DatastoreSearchResult>>,
+    },
+
     /// Inserts a value into the history, updating the current value
     Set {
         /// Path to set the value of
         path: Vec<String>,
         /// Value to set
         value: T,
-        /// Response channel
-        response_channel: Option<OneshotSender<Uuid>>,
+        /// Response channel (sends the change id, or the reason a hook rejected the write)
+        response_channel: Option<OneshotSender<Result<Uuid, DataStoreError>>>,
+    },
+
+    /// Inserts a value into the history if the current value has the expected change id
+    SetIfCurrent {
+        /// Path to set the value of
+        path: Vec<String>,
+        /// Value to set, None to delete
+        value: Option<T>,
+        /// Change id the current value must have
+        expected_change_id: Uuid,
+        /// Response channel (sends the new change id, or why the value wasn't set)
+        response_channel: OneshotSender<Result<Uuid, SetIfCurrentError<T>>>,
+    },
+
+    /// Stores binary content and inserts a reference to it into the history
+    SetBlob {
+        /// Path to set the value of
+        path: Vec<String>,
+        /// MIME type of the content
+        content_type: String,
+        /// Content to store
+        data: Vec<u8>,
+        /// Response channel (sends the change id, or the reason the write was rejected)
+        response_channel: OneshotSender<Result<Uuid, DataStoreError>>,
+    },
+
+    /// Gets the binary content referenced by the current value
+    GetBlob {
+        /// Path of the value referencing the content
+        path: Vec<String>,
+        /// Response channel (sends the reference and content if the value references a blob)
+        response_channel: OneshotSender<Option<(BlobReference, Vec<u8>)>>,
+    },
+
+    /// Inserts a value computed from the current value into the history
+    Update {
+        /// Path to update the value of
+        path: Vec<String>,
+        /// Function computing the new value from the current value, None to leave the value unchanged
+        update: UpdateFunction<T>,
+        /// Response channel (sends the new value entry if the value was changed, or the reason a hook rejected the write)
+        response_channel: OneshotSender<Result<Option<Arc<Value<T>>>, DataStoreError>>,
     },
 
     /// Inserts a None value into the history, updating the current value
     Delete {
         /// Path to set a None value of
         path: Vec<String>,
-        /// Response channel
-        response_channel: Option<OneshotSender<Uuid>>,
+        /// Response channel (sends the change id, or the reason a hook rejected the delete)
+        response_channel: Option<OneshotSender<Result<Uuid, DataStoreError>>>,
+    },
+
+    /// Takes part of the current value until the claim is completed or expires
+    Claim {
+        /// Path of the value to claim part of
+        path: Vec<String>,
+        /// Function taking part of the value
+        claim: ClaimFunction<T>,
+        /// Time after which the claimed part is put back if the claim wasn't completed
+        visibility_timeout: Duration,
+        /// Response channel (sends the claim id if anything was claimed, or the reason a hook rejected the write)
+        response_channel: OneshotSender<Result<Option<Uuid>, DataStoreError>>,
+    },
+
+    /// Completes a claim so the claimed part is never put back
+    CompleteClaim {
+        /// Path the claim was made on
+        path: Vec<String>,
+        /// Claim id to complete
+        claim_id: Uuid,
+        /// Response channel (sends whether the claim was still active)
+        response_channel: OneshotSender<bool>,
     },
 
     /// Subscribes for a change notification on a path
@@ -389,7 +1127,7 @@ enum DataStoreRequest<T> {
         /// Path to subscribe to
         path: Vec<String>,
         /// Subscription notification channel
-        notification_channel: MPSCSender<Vec<Arc<Value<T>>>>,
+        notification_channel: MPSCSender<Arc<Value<T>>>,
         /// Response channel (sends subscription id)
         response_channel: OneshotSender<Uuid>,
     },
@@ -402,6 +1140,24 @@ enum DataStoreRequest<T> {
         response_channel: Option<OneshotSender<()>>,
     },
 
+    /// Export request
+    Export {
+        /// Path of the exported key
+        path: Vec<String>,
+        /// Whether to export previous values
+        include_history: bool,
+        /// Response channel (sends the exported values in the order they were set)
+        response_channel: OneshotSender<Vec<Arc<Value<T>>>>,
+    },
+
+    /// Import request
+    Import {
+        /// Imported values in the order they are stored
+        values: Vec<Value<T>>,
+        /// Response channel (sends the number of imported values)
+        response_channel: OneshotSender<u64>,
+    },
+
     /// Pings the data store
     Ping {
         /// Response channel
@@ -415,13 +1171,37 @@ enum DataStoreRequest<T> {
     },
 }
 
+/// Function used to compute a new value from the current value
+pub type UpdateFunction<T> = Box<dyn FnOnce(Option<&T>) -> Option<T> + Send>;
+
+/// Function used to put a claimed part back into the current value.
+/// Returns None if the current value can't take the part back.
+/// It is called again later if it returned None or a hook rejected the value it computed.
+pub type ReleaseFunction<T> = Box<dyn Fn(Option<&T>) -> Option<T> + Send>;
+
+/// Function used to take part of the current value.
+/// Returns the new value and the function that puts the claimed part back.
+type ClaimFunction<T> = Box<dyn FnOnce(Option<&T>) -> Option<(T, ReleaseFunction<T>)> + Send>;
+
+/// Claimed part of a value
+struct ClaimRecord<T> {
+    /// Path of the value the part was claimed from
+    path: Vec<String>,
+    /// Time the claimed part is put back if the claim wasn't completed
+    expiry: Instant,
+    /// Function that puts the claimed part back into the value
+    release: ReleaseFunction<T>,
+}
+
 struct SubscriptionRecord<T> {
     id: Uuid,
     path: Vec<String>,
-    notification_channel: mpsc_async::Sender<Arc<Value<T>>>,
+    notification_channel: MPSCSender<Arc<Value<T>>>,
 }
 
-/// Object returned by the datastore api
+/// Object returned by the datastore api.
+/// Also the record format of exports, one JSON object per line.
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Value<T> {
     /// The current value, None if not set or deleted
     pub value: Option<T>,
@@ -433,26 +1213,800 @@ pub struct Value<T> {
     pub change_id: Uuid,
 }
 
+impl<T> Value<T> {
+    /// Creates the value returned for a path that has never been set
+    fn unset(path: Vec<String>) -> Self {
+        Self {
+            value: None,
+            path,
+            timestamp: DateTime::UNIX_EPOCH,
+            change_id: Uuid::nil(),
+        }
+    }
+}
+
+/// Item claimed from a list
+#[derive(Serialize)]
+pub struct QueueClaim {
+    /// Claim id used to complete the claim
+    pub id: Uuid,
+    /// The claimed item
+    pub item: serde_json::Value,
+}
+
+/// Page of values returned by a query
+pub struct QueryResult<T> {
+    /// Values in the page
+    pub values: Vec<Arc<Value<T>>>,
+    /// Cursor to get the next page with, None if there are no more values
+    pub next_cursor: Option<u64>,
+}
+
+/// Reason `set_if_current` didn't set a value
+#[derive(Debug)]
+pub enum SetIfCurrentError<T> {
+    /// The current value has a different change id
+    Conflict(Arc<Value<T>>),
+    /// The write failed or was rejected
+    Error(DataStoreError),
+}
+
+impl<T> From<DataStoreError> for SetIfCurrentError<T> {
+    fn from(error: DataStoreError) -> Self {
+        Self::Error(error)
+    }
+}
+
+/// Error returned by data store requests
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum DataStoreError {
+    /// The request queue is full
+    Overloaded,
+    /// The data store didn't respond before the request timeout.
+    /// The request may still be handled later.
+    Timeout,
+    /// The data store thread is no longer running
+    Stopped,
+    /// A hook rejected the write for the reason
+    Rejected(String),
+    /// Imported data is invalid for the reason
+    InvalidData(String),
+}
+
+impl fmt::Display for DataStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataStoreError::Overloaded => write!(f, "Data store request queue is full"),
+            DataStoreError::Timeout => write!(f, "Data store request timed out"),
+            DataStoreError::Stopped => write!(f, "Data store is not running"),
+            DataStoreError::Rejected(reason) => write!(f, "Write rejected: {}", reason),
+            DataStoreError::InvalidData(reason) => write!(f, "Invalid data: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DataStoreError {}
+
+/// Changes returned by `changes_since`
+pub struct ChangesResult<T> {
+    /// Changed values in the order the changes happened
+    pub changes: Vec<Arc<Value<T>>>,
+    /// Cursor to get the following changes with
+    pub next_cursor: u64,
+}
+
+/// Storage of the values of a data store
+enum DataStoreStorage<T> {
+    /// Values are persisted to a database
+    Database(DbSchema),
+    /// Values are only kept in memory and lost when the data store shuts down
+    Memory(MemoryStorage<T>),
+}
+
+/// State owned by the data store thread
+struct DataStoreThread<T> {
+    /// Data store name
+    name: String,
+    /// Data store configuration
+    config: DatastoreConfig,
+    /// Storage the data is kept in
+    storage: DataStoreStorage<T>,
+    /// TLRU cache of deserialized items to allow more efficient handling of large values
+    value_cache_by_change_id: TLRUCache<Uuid, Arc<Value<T>>>,
+    /// Mapping of subscription ids to subscriptions
+    subscriptions_by_id: HashMap<Uuid, Rc<SubscriptionRecord<T>>>,
+    /// Mapping of subscription paths to subscriptions
+    subscriptions_by_path: HashMap<Vec<String>, Vec<Rc<SubscriptionRecord<T>>>>,
+    /// Mapping of claim ids to claims that have not been completed
+    claims: HashMap<Uuid, ClaimRecord<T>>,
+    /// Hooks run around every write
+    hooks: Vec<Box<dyn DataStoreHook<T>>>,
+    /// Values written since the last commit, in the order they were written
+    pending_writes: Vec<Arc<Value<T>>>,
+    /// Set and delete requests in the current write batch, responded to once it is committed
+    pending_responses: Vec<PendingResponse>,
+    /// Time the current write batch was started
+    batch_started: Option<Instant>,
+}
+
+/// Response to a set or delete request waiting for its write batch to be committed
+type PendingResponse = (
+    Option<OneshotSender<Result<Uuid, DataStoreError>>>,
+    Result<Uuid, DataStoreError>,
+);
+
+impl<T: Serialize + DeserializeOwned> DataStoreThread<T> {
+    fn new(
+        name: String,
+        config: DatastoreConfig,
+        storage: DataStoreStorage<T>,
+        hooks: Vec<Box<dyn DataStoreHook<T>>>,
+    ) -> Self {
+        Self {
+            name,
+            config,
+            storage,
+            value_cache_by_change_id: TLRUCache::new(
+                Some(ITEM_CACHE_MAX_ITEMS),
+                None,
+                Some(ITEM_CACHE_MAX_ACCESS_AGE),
+            ),
+            subscriptions_by_id: HashMap::new(),
+            subscriptions_by_path: HashMap::new(),
+            claims: HashMap::new(),
+            hooks,
+            pending_writes: Vec::new(),
+            pending_responses: Vec::new(),
+            batch_started: None,
+        }
+    }
+
+    /// Gets a value from the cache, loading it from the database if it isn't cached
+    fn load_value(
+        &mut self,
+        database: &DbSchema,
+        path: &[String],
+        meta: DatastoreValueMeta,
+    ) -> Arc<Value<T>> {
+        if let Some(value) = self.value_cache_by_change_id.get(&meta.change_id) {
+            return Arc::clone(&value);
+        }
+
+        let value = database
+            .datastore_get_value(&self.name, &self.config, meta.change_id)
+            .map(|json| {
+                serde_json::from_str(&json)
+                    .expect("Error occurred while deserializing data store value")
+            });
+
+        let value = Arc::new(Value {
+            value,
+            path: path.to_vec(),
+            timestamp: meta.timestamp,
+            change_id: meta.change_id,
+        });
+        self.value_cache_by_change_id
+            .insert(meta.change_id, Arc::clone(&value));
+
+        value
+    }
+
+    /// Gets the current value of a path, None if it was never set
+    fn get_current(&mut self, path: &[String]) -> Option<Arc<Value<T>>> {
+        // values of the current write batch aren't in the database yet
+        if let Some(value) = self.pending_writes.iter().rev().find(|x| x.path == path) {
+            return Some(Arc::clone(value));
+        }
+
+        match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let database = database.clone();
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                let meta = database.datastore_get_current(&self.name, &self.config, &path_refs)?;
+                Some(self.load_value(&database, path, meta))
+            }
+            DataStoreStorage::Memory(memory) => memory.get_current(path),
+        }
+    }
+
+    /// Gets the current and previous values of a path in chronological order.
+    /// If the last change id is set and still in the history, only the values after it are returned.
+    fn get_history(&mut self, path: &[String], last_change_id: Option<Uuid>) -> Vec<Arc<Value<T>>> {
+        match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let database = database.clone();
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                database
+                    .datastore_get_history(&self.name, &self.config, &path_refs, last_change_id)
+                    .into_iter()
+                    .map(|meta| self.load_value(&database, path, meta))
+                    .collect()
+            }
+            DataStoreStorage::Memory(memory) => memory.get_history(path, last_change_id),
+        }
+    }
+
+    /// Lists the subkeys of a path that have values set or have subkeys with values set
+    fn list(&self, path: &[String]) -> Vec<String> {
+        match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                database.datastore_list(&self.name, &self.config, &path_refs)
+            }
+            DataStoreStorage::Memory(memory) => memory.list(path),
+        }
+    }
+
+    /// Filters, sorts and paginates the current values of the subkeys of a path
+    fn query(
+        &mut self,
+        path: &[String],
+        filters: &[DatastoreQueryFilter],
+        sort: Option<&DatastoreQuerySort>,
+        limit: Option<u64>,
+        offset: u64,
+    ) -> Vec<Arc<Value<T>>> {
+        match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let database = database.clone();
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                database
+                    .datastore_query(
+                        &self.name,
+                        &self.config,
+                        &path_refs,
+                        filters,
+                        sort,
+                        limit,
+                        offset,
+                    )
+                    .into_iter()
+                    .map(|child| {
+                        let mut child_path = path.to_vec();
+                        child_path.push(child.key);
+                        self.load_value(&database, &child_path, child.value)
+                    })
+                    .collect()
+            }
+            DataStoreStorage::Memory(memory) => memory.query(path, filters, sort, limit, offset),
+        }
+    }
+
+    /// Gets the changes of a path and its subkeys made after a cursor, in the order they happened
+    fn changes_since(
+        &mut self,
+        path: &[String],
+        cursor: u64,
+        limit: Option<u64>,
+    ) -> ChangesResult<T> {
+        let changes: Vec<(u64, Arc<Value<T>>)> = match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let database = database.clone();
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                database
+                    .datastore_changes_since(&self.name, &self.config, &path_refs, cursor, limit)
+                    .into_iter()
+                    .map(|change| {
+                        let id = change.value.id;
+                        (id, self.load_value(&database, &change.path, change.value))
+                    })
+                    .collect()
+            }
+            DataStoreStorage::Memory(memory) => memory.changes_since(path, cursor, limit),
+        };
+
+        ChangesResult {
+            next_cursor: changes.last().map_or(cursor, |(id, _)| *id),
+            changes: changes.into_iter().map(|(_, value)| value).collect(),
+        }
+    }
+
+    /// Gets the value entries of a path and its subkeys in the order they were set.
+    /// Without history, only the current values that are set are returned.
+    fn export(&mut self, path: &[String], include_history: bool) -> Vec<Arc<Value<T>>> {
+        let values = self.changes_since(path, 0, None).changes;
+        if include_history {
+            return values;
+        }
+
+        // the current value of a path is its last entry
+        let mut current: HashMap<&[String], usize> = HashMap::new();
+        for (index, value) in values.iter().enumerate() {
+            current.insert(&value.path, index);
+        }
+        values
+            .iter()
+            .enumerate()
+            .filter(|(index, value)| {
+                value.value.is_some() && current.get(value.path.as_slice()) == Some(index)
+            })
+            .map(|(_, value)| Arc::clone(value))
+            .collect()
+    }
+
+    /// Adds value entries to the write batch as they are, skipping the ones whose change ids are already stored.
+    /// Returns the number of added entries.
+    fn import(&mut self, values: Vec<Value<T>>) -> u64 {
+        // the change ids of each path are loaded once instead of for every imported record
+        let mut known_change_ids: HashMap<Vec<String>, HashSet<Uuid>> = HashMap::new();
+        let mut imported = 0;
+        for value in values {
+            let change_ids = known_change_ids
+                .entry(value.path.clone())
+                .or_insert_with(|| self.change_ids(&value.path));
+            if !change_ids.insert(value.change_id) {
+                continue;
+            }
+
+            let value = Arc::new(value);
+            self.value_cache_by_change_id
+                .insert(value.change_id, Arc::clone(&value));
+            self.pending_writes.push(value);
+            self.batch_started.get_or_insert_with(Instant::now);
+            imported += 1;
+        }
+        imported
+    }
+
+    /// Gets the change ids in the history of a path, including the pending writes
+    fn change_ids(&self, path: &[String]) -> HashSet<Uuid> {
+        let mut change_ids: HashSet<Uuid> = self
+            .pending_writes
+            .iter()
+            .filter(|value| value.path == path)
+            .map(|value| value.change_id)
+            .collect();
+        match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                change_ids.extend(
+                    database
+                        .datastore_get_history(&self.name, &self.config, &path_refs, None)
+                        .iter()
+                        .map(|meta| meta.change_id),
+                );
+            }
+            DataStoreStorage::Memory(memory) => change_ids.extend(
+                memory
+                    .get_history(path, None)
+                    .iter()
+                    .map(|value| value.change_id),
+            ),
+        }
+        change_ids
+    }
+
+    /// Searches the text of the current values of a path and its subkeys
+    fn search(&self, path: &[String], query: &str) -> Vec<DatastoreSearchResult> {
+        match &self.storage {
+            DataStoreStorage::Database(database) => {
+                let path_refs: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+                database.datastore_search(&self.name, &self.config, &path_refs, query)
+            }
+            DataStoreStorage::Memory(memory) => memory.search(&self.config, path, query),
+        }
+    }
+
+    /// Stores a new value if the hooks allow it, then runs the triggers of its path and the hooks.
+    /// Values containing blob references are rejected, those are only written by `store_blob`.
+    /// Returns the stored value, or the reason the write was rejected.
+    fn store_value(
+        &mut self,
+        path: Vec<String>,
+        value: Option<T>,
+    ) -> Result<Arc<Value<T>>, DataStoreError> {
+        // a hand-made reference would give access to the blob of another path by its hash
+        if value
+            .as_ref()
+            .and_then(|x| serde_json::to_value(x).ok())
+            .is_some_and(|x| contains_blob_key(&x))
+        {
+            return Err(DataStoreError::InvalidData(format!(
+                "Values can't contain \"{}\" keys",
+                DATASTORE_BLOB_KEY
+            )));
+        }
+
+        self.store_value_with_blobs(path, value)
+    }
+
+    /// Stores a new value that may reference blobs if the hooks allow it, then runs the triggers of its path and the hooks.
+    /// Returns the stored value, or the reason a hook rejected the write.
+    fn store_value_with_blobs(
+        &mut self,
+        path: Vec<String>,
+        value: Option<T>,
+    ) -> Result<Arc<Value<T>>, DataStoreError> {
+        let value = match value {
+            Some(value) => Some(
+                self.hooks
+                    .iter_mut()
+                    .try_fold(value, |value, hook| hook.before_set(&path, value))
+                    .map_err(DataStoreError::Rejected)?,
+            ),
+            None => {
+                for hook in &mut self.hooks {
+                    hook.before_delete(&path)
+                        .map_err(DataStoreError::Rejected)?;
+                }
+                None
+            }
+        };
+
+        // triggers need the previous value to compute the difference
+        let has_triggers = self
+            .config
+            .triggers
+            .iter()
+            .any(|trigger| triggers::target_path(trigger, &path).is_some());
+        let previous = if has_triggers {
+            self.get_current(&path)
+        } else {
+            None
+        };
+
+        let value = self.write_value(path, value);
+
+        if has_triggers {
+            self.run_triggers(previous.as_ref().and_then(|x| x.value.as_ref()), &value);
+        }
+
+        for hook in &mut self.hooks {
+            if value.value.is_some() {
+                hook.after_set(&value);
+            } else {
+                hook.after_delete(&value);
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Updates the derived keys of the triggers matching a changed value.
+    /// Derived values are converted through JSON, so triggers are skipped if the value type can't hold the derived value.
+    /// Changes of derived keys don't run triggers.
+    fn run_triggers(&mut self, previous: Option<&T>, change: &Value<T>) {
+        let previous = previous.and_then(|x| serde_json::to_value(x).ok());
+        let change = Value {
+            value: change
+                .value
+                .as_ref()
+                .and_then(|x| serde_json::to_value(x).ok()),
+            path: change.path.clone(),
+            timestamp: change.timestamp,
+            change_id: change.change_id,
+        };
+
+        for trigger in self.config.triggers.clone() {
+            let Some(target) = triggers::target_path(&trigger, &change.path) else {
+                continue;
+            };
+
+            let derived = self
+                .get_current(&target)
+                .and_then(|x| x.value.as_ref().and_then(|x| serde_json::to_value(x).ok()));
+            let new_derived = triggers::derive(
+                &trigger.action,
+                previous.as_ref(),
+                &change,
+                derived.as_ref(),
+            )
+            .and_then(|x| serde_json::from_value(x).ok());
+
+            if let Some(new_derived) = new_derived {
+                self.write_value(target, Some(new_derived));
+            }
+        }
+    }
+
+    /// Stores a new value in the cache and adds it to the write batch.
+    /// Subscribers are notified once the batch is committed.
+    fn write_value(&mut self, path: Vec<String>, value: Option<T>) -> Arc<Value<T>> {
+        let value = Arc::new(Value {
+            value,
+            path,
+            timestamp: Utc::now(),
+            change_id: Uuid::new_v4(),
+        });
+        self.value_cache_by_change_id
+            .insert(value.change_id, Arc::clone(&value));
+
+        self.pending_writes.push(Arc::clone(&value));
+        self.batch_started.get_or_insert_with(Instant::now);
+
+        value
+    }
+
+    /// Adds the result of a set or delete request to the write batch
+    fn add_to_batch(
+        &mut self,
+        response_channel: Option<OneshotSender<Result<Uuid, DataStoreError>>>,
+        result: Result<Uuid, DataStoreError>,
+    ) {
+        self.pending_responses.push((response_channel, result));
+        self.batch_started.get_or_insert_with(Instant::now);
+    }
+
+    /// Gets the time the write batch must be committed by if no more writes arrive
+    fn batch_deadline(&self) -> Option<Instant> {
+        self.batch_started
+            .map(|started| started + Duration::from_millis(self.config.write_batch_max_latency))
+    }
+
+    /// Checks if the write batch has as many set and delete requests as allowed
+    fn batch_full(&self) -> bool {
+        self.pending_responses.len() >= self.config.write_batch_max_size
+    }
+
+    /// Writes the values of the write batch to the database in a single transaction,
+    /// then notifies subscribers and responds to the requests in the batch
+    fn commit_writes(&mut self) {
+        self.batch_started = None;
+
+        let values = std::mem::take(&mut self.pending_writes);
+        if !values.is_empty() {
+            match &mut self.storage {
+                DataStoreStorage::Database(database) => {
+                    let writes: Vec<DatastoreWrite> = values
+                        .iter()
+                        .map(|value| DatastoreWrite {
+                            path: value.path.clone(),
+                            value: value.value.as_ref().map(|value| {
+                                serde_json::to_string(value)
+                                    .expect("Error occurred while serializing data store value")
+                            }),
+                            change_id: value.change_id,
+                            timestamp: value.timestamp,
+                        })
+                        .collect();
+                    database.datastore_set_batch(&self.name, &self.config, &writes);
+                }
+                DataStoreStorage::Memory(memory) => memory.set_batch(&self.config, &values),
+            }
+
+            for value in &values {
+                self.notify_subscribers(value);
+            }
+        }
+
+        for (response_channel, result) in std::mem::take(&mut self.pending_responses) {
+            if let Some(response_channel) = response_channel {
+                response_channel.send(result).ok();
+            }
+        }
+    }
+
+    /// Stores a value if the current value has the expected change id.
+    /// Returns the new change id, or why the value wasn't stored.
+    fn store_value_if_current(
+        &mut self,
+        path: Vec<String>,
+        value: Option<T>,
+        expected_change_id: Uuid,
+    ) -> Result<Uuid, SetIfCurrentError<T>> {
+        let current = match self.get_current(&path) {
+            Some(current) => current,
+            None => Arc::new(Value::unset(path.clone())),
+        };
+        if current.change_id != expected_change_id {
+            return Err(SetIfCurrentError::Conflict(current));
+        }
+        self.store_value(path, value)
+            .map(|value| value.change_id)
+            .map_err(SetIfCurrentError::Error)
+    }
+
+    /// Stores binary content by its hash and a value referencing it.
+    /// Returns the change id, or the reason the write was rejected.
+    fn store_blob(
+        &mut self,
+        path: Vec<String>,
+        content_type: &str,
+        data: &[u8],
+    ) -> Result<Uuid, DataStoreError> {
+        let reference = BlobReference::new(content_type, data);
+        let value = serde_json::from_value(reference.to_value()).map_err(|_| {
+            DataStoreError::Rejected(String::from("Data store values can't reference blobs"))
+        })?;
+
+        // the blob is only stored once the hooks accepted the value, so rejected writes leave nothing behind
+        let value = self.store_value_with_blobs(path, Some(value))?;
+        match &mut self.storage {
+            DataStoreStorage::Database(database) => {
+                database.datastore_put_blob(&self.name, &self.config, &reference.hash, data)
+            }
+            DataStoreStorage::Memory(memory) => memory.put_blob(&reference.hash, data),
+        }
+        Ok(value.change_id)
+    }
+
+    /// Loads the binary content referenced by the current value of a path
+    fn load_blob(&mut self, path: &[String]) -> Option<(BlobReference, Vec<u8>)> {
+        let current = self.get_current(path)?;
+        let reference =
+            BlobReference::from_value(serde_json::to_value(current.value.as_ref()?).ok()?)?;
+        let data = match &self.storage {
+            DataStoreStorage::Database(database) => {
+                database.datastore_get_blob(&self.name, &self.config, &reference.hash)?
+            }
+            DataStoreStorage::Memory(memory) => memory.get_blob(&reference.hash)?,
+        };
+        Some((reference, data))
+    }
+
+    /// Stores a value computed from the current value.
+    /// Returns the new value, None if the update function left the value unchanged, or the reason a hook rejected the write.
+    fn update_value(
+        &mut self,
+        path: Vec<String>,
+        update: impl FnOnce(Option<&T>) -> Option<T>,
+    ) -> Result<Option<Arc<Value<T>>>, DataStoreError> {
+        let current = self.get_current(&path);
+        let Some(new_value) = update(current.as_ref().and_then(|x| x.value.as_ref())) else {
+            return Ok(None);
+        };
+        self.store_value(path, Some(new_value)).map(Some)
+    }
+
+    /// Stores a value with part of the current value taken out, keeping the function to put it back until the claim is completed.
+    /// Returns the claim id, None if nothing could be claimed, or the reason a hook rejected the write.
+    fn claim(
+        &mut self,
+        path: Vec<String>,
+        claim: ClaimFunction<T>,
+        visibility_timeout: Duration,
+    ) -> Result<Option<Uuid>, DataStoreError> {
+        // timeouts too large to represent never claim anything instead of panicking the thread
+        let Some(expiry) = Instant::now().checked_add(visibility_timeout) else {
+            return Ok(None);
+        };
+        let current = self.get_current(&path);
+        let Some((new_value, release)) = claim(current.as_ref().and_then(|x| x.value.as_ref()))
+        else {
+            return Ok(None);
+        };
+        self.store_value(path.clone(), Some(new_value))?;
+
+        let claim_id = Uuid::new_v4();
+        self.claims.insert(
+            claim_id,
+            ClaimRecord {
+                path,
+                expiry,
+                release,
+            },
+        );
+        Ok(Some(claim_id))
+    }
+
+    /// Gets the time the next claim expires
+    fn next_claim_expiry(&self) -> Option<Instant> {
+        self.claims.values().map(|claim| claim.expiry).min()
+    }
+
+    /// Puts back the claimed parts of values whose claims expired
+    fn release_expired_claims(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<Uuid> = self
+            .claims
+            .iter()
+            .filter(|(_, claim)| claim.expiry <= now)
+            .map(|(claim_id, _)| *claim_id)
+            .collect();
+
+        for claim_id in expired {
+            if let Some(mut claim) = self.claims.remove(&claim_id) {
+                // a release that was rejected or couldn't put the part back keeps the claim,
+                // so the claimed part isn't lost and can still be completed
+                if !matches!(
+                    self.update_value(claim.path.clone(), &claim.release),
+                    Ok(Some(_))
+                ) {
+                    claim.expiry = now + CLAIM_RELEASE_RETRY_INTERVAL;
+                    self.claims.insert(claim_id, claim);
+                }
+            }
+        }
+    }
+
+    /// Puts back the claimed parts of all values that have not been completed.
+    /// Releases that fail can't be tried again, as the data store is shutting down.
+    fn release_all_claims(&mut self) {
+        let claims: Vec<ClaimRecord<T>> = self.claims.drain().map(|(_, claim)| claim).collect();
+        for claim in claims {
+            self.update_value(claim.path, &claim.release).ok();
+        }
+    }
+
+    /// Adds a subscription, returning the subscription id
+    fn subscribe(
+        &mut self,
+        path: Vec<String>,
+        notification_channel: MPSCSender<Arc<Value<T>>>,
+    ) -> Uuid {
+        let record = Rc::new(SubscriptionRecord {
+            id: Uuid::new_v4(),
+            path,
+            notification_channel,
+        });
+        self.subscriptions_by_id
+            .insert(record.id, Rc::clone(&record));
+        self.subscriptions_by_path
+            .entry(record.path.clone())
+            .or_default()
+            .push(Rc::clone(&record));
+        record.id
+    }
+
+    /// Removes a subscription from the subscription lists
+    fn unsubscribe(&mut self, subscription_id: Uuid) {
+        if let Some(record) = self.subscriptions_by_id.remove(&subscription_id) {
+            if let Some(subscriptions) = self.subscriptions_by_path.get_mut(&record.path) {
+                subscriptions.retain(|x| x.id != subscription_id);
+                if subscriptions.is_empty() {
+                    self.subscriptions_by_path.remove(&record.path);
+                }
+            }
+        }
+    }
+
+    /// Sends a changed value to the subscribers of its path, removing subscriptions that were closed
+    fn notify_subscribers(&mut self, value: &Arc<Value<T>>) {
+        let closed: Vec<Uuid> = match self.subscriptions_by_path.get(&value.path) {
+            Some(subscriptions) => subscriptions
+                .iter()
+                .filter(|subscription| {
+                    subscription
+                        .notification_channel
+                        .send(Arc::clone(value))
+                        .is_err()
+                })
+                .map(|subscription| subscription.id)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        for subscription_id in closed {
+            self.unsubscribe(subscription_id);
+        }
+    }
+}
+
+/// Subscription to the changes of a path
 pub struct Subscription<T> {
     pub id: Uuid,
-    notification_channel: mpsc_async::Receiver<Arc<Value<T>>>,
-    datastore_channel: mpsc::Sender<DataStoreRequest<T>>,
+    notification_channel: mpsc_async::UnboundedReceiver<Arc<Value<T>>>,
+    datastore_channel: mpsc::SyncSender<DataStoreRequest<T>>,
 }
 
 impl<T> Subscription<T> {
-    pub async fn recv(&self) -> Result<Arc<Value<T>>, ()> {
-        todo!()
+    /// Waits for the next change.
+    /// Returns an error if the data store was shut down.
+    pub async fn recv(&mut self) -> Result<Arc<Value<T>>, DataStoreError> {
+        self.notification_channel
+            .recv()
+            .await
+            .ok_or(DataStoreError::Stopped)
     }
 
-    pub async fn recv_timeout(&self) -> Result<Option<Arc<Value<T>>>, ()> {
-        todo!()
+    /// Waits for the next change until the timeout elapses.
+    /// Returns None if no change happened before the timeout, or an error if the data store was shut down.
+    pub async fn recv_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Arc<Value<T>>>, DataStoreError> {
+        match tokio::time::timeout(timeout, self.notification_channel.recv()).await {
+            Ok(Some(value)) => Ok(Some(value)),
+            Ok(None) => Err(DataStoreError::Stopped),
+            Err(_) => Ok(None),
+        }
     }
 }
 impl<T> Drop for Subscription<T> {
     fn drop(&mut self) {
         // Once subscription is dropped, cancel it
         self.datastore_channel
-            .send(DataStoreRequest::Unsubscribe {
+            .try_send(DataStoreRequest::Unsubscribe {
                 subscription_id: self.id,
                 response_channel: None,
             })
@@ -0,0 +1,149 @@
+//! Path patterns for access rules, triggers and indexes
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Path pattern, e.g. "users/{uid}/**".
+/// A "{name}" segment matches any single key and captures it, a "*" segment matches any single key,
+/// and a trailing "**" segment matches any number of keys.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct PathPattern {
+    /// Pattern as written in the configuration
+    pattern: String,
+    /// Segments of the pattern
+    segments: Vec<PatternSegment>,
+}
+
+/// Segment of a path pattern
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatternSegment {
+    /// Matches the key
+    Key(String),
+    /// Matches any single key, capturing it under the name
+    Capture(String),
+    /// Matches any single key
+    Any,
+    /// Matches any number of keys
+    Rest,
+}
+
+impl PathPattern {
+    /// Parses a path pattern.
+    /// Returns an error if a "**" segment isn't the last one, since it would match any keys after it.
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let segments: Vec<PatternSegment> = pattern
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(|segment| match segment {
+                "*" => PatternSegment::Any,
+                "**" => PatternSegment::Rest,
+                _ => match segment.strip_prefix('{').and_then(|x| x.strip_suffix('}')) {
+                    Some(name) => PatternSegment::Capture(String::from(name)),
+                    None => PatternSegment::Key(String::from(segment)),
+                },
+            })
+            .collect();
+
+        if let Some(position) = segments.iter().position(|x| *x == PatternSegment::Rest) {
+            if position + 1 < segments.len() {
+                return Err(format!(
+                    "\"**\" can only be the last segment of path pattern \"{}\"",
+                    pattern
+                ));
+            }
+        }
+
+        Ok(Self {
+            pattern: String::from(pattern),
+            segments,
+        })
+    }
+
+    /// Gets the segments of the pattern
+    pub fn segments(&self) -> &[PatternSegment] {
+        &self.segments
+    }
+
+    /// Checks if a path matches the pattern
+    pub fn matches(&self, path: &[&str]) -> bool {
+        match_segments(&self.segments, path, &mut HashMap::new())
+    }
+
+    /// Matches a path against the pattern, returning the captured keys.
+    /// Returns None if the path doesn't match.
+    pub fn captures<'a>(&self, path: &[&'a str]) -> Option<HashMap<String, &'a str>> {
+        let mut captures = HashMap::new();
+        match_segments(&self.segments, path, &mut captures).then_some(captures)
+    }
+
+    /// Checks if the pattern matches every direct child of a path, e.g. "users/*" for "users"
+    pub fn matches_children(&self, path: &[&str]) -> bool {
+        match self.segments.split_last() {
+            Some((PatternSegment::Any | PatternSegment::Capture(_), parent)) => {
+                match_segments(parent, path, &mut HashMap::new())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for PathPattern {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Self::new(&pattern)
+    }
+}
+
+impl From<PathPattern> for String {
+    fn from(pattern: PathPattern) -> Self {
+        pattern.pattern
+    }
+}
+
+/// Matches a path against pattern segments, collecting the captured keys
+fn match_segments<'a>(
+    segments: &[PatternSegment],
+    path: &[&'a str],
+    captures: &mut HashMap<String, &'a str>,
+) -> bool {
+    match (segments.first(), path.first()) {
+        (None, None) => true,
+        (Some(PatternSegment::Rest), _) => true,
+        (Some(segment), Some(key)) => {
+            let matches = match segment {
+                PatternSegment::Key(pattern_key) => pattern_key == key,
+                PatternSegment::Capture(name) => {
+                    captures.insert(name.clone(), key);
+                    true
+                }
+                PatternSegment::Any | PatternSegment::Rest => true,
+            };
+            matches && match_segments(&segments[1..], &path[1..], captures)
+        }
+        _ => false,
+    }
+}
+
+/// Replaces the "{name}" placeholders of a template with the captured keys.
+/// Placeholders without a capture are left unchanged.
+pub fn expand_template(template: &str, captures: &HashMap<String, &str>) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            break;
+        };
+        let placeholder = &rest[start..start + length + 1];
+        expanded.push_str(&rest[..start]);
+        match captures.get(&placeholder[1..placeholder.len() - 1]) {
+            Some(key) => expanded.push_str(key),
+            None => expanded.push_str(placeholder),
+        }
+        rest = &rest[start + length + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
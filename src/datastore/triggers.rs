@@ -0,0 +1,112 @@
+//! Triggers keeping derived keys up to date
+
+use serde_json::json;
+
+use super::{add_numbers, patterns::expand_template, Value};
+use crate::config::{DatastoreTriggerAction, DatastoreTriggerConfig};
+
+/// Gets the path of the derived key updated by a trigger for a changed path.
+/// Returns None if the trigger doesn't apply to the path.
+pub(super) fn target_path(
+    trigger: &DatastoreTriggerConfig,
+    path: &[String],
+) -> Option<Vec<String>> {
+    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+    let captures = trigger.path.captures(&path)?;
+    let target = expand_template(&trigger.target, &captures);
+    let target: Vec<String> = target
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .map(String::from)
+        .collect();
+
+    // a trigger never updates the key that ran it
+    if target == path {
+        None
+    } else {
+        Some(target)
+    }
+}
+
+/// Computes the new derived value from the previous and new value of the changed key.
+/// Returns None if the derived value doesn't change or can't be updated.
+pub(super) fn derive(
+    action: &DatastoreTriggerAction,
+    previous: Option<&serde_json::Value>,
+    change: &Value<serde_json::Value>,
+    derived: Option<&serde_json::Value>,
+) -> Option<serde_json::Value> {
+    let current = change.value.as_ref();
+    match action {
+        DatastoreTriggerAction::Count => {
+            let delta = current.is_some() as i64 - previous.is_some() as i64;
+            if delta == 0 {
+                return None;
+            }
+            add_to_derived(derived, &serde_json::Number::from(delta))
+        }
+        DatastoreTriggerAction::Sum { field } => {
+            let added = current.and_then(|x| number_field(x, field));
+            let removed = previous.and_then(|x| number_field(x, field));
+            if added.is_none() && removed.is_none() {
+                return None;
+            }
+
+            let zero = serde_json::Number::from(0);
+            let removed = negate(removed.unwrap_or(&zero))?;
+            let delta = add_numbers(added.unwrap_or(&zero), &removed)?;
+            add_to_derived(derived, &delta)
+        }
+        DatastoreTriggerAction::Copy { field } => {
+            let copied = get_field(current?, field)?;
+            if derived == Some(copied) {
+                None
+            } else {
+                Some(copied.clone())
+            }
+        }
+        DatastoreTriggerAction::LastChange => Some(json!({
+            "path": change.path,
+            "timestamp": change.timestamp,
+            "change_id": change.change_id,
+        })),
+    }
+}
+
+/// Gets a field inside a value
+fn get_field<'a>(value: &'a serde_json::Value, field: &[String]) -> Option<&'a serde_json::Value> {
+    field.iter().try_fold(value, |value, key| value.get(key))
+}
+
+/// Gets a numeric field inside a value
+fn number_field<'a>(
+    value: &'a serde_json::Value,
+    field: &[String],
+) -> Option<&'a serde_json::Number> {
+    match get_field(value, field)? {
+        serde_json::Value::Number(number) => Some(number),
+        _ => None,
+    }
+}
+
+/// Adds to a derived number, treating an unset value as 0.
+/// Returns None if the derived value is not a number.
+fn add_to_derived(
+    derived: Option<&serde_json::Value>,
+    delta: &serde_json::Number,
+) -> Option<serde_json::Value> {
+    let sum = match derived {
+        Some(serde_json::Value::Number(derived)) => add_numbers(derived, delta)?,
+        Some(_) => return None,
+        None => delta.clone(),
+    };
+    Some(serde_json::Value::Number(sum))
+}
+
+/// Negates a number
+fn negate(number: &serde_json::Number) -> Option<serde_json::Number> {
+    match number.as_i64().and_then(i64::checked_neg) {
+        Some(negated) => Some(negated.into()),
+        None => serde_json::Number::from_f64(-number.as_f64()?),
+    }
+}
@@ -0,0 +1,313 @@
+//! Authentication endpoint
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::auth_admin::{empty_response, error_response};
+use crate::auth::{
+    api_keys::is_api_key, request_token, Auth, AuthIdentity, LoginStep, SESSION_COOKIE,
+};
+
+/// Creates the router for an authentication endpoint
+pub fn route(auth: Auth) -> Router {
+    Router::new()
+        .route("/login", post(login))
+        .route("/login/second-factor", post(confirm_login))
+        .route("/logout", post(logout))
+        .route("/me", get(me))
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/:name", delete(revoke_api_key))
+        .route("/totp", post(start_totp_enrollment).delete(disable_totp))
+        .route("/totp/confirm", post(confirm_totp_enrollment))
+        .route("/totp/recovery-codes", post(regenerate_recovery_codes))
+        .with_state(auth)
+}
+
+/// Login request body
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+/// Login response body
+#[derive(Serialize)]
+struct LoginResponse {
+    /// Session token to send as bearer token, also set as HttpOnly cookie
+    token: String,
+}
+
+/// Login response body of users with a second factor
+#[derive(Serialize)]
+struct SecondFactorRequiredResponse {
+    second_factor_required: bool,
+    /// Token to send with the second factor code to complete the login
+    challenge: String,
+}
+
+/// Second login step request body
+#[derive(Deserialize)]
+struct ConfirmLoginRequest {
+    challenge: String,
+    /// TOTP or recovery code
+    code: String,
+}
+
+/// Second factor code request body
+#[derive(Deserialize)]
+struct CodeRequest {
+    code: String,
+}
+
+/// Recovery codes response body
+#[derive(Serialize)]
+struct RecoveryCodesResponse {
+    /// Single use codes to log in with if the authenticator app is lost, not shown again
+    recovery_codes: Vec<String>,
+}
+
+/// Create API key request body
+#[derive(Deserialize)]
+struct CreateApiKeyRequest {
+    name: String,
+    /// Roles of the user the key grants, all of them if missing
+    roles: Option<Vec<String>>,
+    /// Time the key stops working, never if missing
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Identity response body
+#[derive(Serialize)]
+struct MeResponse<'a> {
+    username: &'a str,
+    roles: &'a [String],
+    second_factor: bool,
+}
+
+/// Starts a session if the username and password are correct.
+/// Users with a second factor get a login challenge to confirm instead.
+async fn login(State(auth): State<Auth>, Json(request): Json<LoginRequest>) -> Response {
+    match auth.login(&request.username, &request.password).await {
+        Some(LoginStep::Session(token)) => session_response(token),
+        Some(LoginStep::SecondFactor(challenge)) => Json(SecondFactorRequiredResponse {
+            second_factor_required: true,
+            challenge,
+        })
+        .into_response(),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Starts a session if the second factor code of a login challenge is correct
+async fn confirm_login(
+    State(auth): State<Auth>,
+    Json(request): Json<ConfirmLoginRequest>,
+) -> Response {
+    match auth.confirm_login(&request.challenge, &request.code).await {
+        Some(token) => session_response(token),
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Responds with a new session token and sets it as session cookie
+fn session_response(token: String) -> Response {
+    (
+        [(
+            header::SET_COOKIE,
+            format!(
+                "{}={}; Path=/; HttpOnly; SameSite=Strict",
+                SESSION_COOKIE, token
+            ),
+        )],
+        Json(LoginResponse { token }),
+    )
+        .into_response()
+}
+
+/// Ends the session of the request and clears the session cookie
+async fn logout(State(auth): State<Auth>, headers: HeaderMap) -> Response {
+    if let Some(token) = request_token(&headers) {
+        auth.logout(&token).await;
+    }
+
+    (
+        StatusCode::NO_CONTENT,
+        [(
+            header::SET_COOKIE,
+            format!(
+                "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0",
+                SESSION_COOKIE
+            ),
+        )],
+    )
+        .into_response()
+}
+
+/// Gets the username and roles of the logged in user
+async fn me(identity: Option<Extension<AuthIdentity>>) -> Response {
+    match identity {
+        Some(Extension(AuthIdentity {
+            username: Some(username),
+            roles,
+            second_factor,
+        })) => Json(MeResponse {
+            username: &username,
+            roles: &roles,
+            second_factor,
+        })
+        .into_response(),
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Lists the API keys of the logged in user
+async fn list_api_keys(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+    match auth.list_api_keys(username).await {
+        Ok(api_keys) => Json(api_keys).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Creates an API key for the logged in user and responds with the key, which isn't shown again
+async fn create_api_key(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+
+    match auth
+        .create_api_key(
+            username,
+            &request.name,
+            request.roles.as_deref(),
+            request.expires,
+        )
+        .await
+    {
+        Ok(api_key) => (StatusCode::CREATED, Json(api_key)).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Deletes an API key of the logged in user
+async fn revoke_api_key(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+    empty_response(auth.revoke_api_key(username, &name).await)
+}
+
+/// Starts the TOTP enrollment of the logged in user.
+/// Responds with the secret to add to an authenticator app, which is required at login once confirmed.
+async fn start_totp_enrollment(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+    match auth.start_totp_enrollment(username).await {
+        Ok(enrollment) => Json(enrollment).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Confirms the TOTP enrollment of the logged in user with a code from the authenticator app.
+/// Responds with the recovery codes.
+async fn confirm_totp_enrollment(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+    Json(request): Json<CodeRequest>,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+    match auth.confirm_totp_enrollment(username, &request.code).await {
+        Ok(recovery_codes) => Json(RecoveryCodesResponse { recovery_codes }).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Replaces the recovery codes of the logged in user after checking a TOTP code
+async fn regenerate_recovery_codes(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+    Json(request): Json<CodeRequest>,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+    match auth
+        .regenerate_recovery_codes(username, &request.code)
+        .await
+    {
+        Ok(recovery_codes) => Json(RecoveryCodesResponse { recovery_codes }).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Removes the second factor of the logged in user after checking a TOTP or recovery code
+async fn disable_totp(
+    State(auth): State<Auth>,
+    identity: Option<Extension<AuthIdentity>>,
+    headers: HeaderMap,
+    Json(request): Json<CodeRequest>,
+) -> Response {
+    let username = match session_user(&identity, &headers) {
+        Ok(username) => username,
+        Err(status) => return status.into_response(),
+    };
+    empty_response(auth.disable_totp(username, &request.code).await)
+}
+
+/// Gets the username of a user logged in with a session rather than an API key.
+/// API keys can't manage credentials, so a key limited to some roles can't be used to get the others.
+fn session_user<'a>(
+    identity: &'a Option<Extension<AuthIdentity>>,
+    headers: &HeaderMap,
+) -> Result<&'a str, StatusCode> {
+    let Some(username) = user(identity) else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    if request_token(headers).is_some_and(|token| is_api_key(&token)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(username)
+}
+
+/// Gets the username of the logged in user
+fn user(identity: &Option<Extension<AuthIdentity>>) -> Option<&str> {
+    identity.as_ref()?.username.as_deref()
+}
@@ -0,0 +1,221 @@
+//! Authentication administration endpoint
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, put},
+    Json, Router,
+};
+use serde::Deserialize;
+
+use crate::auth::{self, admin::AuthAdminError, Auth};
+
+/// Creates the router for an authentication administration endpoint.
+/// Route permissions are checked before the requests get here.
+pub fn route(auth: Auth, require_second_factor: bool) -> Router {
+    let router = Router::new()
+        .route("/users", get(list_users).post(create_user))
+        .route("/users/:username", get(get_user))
+        .route("/users/:username/active", put(set_user_active))
+        .route("/users/:username/password", put(reset_password))
+        .route(
+            "/users/:username/roles/:role",
+            put(assign_role).delete(unassign_role),
+        )
+        .route(
+            "/users/:username/sessions",
+            get(list_sessions).delete(revoke_sessions),
+        )
+        .route(
+            "/users/:username/sessions/:session_id",
+            delete(revoke_session),
+        )
+        .route(
+            "/users/:username/second-factor",
+            delete(reset_second_factor),
+        )
+        .route("/users/:username/api-keys", get(list_api_keys))
+        .route("/users/:username/api-keys/:name", delete(revoke_api_key))
+        .route("/roles", get(list_roles).post(create_role))
+        .route("/roles/:role", delete(delete_role))
+        .with_state(auth);
+
+    if require_second_factor {
+        router.layer(middleware::from_fn(auth::require_second_factor))
+    } else {
+        router
+    }
+}
+
+/// Create user request body
+#[derive(Deserialize)]
+struct CreateUserRequest {
+    username: String,
+    /// Initial password, the user can't log in without one
+    password: Option<String>,
+    /// Names of existing roles to give the user
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Reset password request body
+#[derive(Deserialize)]
+struct ResetPasswordRequest {
+    /// New password, null to remove the password
+    password: Option<String>,
+}
+
+/// Create role request body
+#[derive(Deserialize)]
+struct CreateRoleRequest {
+    name: String,
+}
+
+/// Lists all users with their roles
+async fn list_users(State(auth): State<Auth>) -> Response {
+    Json(auth.list_users().await).into_response()
+}
+
+/// Creates a user
+async fn create_user(State(auth): State<Auth>, Json(request): Json<CreateUserRequest>) -> Response {
+    match auth
+        .create_user(
+            &request.username,
+            request.password.as_deref(),
+            &request.roles,
+        )
+        .await
+    {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Gets a user with its roles
+async fn get_user(State(auth): State<Auth>, Path(username): Path<String>) -> Response {
+    match auth.get_user(&username).await {
+        Ok(user) => Json(user).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Enables or disables a user
+async fn set_user_active(
+    State(auth): State<Auth>,
+    Path(username): Path<String>,
+    Json(active): Json<bool>,
+) -> Response {
+    empty_response(auth.set_user_active(&username, active).await)
+}
+
+/// Sets a new password for a user, ending the user's sessions
+async fn reset_password(
+    State(auth): State<Auth>,
+    Path(username): Path<String>,
+    Json(request): Json<ResetPasswordRequest>,
+) -> Response {
+    empty_response(
+        auth.reset_password(&username, request.password.as_deref())
+            .await,
+    )
+}
+
+/// Gives a user a role
+async fn assign_role(
+    State(auth): State<Auth>,
+    Path((username, role)): Path<(String, String)>,
+) -> Response {
+    empty_response(auth.assign_role(&username, &role).await)
+}
+
+/// Takes a role from a user
+async fn unassign_role(
+    State(auth): State<Auth>,
+    Path((username, role)): Path<(String, String)>,
+) -> Response {
+    empty_response(auth.unassign_role(&username, &role).await)
+}
+
+/// Lists the sessions of a user
+async fn list_sessions(State(auth): State<Auth>, Path(username): Path<String>) -> Response {
+    match auth.list_sessions(&username).await {
+        Ok(sessions) => Json(sessions).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Ends all sessions of a user
+async fn revoke_sessions(State(auth): State<Auth>, Path(username): Path<String>) -> Response {
+    empty_response(auth.revoke_sessions(&username).await)
+}
+
+/// Ends a session of a user
+async fn revoke_session(
+    State(auth): State<Auth>,
+    Path((username, session_id)): Path<(String, i64)>,
+) -> Response {
+    empty_response(auth.revoke_session(&username, session_id).await)
+}
+
+/// Removes the second factor of a user that lost it
+async fn reset_second_factor(State(auth): State<Auth>, Path(username): Path<String>) -> Response {
+    empty_response(auth.reset_second_factor(&username).await)
+}
+
+/// Lists the API keys of a user
+async fn list_api_keys(State(auth): State<Auth>, Path(username): Path<String>) -> Response {
+    match auth.list_api_keys(&username).await {
+        Ok(api_keys) => Json(api_keys).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Deletes an API key of a user
+async fn revoke_api_key(
+    State(auth): State<Auth>,
+    Path((username, name)): Path<(String, String)>,
+) -> Response {
+    empty_response(auth.revoke_api_key(&username, &name).await)
+}
+
+/// Lists all roles
+async fn list_roles(State(auth): State<Auth>) -> Response {
+    Json(auth.list_roles().await).into_response()
+}
+
+/// Creates a role
+async fn create_role(State(auth): State<Auth>, Json(request): Json<CreateRoleRequest>) -> Response {
+    match auth.create_role(&request.name).await {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Deletes a role, taking it from all users
+async fn delete_role(State(auth): State<Auth>, Path(role): Path<String>) -> Response {
+    empty_response(auth.delete_role(&role).await)
+}
+
+/// Responds without content if the operation succeeded
+pub(super) fn empty_response(result: Result<(), AuthAdminError>) -> Response {
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Responds with the reason an operation failed
+pub(super) fn error_response(error: AuthAdminError) -> Response {
+    let status = match error {
+        AuthAdminError::UserNotFound
+        | AuthAdminError::RoleNotFound
+        | AuthAdminError::SessionNotFound
+        | AuthAdminError::ApiKeyNotFound
+        | AuthAdminError::SecondFactorNotFound => StatusCode::NOT_FOUND,
+        AuthAdminError::RoleNotGranted | AuthAdminError::InvalidCode => StatusCode::FORBIDDEN,
+        AuthAdminError::AlreadyExists => StatusCode::CONFLICT,
+    };
+    (status, error.to_string()).into_response()
+}
@@ -0,0 +1,684 @@
+//! Datastore endpoint
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{
+    auth::{deny, AuthIdentity},
+    config::RoutePermissionValue,
+    database::models::datastore::{
+        DatastoreQueryFilter, DatastoreQueryOperation, DatastoreQuerySort,
+    },
+    datastore::{access::DataAccessRules, DataStore, DataStoreError, SetIfCurrentError, Value},
+};
+
+/// State of a datastore endpoint
+#[derive(Clone)]
+struct DataEndpoint {
+    /// Datastore the endpoint serves
+    datastore: DataStore<serde_json::Value>,
+    /// Access rules for the paths of the datastore
+    rules: Arc<DataAccessRules>,
+    /// Route read permission, needed by updates that return stored values
+    read: Arc<RoutePermissionValue>,
+    /// Permission to export and import the datastore
+    admin: Arc<RoutePermissionValue>,
+    /// Changes to true when the server is shutting down
+    shutdown: watch::Receiver<bool>,
+}
+
+/// Creates the router for a datastore endpoint
+pub fn route(
+    datastore: DataStore<serde_json::Value>,
+    rules: DataAccessRules,
+    read: RoutePermissionValue,
+    admin: RoutePermissionValue,
+    shutdown: watch::Receiver<bool>,
+) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(get_value)
+                .put(set_value)
+                .post(update_value)
+                .delete(delete_value),
+        )
+        .route(
+            "/*path",
+            get(get_value)
+                .put(set_value)
+                .post(update_value)
+                .delete(delete_value),
+        )
+        .with_state(DataEndpoint {
+            datastore,
+            rules: Arc::new(rules),
+            read: Arc::new(read),
+            admin: Arc::new(admin),
+            shutdown,
+        })
+}
+
+/// Query parameters for get requests
+#[derive(Deserialize)]
+struct GetParams {
+    /// Full-text search query.
+    /// If set, the path and its subkeys are searched instead of getting the value.
+    search: Option<String>,
+    /// Cursor returned by the previous changes request, 0 to get all changes.
+    /// If set, the changes of the path and its subkeys are returned instead of the value.
+    changes_since: Option<u64>,
+    /// Maximum number of changes or queried values to return
+    limit: Option<u64>,
+    /// If set, the readable subkeys are listed instead of getting the value
+    list: Option<String>,
+    /// If set, the current values of the readable subkeys are queried instead of getting the value
+    query: Option<String>,
+    /// JSON list of filters the queried values have to match
+    filter: Option<String>,
+    /// Field inside the queried values to sort by, with keys separated by "/" (sorted by key if not set)
+    sort: Option<String>,
+    /// If set, queried values are sorted in descending order
+    descending: Option<String>,
+    /// Cursor returned by the previous query request, to get the next page
+    cursor: Option<u64>,
+    /// Waits up to this many seconds for changes of the value instead of getting the value
+    wait: Option<u64>,
+    /// Change id of the last value seen when waiting for changes.
+    /// Changes after it are returned without waiting.
+    after: Option<Uuid>,
+    /// If set, the binary content referenced by the value is returned with its content type
+    blob: Option<String>,
+    /// If set, the path and its subkeys are exported as JSON Lines (requires the admin permission)
+    export: Option<String>,
+    /// If set, the export includes previous values and deletions
+    history: Option<String>,
+}
+
+/// Response of changes requests
+#[derive(Serialize)]
+struct ChangesResponse<'a> {
+    /// Changed values in the order the changes happened
+    changes: Vec<&'a Value<serde_json::Value>>,
+    /// Cursor to get the following changes with
+    next_cursor: u64,
+}
+
+/// Filter of query requests
+#[derive(Deserialize)]
+struct QueryFilter {
+    /// Path of the field inside the value, empty for the value itself
+    #[serde(default)]
+    field: Vec<String>,
+    /// Comparison to apply to the field
+    op: QueryOperation,
+    /// Value to compare the field with
+    value: serde_json::Value,
+}
+
+/// Comparison of query filters
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum QueryOperation {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+impl From<QueryFilter> for DatastoreQueryFilter {
+    fn from(filter: QueryFilter) -> Self {
+        let value = filter.value;
+        DatastoreQueryFilter {
+            field: filter.field,
+            operation: match filter.op {
+                QueryOperation::Eq => DatastoreQueryOperation::Eq(value),
+                QueryOperation::Lt => DatastoreQueryOperation::Lt(value),
+                QueryOperation::Le => DatastoreQueryOperation::Le(value),
+                QueryOperation::Gt => DatastoreQueryOperation::Gt(value),
+                QueryOperation::Ge => DatastoreQueryOperation::Ge(value),
+                QueryOperation::Contains => DatastoreQueryOperation::Contains(value),
+            },
+        }
+    }
+}
+
+/// Response of query requests
+#[derive(Serialize)]
+struct QueryResponse<'a> {
+    /// Readable values in the page
+    values: Vec<&'a Value<serde_json::Value>>,
+    /// Cursor to get the next page with, None if there are no more values
+    next_cursor: Option<u64>,
+}
+
+/// Gets the current value of a key, or searches, lists, queries, waits for or gets the changes of a key and its subkeys
+async fn get_value(
+    State(endpoint): State<DataEndpoint>,
+    identity: Option<Extension<AuthIdentity>>,
+    path: Option<Path<String>>,
+    Query(params): Query<GetParams>,
+) -> Response {
+    let identity = identity.map(|Extension(x)| x).unwrap_or_default();
+    let path = split_path(&path);
+    let path: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+    let rules = &endpoint.rules;
+
+    // results of requests covering subkeys only include the readable paths
+    let can_read = |path: &[String]| {
+        let path: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+        rules.can_read(&path, &identity)
+    };
+
+    if params.export.is_some() {
+        if !identity.has_permission(&endpoint.admin) {
+            return deny(&identity);
+        }
+        return match endpoint
+            .datastore
+            .export(&path, params.history.is_some())
+            .await
+        {
+            Ok(lines) => ([(header::CONTENT_TYPE, "application/x-ndjson")], lines).into_response(),
+            Err(error) => error_response(error),
+        };
+    }
+
+    if let Some(query) = params.search {
+        let mut results = match endpoint.datastore.search(&path, &query).await {
+            Ok(results) => results,
+            Err(error) => return error_response(error),
+        };
+        results.retain(|result| can_read(&result.path));
+        return Json(results).into_response();
+    }
+
+    if let Some(cursor) = params.changes_since {
+        let result = match endpoint
+            .datastore
+            .changes_since(&path, cursor, params.limit)
+            .await
+        {
+            Ok(result) => result,
+            Err(error) => return error_response(error),
+        };
+        return Json(ChangesResponse {
+            changes: result
+                .changes
+                .iter()
+                .filter(|value| can_read(&value.path))
+                .map(|value| &**value)
+                .collect(),
+            next_cursor: result.next_cursor,
+        })
+        .into_response();
+    }
+
+    if params.list.is_some() {
+        let mut keys = match endpoint.datastore.list(&path).await {
+            Ok(keys) => keys,
+            Err(error) => return error_response(error),
+        };
+        keys.retain(|key| {
+            let mut key_path = path.clone();
+            key_path.push(key);
+            rules.can_read(&key_path, &identity)
+        });
+        return Json(keys).into_response();
+    }
+
+    if params.query.is_some() {
+        let filters: Vec<DatastoreQueryFilter> = match &params.filter {
+            Some(filter) => match serde_json::from_str::<Vec<QueryFilter>>(filter) {
+                Ok(filters) => filters
+                    .into_iter()
+                    .map(DatastoreQueryFilter::from)
+                    .collect(),
+                Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+            },
+            None => Vec::new(),
+        };
+        let sort = params.sort.map(|field| DatastoreQuerySort {
+            field: field
+                .split('/')
+                .filter(|x| !x.is_empty())
+                .map(String::from)
+                .collect(),
+            descending: params.descending.is_some(),
+        });
+        let result = match endpoint
+            .datastore
+            .query(&path, &filters, sort, params.limit, params.cursor)
+            .await
+        {
+            Ok(result) => result,
+            Err(error) => return error_response(error),
+        };
+        return Json(QueryResponse {
+            values: result
+                .values
+                .iter()
+                .filter(|value| can_read(&value.path))
+                .map(|value| &**value)
+                .collect(),
+            next_cursor: result.next_cursor,
+        })
+        .into_response();
+    }
+
+    if !rules.can_read(&path, &identity) {
+        return deny(&identity);
+    }
+
+    if let Some(timeout) = params.wait {
+        // subscribe before checking for missed changes so none are lost in between
+        let mut subscription = match endpoint.datastore.subscribe(&path).await {
+            Ok(subscription) => subscription,
+            Err(error) => return error_response(error),
+        };
+        if params.after.is_some() {
+            let values = match endpoint.datastore.get_all(&path, params.after).await {
+                Ok(values) => values,
+                Err(error) => return error_response(error),
+            };
+            let values: Vec<&Value<serde_json::Value>> = values
+                .iter()
+                .map(|value| &**value)
+                .filter(|value| Some(value.change_id) != params.after)
+                .collect();
+            if !values.is_empty() {
+                return Json(values).into_response();
+            }
+        }
+
+        // waiting requests are ended when the server shuts down so they don't hold it up
+        let mut shutdown = endpoint.shutdown.clone();
+        return tokio::select! {
+            result = subscription.recv_timeout(Duration::from_secs(timeout)) => match result {
+                Ok(Some(value)) => Json([&*value]).into_response(),
+                Ok(None) => Json(Vec::<Value<serde_json::Value>>::new()).into_response(),
+                Err(error) => error_response(error),
+            },
+            _ = shutdown.wait_for(|shutting_down| *shutting_down) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server is shutting down",
+            )
+                .into_response(),
+        };
+    }
+
+    if params.blob.is_some() {
+        return match endpoint.datastore.get_blob(&path).await {
+            Ok(Some((reference, data))) => (
+                // uploaded content is downloaded rather than rendered, so it can't run scripts on this origin
+                [
+                    (header::CONTENT_TYPE, reference.content_type),
+                    (header::ETAG, format!("\"{}\"", reference.hash)),
+                    (header::X_CONTENT_TYPE_OPTIONS, String::from("nosniff")),
+                    (header::CONTENT_DISPOSITION, String::from("attachment")),
+                ],
+                data,
+            )
+                .into_response(),
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(error) => error_response(error),
+        };
+    }
+
+    let value = match endpoint.datastore.get_current(&path).await {
+        Ok(value) => value,
+        Err(error) => return error_response(error),
+    };
+    if value.value.is_some() {
+        Json(&*value).into_response()
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+/// Sets the value of a key
+async fn set_value(
+    State(endpoint): State<DataEndpoint>,
+    identity: Option<Extension<AuthIdentity>>,
+    path: Option<Path<String>>,
+    Query(params): Query<PutParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let identity = identity.map(|Extension(x)| x).unwrap_or_default();
+    let path = split_path(&path);
+    let path: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+
+    if !endpoint.rules.can_write(&path, &identity) {
+        return deny(&identity);
+    }
+
+    if params.blob.is_some() {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("application/octet-stream");
+        return write_response(
+            endpoint
+                .datastore
+                .set_blob(&path, content_type, body.to_vec())
+                .await,
+        );
+    }
+
+    let Ok(value) = serde_json::from_slice(&body) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    write_response(endpoint.datastore.set(&path, value).await)
+}
+
+/// Query parameters for put requests
+#[derive(Deserialize)]
+struct PutParams {
+    /// Stores the request body as binary content with the request content type
+    blob: Option<String>,
+}
+
+/// Query parameters for post requests
+#[derive(Deserialize)]
+struct PostParams {
+    /// Amount to atomically add to the number stored at the key, an integer or a decimal number
+    increment: Option<String>,
+    /// Appends the request body to the list stored at the key
+    push: Option<String>,
+    /// Removes the last item of the list stored at the key
+    pop: Option<String>,
+    /// Inserts the request body into the list stored at the key at this index
+    insert_at: Option<usize>,
+    /// Removes the item at this index from the list stored at the key
+    remove_at: Option<usize>,
+    /// Claims the first item of the list stored at the key for this many seconds
+    claim: Option<u64>,
+    /// Completes the claim with this id made on the key
+    complete: Option<Uuid>,
+    /// Applies the list of collaborative text operations in the request body to the text stored at the key
+    text_operations: Option<String>,
+    /// Applies the list of writes queued by an offline client in the request body
+    sync: Option<String>,
+    /// Imports the JSON Lines export in the request body (requires the admin permission)
+    import: Option<String>,
+}
+
+/// Path of an imported record
+#[derive(Deserialize)]
+struct ImportRecordPath {
+    path: Vec<String>,
+}
+
+/// Write queued by an offline client
+#[derive(Deserialize)]
+struct SyncWrite {
+    /// Path of the key relative to the request path
+    path: Vec<String>,
+    /// Value to set, null to delete
+    value: Option<serde_json::Value>,
+    /// Change id of the value the client last saw, the nil uuid if the key was unset
+    base_change_id: Uuid,
+}
+
+/// Outcome of a write queued by an offline client
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum SyncResult<'a> {
+    /// The write was applied
+    Applied { change_id: Uuid },
+    /// The key changed since the client last saw it, so the write was not applied
+    Conflict {
+        current: &'a Value<serde_json::Value>,
+    },
+    /// The client is not allowed to read and change the key
+    Forbidden,
+    /// A hook rejected the write for the reason
+    Rejected { reason: &'a str },
+    /// The value is not valid for the reason
+    Invalid { reason: &'a str },
+    /// The datastore couldn't handle the write, it can be retried later
+    Unavailable,
+}
+
+/// Atomically updates the value of a key
+async fn update_value(
+    State(endpoint): State<DataEndpoint>,
+    identity: Option<Extension<AuthIdentity>>,
+    path: Option<Path<String>>,
+    Query(params): Query<PostParams>,
+    body: Bytes,
+) -> Response {
+    let identity = identity.map(|Extension(x)| x).unwrap_or_default();
+    let path = split_path(&path);
+    let path: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+    let datastore = &endpoint.datastore;
+    let rules = &endpoint.rules;
+
+    if params.import.is_some() {
+        return import_values(&endpoint, &identity, &path, &body).await;
+    }
+
+    // the route only checks the write permission of post requests,
+    // but the operations return (part of) the stored values, so they also require read access
+    if !identity.has_permission(&endpoint.read) {
+        return deny(&identity);
+    }
+
+    // sync requests check the access of each write instead
+    if params.sync.is_none()
+        && !(rules.can_read(&path, &identity) && rules.can_write(&path, &identity))
+    {
+        return deny(&identity);
+    }
+
+    // operations that change the value return the new value
+    // operations that take an item return the item
+    // None means the current value isn't the right type or there is nothing to take
+    let result = if let Some(delta) = params.increment {
+        let Some(delta) = parse_number(&delta) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        datastore
+            .increment(&path, delta)
+            .await
+            .map(|value| value.map(|value| Json(&*value).into_response()))
+    } else if params.push.is_some() {
+        let Ok(item) = serde_json::from_slice(&body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        datastore
+            .push(&path, item)
+            .await
+            .map(|value| value.map(|value| Json(&*value).into_response()))
+    } else if params.pop.is_some() {
+        datastore
+            .pop(&path)
+            .await
+            .map(|item| item.map(|item| Json(item).into_response()))
+    } else if let Some(index) = params.insert_at {
+        let Ok(item) = serde_json::from_slice(&body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        datastore
+            .insert_at(&path, index, item)
+            .await
+            .map(|value| value.map(|value| Json(&*value).into_response()))
+    } else if let Some(index) = params.remove_at {
+        datastore
+            .remove_at(&path, index)
+            .await
+            .map(|item| item.map(|item| Json(item).into_response()))
+    } else if let Some(visibility_timeout) = params.claim {
+        datastore
+            .claim(&path, Duration::from_secs(visibility_timeout))
+            .await
+            .map(|claim| claim.map(|claim| Json(claim).into_response()))
+    } else if params.text_operations.is_some() {
+        let Ok(operations) = serde_json::from_slice(&body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        datastore
+            .apply_text_operations(&path, operations)
+            .await
+            .map(|value| value.map(|value| Json(&*value).into_response()))
+    } else if params.sync.is_some() {
+        let Ok(writes) = serde_json::from_slice::<Vec<SyncWrite>>(&body) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+
+        // writes are applied in order, each only if its key didn't change since the client saw it
+        let mut results = Vec::new();
+        for write in writes {
+            let mut write_path = path.clone();
+            write_path.extend(write.path.iter().map(|x| x.as_str()));
+            if !(rules.can_read(&write_path, &identity) && rules.can_write(&write_path, &identity))
+            {
+                results.push(None);
+                continue;
+            }
+            results.push(Some(
+                datastore
+                    .set_if_current(&write_path, write.value, write.base_change_id)
+                    .await,
+            ));
+        }
+
+        let results: Vec<SyncResult> = results
+            .iter()
+            .map(|result| match result {
+                Some(Ok(change_id)) => SyncResult::Applied {
+                    change_id: *change_id,
+                },
+                Some(Err(SetIfCurrentError::Conflict(current))) => SyncResult::Conflict { current },
+                Some(Err(SetIfCurrentError::Error(DataStoreError::Rejected(reason)))) => {
+                    SyncResult::Rejected { reason }
+                }
+                Some(Err(SetIfCurrentError::Error(DataStoreError::InvalidData(reason)))) => {
+                    SyncResult::Invalid { reason }
+                }
+                Some(Err(SetIfCurrentError::Error(
+                    DataStoreError::Overloaded | DataStoreError::Timeout | DataStoreError::Stopped,
+                ))) => SyncResult::Unavailable,
+                None => SyncResult::Forbidden,
+            })
+            .collect();
+        Ok(Some(Json(results).into_response()))
+    } else if let Some(claim_id) = params.complete {
+        datastore
+            .complete_claim(&path, claim_id)
+            .await
+            .map(|completed| completed.then(|| StatusCode::NO_CONTENT.into_response()))
+    } else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match result {
+        Ok(Some(response)) => response,
+        Ok(None) => StatusCode::CONFLICT.into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Deletes the value of a key
+async fn delete_value(
+    State(endpoint): State<DataEndpoint>,
+    identity: Option<Extension<AuthIdentity>>,
+    path: Option<Path<String>>,
+) -> Response {
+    let identity = identity.map(|Extension(x)| x).unwrap_or_default();
+    let path = split_path(&path);
+    let path: Vec<&str> = path.iter().map(|x| x.as_str()).collect();
+
+    if !endpoint.rules.can_write(&path, &identity) {
+        return deny(&identity);
+    }
+
+    write_response(endpoint.datastore.delete(&path).await)
+}
+
+/// Imports a JSON Lines export whose records are all inside the path
+async fn import_values(
+    endpoint: &DataEndpoint,
+    identity: &AuthIdentity,
+    path: &[&str],
+    body: &[u8],
+) -> Response {
+    if !identity.has_permission(&endpoint.admin) {
+        return deny(identity);
+    }
+
+    // invalid records are reported by the import itself
+    let prefix: Vec<String> = path.iter().map(|x| x.to_string()).collect();
+    let outside_path = body
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| serde_json::from_slice::<ImportRecordPath>(line).ok())
+        .any(|record| !record.path.starts_with(&prefix));
+    if outside_path {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Imported records must be inside the request path",
+        )
+            .into_response();
+    }
+
+    match endpoint.datastore.import(body).await {
+        Ok(imported) => Json(imported).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Responds with the change id of a write, or the reason it failed
+fn write_response(result: Result<Uuid, DataStoreError>) -> Response {
+    match result {
+        Ok(change_id) => Json(change_id).into_response(),
+        Err(error) => error_response(error),
+    }
+}
+
+/// Responds with the reason a datastore request failed.
+/// Requests the datastore couldn't handle in time can be retried later.
+fn error_response(error: DataStoreError) -> Response {
+    match error {
+        DataStoreError::Rejected(reason) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, reason).into_response()
+        }
+        DataStoreError::InvalidData(reason) => (StatusCode::BAD_REQUEST, reason).into_response(),
+        DataStoreError::Overloaded | DataStoreError::Timeout | DataStoreError::Stopped => {
+            (StatusCode::SERVICE_UNAVAILABLE, error.to_string()).into_response()
+        }
+    }
+}
+
+/// Parses a query parameter as an integer or a finite decimal number
+fn parse_number(value: &str) -> Option<serde_json::Number> {
+    match value.parse::<i64>() {
+        Ok(integer) => Some(integer.into()),
+        Err(_) => serde_json::Number::from_f64(value.parse().ok()?),
+    }
+}
+
+/// Splits a request path into datastore keys
+fn split_path(path: &Option<Path<String>>) -> Vec<String> {
+    match path {
+        Some(Path(path)) => path
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect(),
+        None => Vec::new(),
+    }
+}
@@ -0,0 +1,84 @@
+//! File endpoint
+
+use std::path::PathBuf;
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+/// State of a file endpoint
+#[derive(Clone)]
+struct FileEndpoint {
+    /// Directory the files are served from
+    server_file_path: PathBuf,
+    /// File served for requests of a directory
+    index_file: Option<String>,
+}
+
+/// Creates the router for a file endpoint
+pub fn route(server_file_path: &str, index_file: Option<String>) -> Router {
+    Router::new()
+        .route("/", get(get_file))
+        .route("/*path", get(get_file))
+        .with_state(FileEndpoint {
+            server_file_path: PathBuf::from(server_file_path),
+            index_file,
+        })
+}
+
+/// Gets a file inside the served directory, or the index file of a directory
+async fn get_file(State(endpoint): State<FileEndpoint>, path: Option<Path<String>>) -> Response {
+    let mut file_path = endpoint.server_file_path.clone();
+    for key in path.iter().flat_map(|Path(path)| path.split('/')) {
+        // paths can't leave the served directory
+        if key == ".." || key.contains('\\') {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        if !key.is_empty() && key != "." {
+            file_path.push(key);
+        }
+    }
+
+    if tokio::fs::metadata(&file_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        match &endpoint.index_file {
+            Some(index_file) => file_path.push(index_file),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    match tokio::fs::read(&file_path).await {
+        Ok(data) => ([(header::CONTENT_TYPE, content_type(&file_path))], data).into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Gets the content type of a file by its extension
+fn content_type(file_path: &std::path::Path) -> &'static str {
+    let extension = file_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
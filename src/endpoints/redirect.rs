@@ -0,0 +1,11 @@
+//! Redirect endpoint
+
+use axum::{response::Redirect, routing::any, Router};
+
+/// Creates the router for a redirect endpoint, redirecting every request to the target
+pub fn route(target: String) -> Router {
+    let redirect = move || async move { Redirect::temporary(&target) };
+    Router::new()
+        .route("/", any(redirect.clone()))
+        .route("/*path", any(redirect))
+}
@@ -0,0 +1,45 @@
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Block size of SHA-1 and SHA-256
+const BLOCK_SIZE: usize = 64;
+
+/// Computes the HMAC-SHA256 of a message (RFC 2104)
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    hmac::<Sha256>(key, message).into()
+}
+
+/// Computes the HMAC-SHA1 of a message (RFC 2104), as used by TOTP authenticator apps
+pub fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    hmac::<Sha1>(key, message).into()
+}
+
+/// Computes the HMAC of a message with a hash function with 64 byte blocks
+fn hmac<D: Digest>(key: &[u8], message: &[u8]) -> sha2::digest::Output<D> {
+    // keys longer than a block are hashed first, shorter keys are padded with zeros
+    let mut block_key = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        let key_hash = D::digest(key);
+        block_key[..key_hash.len()].copy_from_slice(&key_hash);
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = D::new();
+    inner.update(block_key.map(|byte| byte ^ 0x36));
+    inner.update(message);
+
+    let mut outer = D::new();
+    outer.update(block_key.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize()
+}
+
+/// Compares two byte strings without stopping at the first difference, so the time taken doesn't reveal how much of a secret was guessed right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
@@ -1,2 +1,4 @@
+pub mod hmac;
+pub mod html;
 pub mod sync_async;
 pub mod tlru_cache;
@@ -13,7 +13,7 @@ mod tests;
 
 use application::Application;
 use config::Config;
-use tokio::io;
+use tokio::{io, signal};
 
 /// Application entry point
 #[tokio::main]
@@ -23,9 +23,36 @@ async fn main() -> io::Result<()> {
 
     let application = Application::build(&config).await;
 
-    let result = io::Result::Ok(());
+    // serve the application routes until the process is asked to stop
+    let listener =
+        tokio::net::TcpListener::bind((config.server.host.as_str(), config.server.port)).await?;
+    let result = application.serve(listener, shutdown_signal()).await;
 
     application.stop().await;
 
     result
 }
+
+/// Completes when the process receives SIGINT (Ctrl+C) or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        signal::ctrl_c()
+            .await
+            .expect("Error occurred while listening for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Error occurred while listening for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,449 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+};
+use uuid::Uuid;
+
+use crate::{
+    application::{Application, ApplicationEndpoint},
+    config::Config,
+    datastore::DataStoreError,
+};
+
+#[tokio::test]
+async fn route_datastores() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "datastores": {"shared": {}},
+        "routes": {
+            "/a": {"handler": "Data", "permissions": {"read": true, "write": true}, "datastore": "shared"},
+            "/b": {"handler": "Data", "permissions": {"read": true, "write": true}, "datastore": "shared"},
+            "/c": {"handler": "Data", "permissions": {"read": true, "write": true}},
+            "/d": {"handler": "Data", "permissions": {"read": true, "write": true}},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let datastore = |path: &str| match &application.endpoints[path] {
+        ApplicationEndpoint::Data { datastore, .. } => *datastore.clone(),
+        _ => panic!("Expected a data endpoint"),
+    };
+
+    // routes using the same named datastore share it
+    datastore("/a").set(&["key"], json!("a")).await.unwrap();
+    assert_eq!(
+        datastore("/b").get_current(&["key"]).await.unwrap().value,
+        Some(json!("a"))
+    );
+    assert_eq!(
+        application.app_data.datastores["shared"]
+            .get_current(&["key"])
+            .await
+            .unwrap()
+            .value,
+        Some(json!("a"))
+    );
+
+    // routes without a named datastore get their own
+    datastore("/c").set(&["key"], json!("c")).await.unwrap();
+    assert_eq!(
+        datastore("/d").get_current(&["key"]).await.unwrap().value,
+        None
+    );
+
+    // stopping the application shuts down the datastores
+    let shared = datastore("/a");
+    let anonymous = datastore("/c");
+    application.stop().await;
+    assert_eq!(shared.ping().await, Err(DataStoreError::Stopped));
+    assert_eq!(anonymous.ping().await, Err(DataStoreError::Stopped));
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080, "shutdown_grace_period": 5},
+        "routes": {
+            "/data": {"handler": "Data", "permissions": {"read": true, "write": true}},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        // a request waiting for changes is held open by the server
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /data/key?wait=60 HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let started = Instant::now();
+        signal_tx.send(()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        (response, started.elapsed())
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, (response, elapsed)) = tokio::join!(server, client);
+    result.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"));
+    assert!(response.ends_with("Server is shutting down"));
+    assert!(elapsed < Duration::from_secs(5));
+
+    // no new connections are accepted
+    assert!(TcpStream::connect(address).await.is_err());
+
+    application.stop().await;
+}
+
+#[tokio::test]
+async fn claim_timeout_limits() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "datastores": {"jobs": {"max_claim_visibility_timeout": 600}},
+        "routes": {
+            "/data": {"handler": "Data", "permissions": {"read": true, "write": true}, "datastore": "jobs"},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        let (status, _) = http_request(address, "POST", "/data/jobs?push", &[], "1").await;
+        assert_eq!(status, 200);
+
+        // timeouts that can't be represented or are above the maximum are rejected
+        for timeout in ["18446744073709551615", "601"] {
+            let (status, _) = http_request(
+                address,
+                "POST",
+                &format!("/data/jobs?claim={}", timeout),
+                &[],
+                "",
+            )
+            .await;
+            assert_eq!(status, 400);
+        }
+
+        // the data store keeps working
+        let (status, body) = http_request(address, "POST", "/data/jobs?claim=600", &[], "").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"item\":1"));
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+}
+
+#[tokio::test]
+async fn query_route() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "routes": {
+            "/data": {
+                "handler": "Data",
+                "permissions": {"read": true, "write": true},
+                "rules": [{"path": "scores/c", "read": false}],
+            },
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        for (key, score) in [("a", 30), ("b", 10), ("c", 20), ("d", 40)] {
+            let (status, _) = http_request(
+                address,
+                "PUT",
+                &format!("/data/scores/{}", key),
+                &[("Content-Type", "application/json")],
+                &json!({"score": score}).to_string(),
+            )
+            .await;
+            assert_eq!(status, 200);
+        }
+
+        let query = |params: String| async move {
+            let (status, body) = http_request(
+                address,
+                "GET",
+                &format!("/data/scores?query{}", params),
+                &[],
+                "",
+            )
+            .await;
+            (
+                status,
+                serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default(),
+            )
+        };
+        let keys = |body: &serde_json::Value| -> Vec<String> {
+            body["values"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|value| String::from(value["path"][1].as_str().unwrap()))
+                .collect()
+        };
+
+        // unreadable values are left out
+        let filter =
+            "%5B%7B%22field%22%3A%5B%22score%22%5D%2C%22op%22%3A%22gt%22%2C%22value%22%3A15%7D%5D";
+        let (status, body) = query(format!("&filter={}&sort=score&descending", filter)).await;
+        assert_eq!(status, 200);
+        assert_eq!(keys(&body), vec!["d", "a"]);
+
+        let (status, body) = query(String::from("&sort=score&limit=2")).await;
+        assert_eq!(status, 200);
+        assert_eq!(keys(&body), vec!["b"]);
+        let cursor = body["next_cursor"].as_u64().unwrap();
+        let (_, body) = query(format!("&sort=score&limit=2&cursor={}", cursor)).await;
+        assert_eq!(keys(&body), vec!["a", "d"]);
+        let cursor = body["next_cursor"].as_u64().unwrap();
+        let (_, body) = query(format!("&sort=score&limit=2&cursor={}", cursor)).await;
+        assert!(keys(&body).is_empty());
+        assert!(body["next_cursor"].is_null());
+
+        let (status, _) = query(String::from("&filter=invalid")).await;
+        assert_eq!(status, 400);
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+}
+
+#[tokio::test]
+async fn update_routes() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "routes": {
+            "/data": {"handler": "Data", "permissions": {"read": true, "write": true}},
+            "/inbox": {"handler": "Data", "permissions": {"read": false, "write": true}},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        let post = |path: &'static str, body: String| async move {
+            let (status, body) = http_request(
+                address,
+                "POST",
+                path,
+                &[("Content-Type", "application/json")],
+                &body,
+            )
+            .await;
+            (
+                status,
+                serde_json::from_str::<serde_json::Value>(&body).unwrap_or_default(),
+            )
+        };
+
+        // increments are parsed from the query string
+        let (status, body) = post("/data/counter?increment=5", String::new()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["value"], json!(5));
+        let (status, body) = post("/data/counter?increment=1.5", String::new()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["value"], json!(6.5));
+        let (status, _) = post("/data/counter?increment=five", String::new()).await;
+        assert_eq!(status, 400);
+
+        let (status, body) = post("/data/list?push", json!("a").to_string()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["value"], json!(["a"]));
+        let (_, body) = post("/data/list?push", json!("b").to_string()).await;
+        assert_eq!(body["value"], json!(["a", "b"]));
+        let (status, body) = post("/data/list?pop", String::new()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!("b"));
+        let (status, _) = post("/data/counter?pop", String::new()).await;
+        assert_eq!(status, 409);
+
+        let operations = json!([
+            {"type": "insert", "id": {"counter": 1, "replica": "x"}, "after": null, "character": "h"},
+            {"type": "insert", "id": {"counter": 2, "replica": "x"}, "after": {"counter": 1, "replica": "x"}, "character": "i"},
+        ]);
+        let (status, body) = post("/data/text?text_operations", operations.to_string()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["value"]["operations"], operations);
+        let (status, _) = post("/data/text?text_operations", String::from("invalid")).await;
+        assert_eq!(status, 400);
+
+        // a sync write is only applied if the key didn't change since the client saw it
+        let write = json!([{"path": ["a"], "value": 1, "base_change_id": Uuid::nil()}]);
+        let (status, body) = post("/data/notes?sync", write.to_string()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["status"], "applied");
+        let (status, body) = post("/data/notes?sync", write.to_string()).await;
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["status"], "conflict");
+        assert_eq!(body[0]["current"]["value"], json!(1));
+
+        // invalid values are reported as such, so clients don't retry them
+        let write =
+            json!([{"path": ["b"], "value": {"$blob": "x"}, "base_change_id": Uuid::nil()}]);
+        let (_, body) = post("/data/notes?sync", write.to_string()).await;
+        assert_eq!(body[0]["status"], "invalid");
+
+        // updates return stored values, so they need the route read permission
+        let (status, _) = http_request(
+            address,
+            "PUT",
+            "/inbox/list",
+            &[("Content-Type", "application/json")],
+            "[1]",
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (status, _) = post("/inbox/list?pop", String::new()).await;
+        assert_eq!(status, 401);
+        let (status, _) = post("/inbox?sync", write.to_string()).await;
+        assert_eq!(status, 401);
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+}
+
+#[tokio::test]
+async fn file_routes() {
+    let parent = std::env::temp_dir().join(format!("garnetdg-files-{}", std::process::id()));
+    let directory = parent.join("public");
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("index.html"), "<p>index</p>").unwrap();
+    std::fs::write(parent.join("secret.txt"), "secret").unwrap();
+    let directory = directory.to_str().unwrap();
+
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "routes": {
+            "/": {"handler": "File", "server_file_path": directory, "index_file": "index.html"},
+            "/private": {
+                "handler": "File",
+                "permissions": {"read": false, "write": false},
+                "server_file_path": directory,
+                "index_file": "index.html",
+            },
+            "/old": {"handler": "Redirect", "redirect_target": "/"},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        let (status, body) = http_request(address, "GET", "/", &[], "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, "<p>index</p>");
+        let (status, _) = http_request(address, "GET", "/index.html", &[], "").await;
+        assert_eq!(status, 200);
+        let (status, _) = http_request(address, "GET", "/missing.html", &[], "").await;
+        assert_eq!(status, 404);
+        let (status, _) = http_request(address, "GET", "/%2E%2E/secret.txt", &[], "").await;
+        assert_eq!(status, 404);
+
+        // file routes check their permissions like the other routes
+        let (status, _) = http_request(address, "GET", "/private/index.html", &[], "").await;
+        assert_eq!(status, 401);
+        let (status, _) = http_request(address, "GET", "/private", &[], "").await;
+        assert_eq!(status, 401);
+
+        let (status, _) = http_request(address, "GET", "/old/page", &[], "").await;
+        assert_eq!(status, 307);
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+    std::fs::remove_dir_all(parent).unwrap();
+}
+
+/// Sends an HTTP request on a new connection and returns the response status code and body
+pub(super) async fn http_request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> (u16, String) {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let status = response[9..12].parse().unwrap();
+    let body = match response.split_once("\r\n\r\n") {
+        Some((_, body)) => String::from(body),
+        None => String::new(),
+    };
+    (status, body)
+}
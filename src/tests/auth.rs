@@ -0,0 +1,728 @@
+use std::time::Duration;
+
+use chrono::Utc;
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use serde_json::json;
+use tokio::{net::TcpListener, sync::oneshot};
+
+use super::application::http_request;
+use crate::{
+    application::Application,
+    auth::{admin::AuthAdminError, hash_password, request_token, Auth, LoginStep},
+    config::{AuthenticationConfig, Config, DatabaseConfig},
+    database::DbSchema,
+};
+
+fn auth(database: &DbSchema) -> Auth {
+    let config: AuthenticationConfig = serde_json::from_value(json!({
+        "database_schema": "auth",
+        "defaults": {"roles": [], "users": {}},
+    }))
+    .unwrap();
+    Auth::new(&config, database.clone())
+}
+
+/// Gets the session token of a login without second factor
+fn session(step: Option<LoginStep>) -> Option<String> {
+    match step {
+        Some(LoginStep::Session(token)) => Some(token),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn login_sessions() {
+    let database = DbSchema::new_memory();
+    let auth = auth(&database);
+    database.auth_create_user("alice", Some(&hash_password("secret")));
+    database.auth_create_user("bob", None);
+
+    assert_eq!(session(auth.login("alice", "wrong").await), None);
+    assert_eq!(session(auth.login("carol", "secret").await), None);
+    // users without a password can't log in
+    assert_eq!(session(auth.login("bob", "").await), None);
+
+    let token = session(auth.login("alice", "secret").await).unwrap();
+    assert_eq!(token.len(), 64);
+    let identity = auth.identify(&token).await.unwrap();
+    assert_eq!(identity.username.as_deref(), Some("alice"));
+    assert!(identity.roles.is_empty());
+
+    // each login gets its own session
+    let other_token = session(auth.login("alice", "secret").await).unwrap();
+    assert_ne!(token, other_token);
+
+    auth.logout(&token).await;
+    assert!(auth.identify(&token).await.is_none());
+    assert!(auth.identify(&other_token).await.is_some());
+    assert!(auth.identify("unknown").await.is_none());
+}
+
+#[test]
+fn session_token_sources() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(request_token(&headers), None);
+
+    headers.insert(
+        header::COOKIE,
+        HeaderValue::from_static("theme=dark; session=abc; other=1"),
+    );
+    assert_eq!(request_token(&headers).as_deref(), Some("abc"));
+
+    // bearer tokens take precedence over the cookie
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_static("Bearer def"),
+    );
+    assert_eq!(request_token(&headers).as_deref(), Some("def"));
+
+    // the API key header comes after the bearer token but before the cookie
+    headers.insert("X-API-Key", HeaderValue::from_static("ak_ghi"));
+    assert_eq!(request_token(&headers).as_deref(), Some("def"));
+    headers.remove(header::AUTHORIZATION);
+    assert_eq!(request_token(&headers).as_deref(), Some("ak_ghi"));
+}
+
+#[tokio::test]
+async fn default_users() {
+    let databases: DatabaseConfig = serde_json::from_value(json!({
+        "connections": {"main": {"driver": "SQLite3", "database": ":memory:"}},
+        "schemas": {
+            "first": {"connection": "main", "table_prefix": "first"},
+            "second": {"connection": "main", "table_prefix": "second"},
+        },
+    }))
+    .unwrap();
+    let schemas = DbSchema::connect_all(&databases);
+    let defaults = |password: &str| -> AuthenticationConfig {
+        serde_json::from_value(json!({
+            "database_schema": "first",
+            "defaults": {
+                "roles": ["admin", "editor"],
+                "users": {
+                    "alice": {"default_password": password, "roles": ["admin", "viewer"]},
+                    "bob": {"roles": []},
+                },
+            },
+        }))
+        .unwrap()
+    };
+
+    let auth = Auth::new(&defaults("initial"), schemas["first"].clone());
+    let token = session(auth.login("alice", "initial").await).unwrap();
+    let identity = auth.identify(&token).await.unwrap();
+    assert_eq!(identity.roles, vec!["admin", "viewer"]);
+    assert!(schemas["first"]
+        .auth_get_user("bob")
+        .unwrap()
+        .password_hash
+        .is_none());
+    assert!(!schemas["first"].auth_create_role("editor"));
+
+    // existing users keep their password when the defaults are applied again
+    let auth = Auth::new(&defaults("changed"), schemas["first"].clone());
+    assert!(session(auth.login("alice", "initial").await).is_some());
+    assert!(session(auth.login("alice", "changed").await).is_none());
+
+    // schemas with other table prefixes get their own tables
+    let auth = Auth::new(&defaults("changed"), schemas["second"].clone());
+    assert!(session(auth.login("alice", "changed").await).is_some());
+    assert!(session(auth.login("alice", "initial").await).is_none());
+}
+
+#[tokio::test]
+async fn route_permissions() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "databases": {
+            "connections": {"main": {"driver": "SQLite3", "database": ":memory:"}},
+            "schemas": {"main": {"connection": "main", "table_prefix": null}},
+        },
+        "authentication": {
+            "database_schema": "main",
+            "defaults": {
+                "roles": ["editor"],
+                "users": {
+                    "alice": {"default_password": "alice", "roles": ["editor"]},
+                    "bob": {"default_password": "bob", "roles": []},
+                },
+            },
+        },
+        "routes": {
+            "/auth": {"handler": "Auth"},
+            "/admin": {"handler": "AuthAdmin", "permissions": {"read": ["editor"], "write": ["editor"]}, "require_second_factor": false},
+            "/data": {"handler": "Data", "permissions": {"read": true, "write": ["editor"]}},
+            "/private": {"handler": "Data", "permissions": {"read": ["editor"], "write": false}},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        let login = |username: &'static str| async move {
+            let body = json!({"username": username, "password": username}).to_string();
+            let (status, body) = http_request(
+                address,
+                "POST",
+                "/auth/login",
+                &[("Content-Type", "application/json")],
+                &body,
+            )
+            .await;
+            assert_eq!(status, 200);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            format!("Bearer {}", body["token"].as_str().unwrap())
+        };
+        let alice = login("alice").await;
+        let bob = login("bob").await;
+
+        let put = |authorization: Option<String>| async move {
+            let headers: Vec<(&str, &str)> = authorization
+                .as_deref()
+                .map(|value| ("Authorization", value))
+                .into_iter()
+                .collect();
+            http_request(address, "PUT", "/data/key", &headers, "1")
+                .await
+                .0
+        };
+        assert_eq!(put(None).await, 401);
+        assert_eq!(put(Some(bob.clone())).await, 403);
+        assert_eq!(put(Some(alice.clone())).await, 200);
+
+        // reading is allowed for everyone
+        let (status, body) = http_request(address, "GET", "/data/key", &[], "").await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"value\":1"));
+
+        let get_private = |authorization: Option<String>| async move {
+            let headers: Vec<(&str, &str)> = authorization
+                .as_deref()
+                .map(|value| ("Authorization", value))
+                .into_iter()
+                .collect();
+            http_request(address, "GET", "/private/key", &headers, "")
+                .await
+                .0
+        };
+        assert_eq!(get_private(None).await, 401);
+        assert_eq!(get_private(Some(bob.clone())).await, 403);
+        assert_eq!(get_private(Some(alice.clone())).await, 404);
+
+        let (status, body) =
+            http_request(address, "GET", "/auth/me", &[("Authorization", &alice)], "").await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            json!({"username": "alice", "roles": ["editor"], "second_factor": false})
+        );
+        assert_eq!(
+            http_request(address, "GET", "/auth/me", &[], "").await.0,
+            401
+        );
+
+        // administration is limited to the route permissions
+        let (status, body) = http_request(
+            address,
+            "GET",
+            "/admin/users/bob",
+            &[("Authorization", &alice)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"active\":true"));
+        assert_eq!(
+            http_request(
+                address,
+                "GET",
+                "/admin/users",
+                &[("Authorization", &bob)],
+                ""
+            )
+            .await
+            .0,
+            403
+        );
+        assert_eq!(
+            http_request(
+                address,
+                "PUT",
+                "/admin/users/bob/active",
+                &[
+                    ("Authorization", &alice),
+                    ("Content-Type", "application/json")
+                ],
+                "false"
+            )
+            .await
+            .0,
+            204
+        );
+        assert_eq!(
+            http_request(address, "GET", "/auth/me", &[("Authorization", &bob)], "")
+                .await
+                .0,
+            401
+        );
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+}
+
+#[tokio::test]
+async fn administration() {
+    let database = DbSchema::new_memory();
+    let auth = auth(&database);
+
+    auth.create_role("editor").await.unwrap();
+    assert_eq!(
+        auth.create_role("editor").await,
+        Err(AuthAdminError::AlreadyExists)
+    );
+    assert_eq!(
+        auth.create_user("alice", Some("first"), &[String::from("missing")])
+            .await,
+        Err(AuthAdminError::RoleNotFound)
+    );
+    auth.create_user("alice", Some("first"), &[String::from("editor")])
+        .await
+        .unwrap();
+    auth.create_user("bob", None, &[]).await.unwrap();
+    assert_eq!(
+        auth.create_user("bob", None, &[]).await,
+        Err(AuthAdminError::AlreadyExists)
+    );
+
+    let users = auth.list_users().await;
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[0].username, "alice");
+    assert_eq!(users[0].roles, vec!["editor"]);
+    assert!(users[0].has_password);
+    assert!(!users[1].has_password);
+
+    // roles
+    auth.create_role("viewer").await.unwrap();
+    auth.assign_role("bob", "viewer").await.unwrap();
+    auth.assign_role("bob", "viewer").await.unwrap();
+    assert_eq!(
+        auth.assign_role("bob", "missing").await,
+        Err(AuthAdminError::RoleNotFound)
+    );
+    assert_eq!(
+        auth.assign_role("carol", "viewer").await,
+        Err(AuthAdminError::UserNotFound)
+    );
+    assert_eq!(auth.get_user("bob").await.unwrap().roles, vec!["viewer"]);
+    auth.unassign_role("bob", "viewer").await.unwrap();
+    assert!(auth.get_user("bob").await.unwrap().roles.is_empty());
+    auth.delete_role("editor").await.unwrap();
+    assert_eq!(
+        auth.delete_role("editor").await,
+        Err(AuthAdminError::RoleNotFound)
+    );
+    assert_eq!(auth.list_roles().await, vec!["viewer"]);
+    assert!(auth.get_user("alice").await.unwrap().roles.is_empty());
+
+    // sessions
+    let first = session(auth.login("alice", "first").await).unwrap();
+    let second = session(auth.login("alice", "first").await).unwrap();
+    let sessions = auth.list_sessions("alice").await.unwrap();
+    assert_eq!(sessions.len(), 2);
+    auth.revoke_session("alice", sessions[0].id).await.unwrap();
+    assert_eq!(
+        auth.revoke_session("bob", sessions[1].id).await,
+        Err(AuthAdminError::SessionNotFound)
+    );
+    assert!(auth.identify(&first).await.is_none());
+    assert!(auth.identify(&second).await.is_some());
+
+    // disabled users can't log in or use their sessions
+    auth.set_user_active("alice", false).await.unwrap();
+    assert!(auth.identify(&second).await.is_none());
+    assert!(session(auth.login("alice", "first").await).is_none());
+    auth.set_user_active("alice", true).await.unwrap();
+    assert!(auth.identify(&second).await.is_some());
+
+    // resetting the password ends the sessions
+    auth.reset_password("alice", Some("second")).await.unwrap();
+    assert!(auth.identify(&second).await.is_none());
+    assert!(session(auth.login("alice", "first").await).is_none());
+    assert!(session(auth.login("alice", "second").await).is_some());
+    auth.revoke_sessions("alice").await.unwrap();
+    assert!(auth.list_sessions("alice").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn session_expiry() {
+    let database = DbSchema::new_memory();
+    let config: AuthenticationConfig = serde_json::from_value(json!({
+        "database_schema": "auth",
+        "defaults": {"roles": [], "users": {"alice": {"default_password": "secret", "roles": []}}},
+        "session_lifetime": 4,
+        "session_idle_timeout": 2,
+    }))
+    .unwrap();
+    let auth = Auth::new(&config, database);
+
+    // sessions expire when they aren't used
+    let idle = session(auth.login("alice", "secret").await).unwrap();
+    assert!(auth.identify(&idle).await.is_some());
+    tokio::time::sleep(Duration::from_millis(2200)).await;
+    assert!(auth.identify(&idle).await.is_none());
+
+    // using a session keeps it alive until its lifetime is over
+    let unused = session(auth.login("alice", "secret").await).unwrap();
+    let used = session(auth.login("alice", "secret").await).unwrap();
+    for _ in 0..3 {
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert!(auth.identify(&used).await.is_some());
+    }
+    assert_eq!(auth.list_sessions("alice").await.unwrap().len(), 2);
+    assert_eq!(auth.purge_expired_sessions().await, 1);
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(auth.identify(&used).await.is_none());
+    assert!(auth.identify(&unused).await.is_none());
+    assert!(auth.list_sessions("alice").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn hashed_session_tokens() {
+    let path = std::env::temp_dir().join(format!("auth_{}.sqlite3", uuid::Uuid::new_v4()));
+    let database = path.to_str().unwrap();
+
+    // session stored with its raw token before tokens were hashed
+    let connection = rusqlite::Connection::open(database).unwrap();
+    connection
+        .execute_batch(
+            "
+CREATE TABLE \"users\" (\"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, \"username\" TEXT NOT NULL UNIQUE, \"password_hash\" TEXT, \"active\" INTEGER NOT NULL DEFAULT 1);
+CREATE TABLE \"sessions\" (\"id\" INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL, \"token\" TEXT NOT NULL UNIQUE, \"user_id\" INTEGER NOT NULL REFERENCES \"users\" (\"id\"), \"timestamp\" TEXT NOT NULL);
+INSERT INTO \"users\" (\"username\") VALUES ('alice');
+            ",
+        )
+        .unwrap();
+    connection
+        .execute(
+            "INSERT INTO \"sessions\" (\"token\", \"user_id\", \"timestamp\") VALUES ('old', 1, ?1);",
+            [chrono::Utc::now()],
+        )
+        .unwrap();
+
+    let databases: DatabaseConfig = serde_json::from_value(json!({
+        "connections": {"main": {"driver": "SQLite3", "database": database}},
+        "schemas": {"main": {"connection": "main", "table_prefix": null}},
+    }))
+    .unwrap();
+    let schemas = DbSchema::connect_all(&databases);
+    let config: AuthenticationConfig = serde_json::from_value(json!({
+        "database_schema": "main",
+        "defaults": {"roles": [], "users": {"bob": {"default_password": "secret", "roles": []}}},
+        "session_token_key": "key",
+    }))
+    .unwrap();
+    let auth = Auth::new(&config, schemas["main"].clone());
+
+    // migrated sessions keep working
+    let identity = auth.identify("old").await.unwrap();
+    assert_eq!(identity.username.as_deref(), Some("alice"));
+
+    // the database only has the token hashes
+    let token = session(auth.login("bob", "secret").await).unwrap();
+    let stored: Vec<String> = connection
+        .prepare("SELECT \"token_hash\" FROM \"sessions\" ORDER BY \"id\";")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(stored.len(), 2);
+    assert!(!stored.contains(&String::from("old")));
+    assert!(!stored.contains(&token));
+
+    // tokens hashed with another key aren't accepted
+    let config: AuthenticationConfig = serde_json::from_value(json!({
+        "database_schema": "main",
+        "defaults": {"roles": [], "users": {}},
+        "session_token_key": "other",
+    }))
+    .unwrap();
+    let other = Auth::new(&config, schemas["main"].clone());
+    assert!(other.identify(&token).await.is_none());
+    assert!(auth.identify(&token).await.is_some());
+
+    drop(connection);
+    for suffix in ["", "-wal", "-shm"] {
+        std::fs::remove_file(format!("{}{}", database, suffix)).ok();
+    }
+}
+
+#[tokio::test]
+async fn api_keys() {
+    let database = DbSchema::new_memory();
+    let auth = auth(&database);
+    auth.create_role("editor").await.unwrap();
+    auth.create_role("viewer").await.unwrap();
+    auth.create_user("alice", None, &["editor".into(), "viewer".into()])
+        .await
+        .unwrap();
+
+    let full = auth
+        .create_api_key("alice", "ci", None, None)
+        .await
+        .unwrap();
+    assert!(full.key.starts_with("ak_"));
+    assert_eq!(full.info.name, "ci");
+    // users without a password can still use API keys
+    let identity = auth.identify(&full.key).await.unwrap();
+    assert_eq!(identity.username.as_deref(), Some("alice"));
+    assert_eq!(identity.roles, vec!["editor", "viewer"]);
+
+    let limited = auth
+        .create_api_key("alice", "sensor", Some(&["viewer".into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        auth.identify(&limited.key).await.unwrap().roles,
+        vec!["viewer"]
+    );
+
+    assert_eq!(
+        auth.create_api_key("alice", "ci", None, None)
+            .await
+            .unwrap_err(),
+        AuthAdminError::AlreadyExists
+    );
+    assert_eq!(
+        auth.create_api_key("alice", "admin", Some(&["admin".into()]), None)
+            .await
+            .unwrap_err(),
+        AuthAdminError::RoleNotGranted
+    );
+    assert_eq!(
+        auth.create_api_key("bob", "ci", None, None)
+            .await
+            .unwrap_err(),
+        AuthAdminError::UserNotFound
+    );
+
+    // only hashes of the keys are stored
+    let names: Vec<String> = auth
+        .list_api_keys("alice")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|api_key| api_key.name)
+        .collect();
+    assert_eq!(names, vec!["ci", "sensor"]);
+    assert!(database.auth_get_api_key(&limited.key).is_none());
+
+    // keys lose roles taken from the user
+    auth.unassign_role("alice", "viewer").await.unwrap();
+    assert!(auth.identify(&limited.key).await.unwrap().roles.is_empty());
+    assert_eq!(
+        auth.identify(&full.key).await.unwrap().roles,
+        vec!["editor"]
+    );
+
+    auth.set_user_active("alice", false).await.unwrap();
+    assert!(auth.identify(&full.key).await.is_none());
+    auth.set_user_active("alice", true).await.unwrap();
+
+    let expiring = auth
+        .create_api_key(
+            "alice",
+            "temporary",
+            None,
+            Some(Utc::now() + chrono::Duration::milliseconds(500)),
+        )
+        .await
+        .unwrap();
+    assert!(auth.identify(&expiring.key).await.is_some());
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert!(auth.identify(&expiring.key).await.is_none());
+    auth.purge_expired_sessions().await;
+    assert_eq!(auth.list_api_keys("alice").await.unwrap().len(), 2);
+
+    auth.revoke_api_key("alice", "ci").await.unwrap();
+    assert!(auth.identify(&full.key).await.is_none());
+    assert_eq!(
+        auth.revoke_api_key("alice", "ci").await.unwrap_err(),
+        AuthAdminError::ApiKeyNotFound
+    );
+
+    // resetting the password revokes the remaining keys
+    assert!(auth.identify(&limited.key).await.is_some());
+    auth.reset_password("alice", Some("new")).await.unwrap();
+    assert!(auth.identify(&limited.key).await.is_none());
+    assert!(auth.list_api_keys("alice").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn api_key_routes() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "databases": {
+            "connections": {"main": {"driver": "SQLite3", "database": ":memory:"}},
+            "schemas": {"main": {"connection": "main", "table_prefix": null}},
+        },
+        "authentication": {
+            "database_schema": "main",
+            "defaults": {
+                "roles": ["editor"],
+                "users": {"alice": {"default_password": "alice", "roles": ["editor"]}},
+            },
+        },
+        "routes": {
+            "/auth": {"handler": "Auth"},
+            "/admin": {"handler": "AuthAdmin", "permissions": {"read": ["editor"], "write": ["editor"]}, "require_second_factor": false},
+            "/data": {"handler": "Data", "permissions": {"read": true, "write": ["editor"]}},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        let body = json!({"username": "alice", "password": "alice"}).to_string();
+        let (_, body) = http_request(
+            address,
+            "POST",
+            "/auth/login",
+            &[("Content-Type", "application/json")],
+            &body,
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let session = format!("Bearer {}", body["token"].as_str().unwrap());
+
+        let create = |authorization: String, body: serde_json::Value| async move {
+            http_request(
+                address,
+                "POST",
+                "/auth/api-keys",
+                &[
+                    ("Authorization", &authorization),
+                    ("Content-Type", "application/json"),
+                ],
+                &body.to_string(),
+            )
+            .await
+        };
+        let (status, body) = create(session.clone(), json!({"name": "device"})).await;
+        assert_eq!(status, 201);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let key = String::from(body["key"].as_str().unwrap());
+        assert_eq!(body["name"], "device");
+
+        let (status, _) = create(session.clone(), json!({"name": "device"})).await;
+        assert_eq!(status, 409);
+        let (status, _) = create(
+            session.clone(),
+            json!({"name": "other", "roles": ["admin"]}),
+        )
+        .await;
+        assert_eq!(status, 403);
+        // API keys can't create more keys
+        let (status, _) = create(format!("Bearer {}", key), json!({"name": "copy"})).await;
+        assert_eq!(status, 403);
+
+        // the key works as bearer token and in the API key header
+        let (status, _) = http_request(
+            address,
+            "PUT",
+            "/data/key",
+            &[("Authorization", &format!("Bearer {}", key))],
+            "1",
+        )
+        .await;
+        assert_eq!(status, 200);
+        let (status, _) =
+            http_request(address, "PUT", "/data/key", &[("X-API-Key", &key)], "2").await;
+        assert_eq!(status, 200);
+
+        // the key isn't listed
+        let (status, body) = http_request(
+            address,
+            "GET",
+            "/auth/api-keys",
+            &[("Authorization", &session)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"device\""));
+        assert!(!body.contains(&key));
+
+        // API keys can't list or revoke keys either
+        let (status, _) =
+            http_request(address, "GET", "/auth/api-keys", &[("X-API-Key", &key)], "").await;
+        assert_eq!(status, 403);
+        let (status, _) = http_request(
+            address,
+            "DELETE",
+            "/auth/api-keys/device",
+            &[("X-API-Key", &key)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 403);
+
+        let (status, body) = http_request(
+            address,
+            "GET",
+            "/admin/users/alice/api-keys",
+            &[("Authorization", &session)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"device\""));
+        let (status, _) = http_request(
+            address,
+            "DELETE",
+            "/admin/users/alice/api-keys/device",
+            &[("Authorization", &session)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 204);
+
+        let (status, _) =
+            http_request(address, "PUT", "/data/key", &[("X-API-Key", &key)], "3").await;
+        assert_eq!(status, 401);
+        let (status, _) = http_request(
+            address,
+            "DELETE",
+            "/auth/api-keys/device",
+            &[("Authorization", &session)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 404);
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+}
@@ -1,4 +1,32 @@
-use crate::{config::DatastoreConfig, datastore::DataStore};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::{DatastoreConfig, DatastoreIndexConfig},
+    database::{
+        models::datastore::{
+            DatastoreQueryFilter, DatastoreQueryOperation, DatastoreQuerySort,
+            DatastoreSearchResult,
+        },
+        DbSchema,
+    },
+    datastore::{
+        blobs::BlobReference,
+        crdt::{CrdtText, TextElementId, TextOperation},
+        hooks::DataStoreHook,
+        patterns::PathPattern,
+        DataStore, DataStoreError, QueryResult, SetIfCurrentError, Value,
+    },
+};
 
 #[tokio::test]
 async fn ping() {
@@ -9,17 +37,25 @@ async fn ping() {
             keep_history: true,
             history_max_age: Some(3600),
             history_max_entries: Some(1000),
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
         },
         None,
     )
     .await;
 
-    datastore.ping().await;
+    datastore.ping().await.unwrap();
 
     let datastore2 = datastore.clone();
 
-    datastore.ping().await;
-    datastore2.ping().await;
+    datastore.ping().await.unwrap();
+    datastore2.ping().await.unwrap();
 }
 
 #[tokio::test]
@@ -31,20 +67,2041 @@ async fn basic_get_set() {
             keep_history: false,
             history_max_age: None,
             history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
         },
         None,
     )
     .await;
 
-    datastore.set(&[], String::from("test1")).await;
+    datastore.set(&[], String::from("test1")).await.unwrap();
     assert_eq!(
-        datastore.get_current(&[]).await.value,
+        datastore.get_current(&[]).await.unwrap().value,
         Some(String::from("test1"))
     );
 
-    datastore.set(&[], String::from("test2")).await;
+    datastore.set(&[], String::from("test2")).await.unwrap();
     assert_eq!(
-        datastore.get_current(&[]).await.value,
+        datastore.get_current(&[]).await.unwrap().value,
         Some(String::from("test2"))
     );
 }
+
+#[tokio::test]
+async fn list() {
+    let datastore: DataStore<String> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    datastore.set(&["a"], String::from("a")).await.unwrap();
+    datastore.set(&["b", "c"], String::from("c")).await.unwrap();
+    datastore.set(&["d"], String::from("d")).await.unwrap();
+    datastore.delete(&["d"]).await.unwrap();
+
+    assert_eq!(datastore.list(&[]).await.unwrap(), vec!["a", "b"]);
+    assert_eq!(datastore.list(&["b"]).await.unwrap(), vec!["c"]);
+    assert!(datastore.list(&["e"]).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn query() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    datastore
+        .set(
+            &["scores", "a"],
+            json!({"name": "alice", "score": 30, "tags": ["x"]}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["scores", "b"],
+            json!({"name": "bob", "score": 10, "tags": ["y"]}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["scores", "c"],
+            json!({"name": "carol", "score": 20, "tags": ["x", "y"]}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["scores", "d"],
+            json!({"name": "dave", "score": 40, "tags": []}),
+        )
+        .await
+        .unwrap();
+    datastore.delete(&["scores", "d"]).await.unwrap();
+
+    let keys = |result: &QueryResult<serde_json::Value>| -> Vec<String> {
+        result
+            .values
+            .iter()
+            .map(|value| value.path.last().unwrap().clone())
+            .collect()
+    };
+
+    // no filters, sorted by key
+    let result = datastore
+        .query(&["scores"], &[], None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["a", "b", "c"]);
+    assert_eq!(result.next_cursor, None);
+
+    // filters
+    let result = datastore
+        .query(
+            &["scores"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("name")],
+                operation: DatastoreQueryOperation::Eq(json!("bob")),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["b"]);
+    assert_eq!(result.values[0].value.as_ref().unwrap()["score"], json!(10));
+
+    let result = datastore
+        .query(
+            &["scores"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("score")],
+                operation: DatastoreQueryOperation::Lt(json!(25)),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["b", "c"]);
+
+    let result = datastore
+        .query(
+            &["scores"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("tags")],
+                operation: DatastoreQueryOperation::Contains(json!("x")),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["a", "c"]);
+
+    let result = datastore
+        .query(
+            &["scores"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("name")],
+                operation: DatastoreQueryOperation::Contains(json!("o")),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["b", "c"]);
+
+    // sorting and pagination
+    let sort = DatastoreQuerySort {
+        field: vec![String::from("score")],
+        descending: true,
+    };
+    let result = datastore
+        .query(&["scores"], &[], Some(sort.clone()), Some(2), None)
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["a", "c"]);
+    assert_eq!(result.next_cursor, Some(2));
+
+    let result = datastore
+        .query(&["scores"], &[], Some(sort), Some(2), result.next_cursor)
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["b"]);
+    assert_eq!(result.next_cursor, None);
+}
+
+#[tokio::test]
+async fn history() {
+    let datastore: DataStore<String> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: true,
+            history_max_age: None,
+            history_max_entries: Some(2),
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    let change_1 = datastore.set(&["a"], String::from("1")).await.unwrap();
+    let change_2 = datastore.set(&["a"], String::from("2")).await.unwrap();
+    datastore.set(&["a"], String::from("3")).await.unwrap();
+    datastore.set(&["a"], String::from("4")).await.unwrap();
+
+    // only the current value and 2 previous values are kept
+    let values: Vec<Option<String>> = datastore
+        .get_all(&["a"], None)
+        .await
+        .unwrap()
+        .iter()
+        .map(|x| x.value.clone())
+        .collect();
+    assert_eq!(
+        values,
+        vec![
+            Some(String::from("2")),
+            Some(String::from("3")),
+            Some(String::from("4"))
+        ]
+    );
+
+    let values = datastore.get_all(&["a"], Some(change_2)).await.unwrap();
+    assert_eq!(values.len(), 2);
+
+    // change id no longer in history returns all kept values
+    let values = datastore.get_all(&["a"], Some(change_1)).await.unwrap();
+    assert_eq!(values.len(), 3);
+}
+
+#[tokio::test]
+async fn query_indexed() {
+    let database = DbSchema::new_memory();
+
+    // values set before the index is configured
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        Some(database.clone()),
+    )
+    .await;
+    datastore
+        .set(
+            &["users", "a"],
+            json!({"email": "a@example.com", "score": 30}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["users", "b"],
+            json!({"email": "b@example.com", "score": 10}),
+        )
+        .await
+        .unwrap();
+    drop(datastore);
+
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::from([
+                (
+                    String::from("email"),
+                    DatastoreIndexConfig {
+                        path: PathPattern::new("users/*").unwrap(),
+                        field: vec![String::from("email")],
+                    },
+                ),
+                (
+                    String::from("score"),
+                    DatastoreIndexConfig {
+                        path: PathPattern::new("users/*").unwrap(),
+                        field: vec![String::from("score")],
+                    },
+                ),
+            ]),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        Some(database),
+    )
+    .await;
+    datastore
+        .set(
+            &["users", "c"],
+            json!({"email": "c@example.com", "score": 20}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["users", "a"],
+            json!({"email": "a@example.org", "score": 40}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["users", "d"],
+            json!({"email": "d@example.com", "score": 50}),
+        )
+        .await
+        .unwrap();
+    datastore.delete(&["users", "d"]).await.unwrap();
+
+    let keys = |result: &QueryResult<serde_json::Value>| -> Vec<String> {
+        result
+            .values
+            .iter()
+            .map(|value| value.path.last().unwrap().clone())
+            .collect()
+    };
+
+    // lookup by indexed field
+    let result = datastore
+        .query(
+            &["users"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("email")],
+                operation: DatastoreQueryOperation::Eq(json!("b@example.com")),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["b"]);
+
+    let result = datastore
+        .query(
+            &["users"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("email")],
+                operation: DatastoreQueryOperation::Eq(json!("a@example.com")),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(result.values.is_empty());
+
+    // sort and filter by indexed fields
+    let result = datastore
+        .query(
+            &["users"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("score")],
+                operation: DatastoreQueryOperation::Ge(json!(20)),
+            }],
+            Some(DatastoreQuerySort {
+                field: vec![String::from("score")],
+                descending: true,
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(keys(&result), vec!["a", "c"]);
+}
+
+#[tokio::test]
+async fn query_escaped_fields() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        Some(DbSchema::new_memory()),
+    )
+    .await;
+    datastore
+        .set(&["items", "a"], json!({"say \"hi\"": 1, "back\\slash": 2}))
+        .await
+        .unwrap();
+    datastore
+        .set(&["items", "b"], json!({"say \"hi\"": 2, "back\\slash": 1}))
+        .await
+        .unwrap();
+
+    // keys with quotes and backslashes are matched exactly
+    let result = datastore
+        .query(
+            &["items"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("say \"hi\"")],
+                operation: DatastoreQueryOperation::Eq(json!(2)),
+            }],
+            Some(DatastoreQuerySort {
+                field: vec![String::from("back\\slash")],
+                descending: true,
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(result.values.len(), 1);
+    assert_eq!(result.values[0].path, vec!["items", "b"]);
+
+    let result = datastore
+        .query(
+            &["items"],
+            &[DatastoreQueryFilter {
+                field: vec![String::from("say \"")],
+                operation: DatastoreQueryOperation::Eq(json!(2)),
+            }],
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert!(result.values.is_empty());
+
+    let result = datastore
+        .query(
+            &["items"],
+            &[],
+            Some(DatastoreQuerySort {
+                field: vec![String::from("back\\slash")],
+                descending: false,
+            }),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    let keys: Vec<&str> = result
+        .values
+        .iter()
+        .map(|value| value.path[1].as_str())
+        .collect();
+    assert_eq!(keys, vec!["b", "a"]);
+}
+
+#[tokio::test]
+async fn search() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: true,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    datastore
+        .set(
+            &["notes", "a"],
+            json!({"title": "Shopping", "body": "apples and oranges", "count": 2}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(
+            &["notes", "b"],
+            json!({"title": "Apples", "body": "apple pie recipe with apples"}),
+        )
+        .await
+        .unwrap();
+    datastore
+        .set(&["notes", "c"], json!({"title": "Work", "body": "meeting"}))
+        .await
+        .unwrap();
+    datastore
+        .set(&["other"], json!({"title": "apples elsewhere"}))
+        .await
+        .unwrap();
+
+    let paths = |results: &[DatastoreSearchResult]| -> Vec<Vec<String>> {
+        results.iter().map(|x| x.path.clone()).collect()
+    };
+
+    // results are ranked and limited to the path
+    let results = datastore.search(&["notes"], "apples").await.unwrap();
+    assert_eq!(
+        paths(&results),
+        vec![vec!["notes", "b"], vec!["notes", "a"]]
+    );
+    assert!(results[1].snippet.contains("<mark>apples</mark>"));
+
+    let results = datastore.search(&[], "apples").await.unwrap();
+    assert_eq!(results.len(), 3);
+
+    // prefix matches
+    let results = datastore.search(&["notes"], "meet*").await.unwrap();
+    assert_eq!(paths(&results), vec![vec!["notes", "c"]]);
+
+    // query syntax is treated as text
+    let results = datastore.search(&["notes"], "\"apples OR (").await.unwrap();
+    assert!(results.is_empty());
+
+    // updated and deleted values
+    datastore
+        .set(&["notes", "c"], json!({"title": "Work", "body": "apples"}))
+        .await
+        .unwrap();
+    datastore.delete(&["notes", "b"]).await.unwrap();
+    let results = datastore.search(&["notes"], "apples").await.unwrap();
+    assert_eq!(
+        paths(&results),
+        vec![vec!["notes", "c"], vec!["notes", "a"]]
+    );
+
+    // snippets only contain the highlighting as markup
+    datastore
+        .set(&["notes", "d"], json!("fresh <b>kiwi</b> & \"pears\""))
+        .await
+        .unwrap();
+    let results = datastore.search(&["notes"], "kiwi").await.unwrap();
+    assert_eq!(
+        results[0].snippet,
+        "fresh &lt;b&gt;<mark>kiwi</mark>&lt;/b&gt; &amp; &quot;pears&quot;"
+    );
+}
+
+#[tokio::test]
+async fn subscribe() {
+    let datastore: DataStore<String> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    let mut subscription = datastore.subscribe(&["a"]).await.unwrap();
+    let mut other_subscription = datastore.subscribe(&["b"]).await.unwrap();
+
+    let change_id = datastore.set(&["a"], String::from("1")).await.unwrap();
+    let value = subscription.recv().await.unwrap();
+    assert_eq!(value.change_id, change_id);
+    assert_eq!(value.value, Some(String::from("1")));
+
+    datastore.delete(&["a"]).await.unwrap();
+    assert_eq!(subscription.recv().await.unwrap().value, None);
+
+    assert!(other_subscription
+        .recv_timeout(Duration::from_millis(10))
+        .await
+        .unwrap()
+        .is_none());
+
+    // no notifications after unsubscribing
+    drop(subscription);
+    datastore.set(&["a"], String::from("2")).await.unwrap();
+    datastore.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn increment() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    let mut subscription = datastore.subscribe(&["votes"]).await.unwrap();
+
+    // concurrent increments don't lose updates
+    let tasks: Vec<_> = (0..50)
+        .map(|_| {
+            let datastore = datastore.clone();
+            tokio::spawn(async move { datastore.increment(&["votes"], 1.into()).await.unwrap() })
+        })
+        .collect();
+    for task in tasks {
+        assert!(task.await.unwrap().is_some());
+    }
+    assert_eq!(
+        datastore.get_current(&["votes"]).await.unwrap().value,
+        Some(json!(50))
+    );
+
+    // notifications carry the new value
+    for i in 1..=50 {
+        assert_eq!(subscription.recv().await.unwrap().value, Some(json!(i)));
+    }
+
+    let value = datastore
+        .increment(&["votes"], serde_json::Number::from_f64(0.5).unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(value.value, Some(json!(50.5)));
+
+    datastore.set(&["name"], json!("text")).await.unwrap();
+    assert!(datastore
+        .increment(&["name"], 1.into())
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        datastore.get_current(&["name"]).await.unwrap().value,
+        Some(json!("text"))
+    );
+}
+
+#[tokio::test]
+async fn list_operations() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    let mut subscription = datastore.subscribe(&["list"]).await.unwrap();
+
+    datastore
+        .push(&["list"], json!("a"))
+        .await
+        .unwrap()
+        .unwrap();
+    datastore
+        .push(&["list"], json!("c"))
+        .await
+        .unwrap()
+        .unwrap();
+    datastore
+        .insert_at(&["list"], 1, json!("b"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(datastore
+        .insert_at(&["list"], 4, json!("e"))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        datastore.get_current(&["list"]).await.unwrap().value,
+        Some(json!(["a", "b", "c"]))
+    );
+
+    assert_eq!(datastore.pop(&["list"]).await.unwrap(), Some(json!("c")));
+    assert_eq!(
+        datastore.remove_at(&["list"], 0).await.unwrap(),
+        Some(json!("a"))
+    );
+    assert_eq!(datastore.remove_at(&["list"], 1).await.unwrap(), None);
+    assert_eq!(
+        datastore.get_current(&["list"]).await.unwrap().value,
+        Some(json!(["b"]))
+    );
+
+    // each operation is a separate change
+    let changes = [
+        json!(["a"]),
+        json!(["a", "c"]),
+        json!(["a", "b", "c"]),
+        json!(["a", "b"]),
+        json!(["b"]),
+    ];
+    for change in changes {
+        assert_eq!(subscription.recv().await.unwrap().value, Some(change));
+    }
+
+    datastore.set(&["text"], json!("text")).await.unwrap();
+    assert!(datastore
+        .push(&["text"], json!("a"))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(datastore.pop(&["text"]).await.unwrap(), None);
+    assert_eq!(datastore.pop(&["empty"]).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn queue_claim() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    datastore.push(&["jobs"], json!(1)).await.unwrap();
+    datastore.push(&["jobs"], json!(2)).await.unwrap();
+
+    // completed claims are removed
+    let claim = datastore
+        .claim(&["jobs"], Duration::from_secs(60))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claim.item, json!(1));
+    // claims can't be completed through another path
+    assert!(!datastore
+        .complete_claim(&["other"], claim.id)
+        .await
+        .unwrap());
+    assert!(datastore.complete_claim(&["jobs"], claim.id).await.unwrap());
+    assert!(!datastore.complete_claim(&["jobs"], claim.id).await.unwrap());
+
+    // expired claims are put back
+    let claim = datastore
+        .claim(&["jobs"], Duration::from_millis(50))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claim.item, json!(2));
+    assert!(datastore
+        .claim(&["jobs"], Duration::from_secs(60))
+        .await
+        .unwrap()
+        .is_none());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    datastore.ping().await.unwrap();
+    assert_eq!(
+        datastore.get_current(&["jobs"]).await.unwrap().value,
+        Some(json!([2]))
+    );
+    assert!(!datastore.complete_claim(&["jobs"], claim.id).await.unwrap());
+
+    // claims are kept until the value is a list again
+    let claim = datastore
+        .claim(&["jobs"], Duration::from_millis(50))
+        .await
+        .unwrap()
+        .unwrap();
+    datastore.set(&["jobs"], json!("replaced")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    datastore.ping().await.unwrap();
+    datastore.set(&["jobs"], json!([3])).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    datastore.ping().await.unwrap();
+    assert_eq!(
+        datastore.get_current(&["jobs"]).await.unwrap().value,
+        Some(json!([2, 3]))
+    );
+    assert!(!datastore.complete_claim(&["jobs"], claim.id).await.unwrap());
+
+    let claim = datastore
+        .claim(&["jobs"], Duration::from_secs(60))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(claim.item, json!(2));
+
+    // timeouts above the maximum are rejected without touching the data store thread
+    assert!(matches!(
+        datastore.claim(&["jobs"], Duration::from_secs(43201)).await,
+        Err(DataStoreError::InvalidData(_))
+    ));
+    assert!(matches!(
+        datastore.claim(&["jobs"], Duration::MAX).await,
+        Err(DataStoreError::InvalidData(_))
+    ));
+    datastore.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn text_operations() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    let id = |counter, replica: &str| TextElementId {
+        counter,
+        replica: String::from(replica),
+    };
+    let insert = |counter, replica, after, character| TextOperation::Insert {
+        id: id(counter, replica),
+        after,
+        character,
+    };
+    let text = |value: &serde_json::Value| {
+        serde_json::from_value::<CrdtText>(value.clone())
+            .unwrap()
+            .text()
+    };
+
+    let mut subscription = datastore.subscribe(&["doc"]).await.unwrap();
+
+    let base = vec![
+        insert(1, "a", None, 'a'),
+        insert(2, "a", Some(id(1, "a")), 'c'),
+    ];
+    let value = datastore
+        .apply_text_operations(&["doc"], base.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(text(value.value.as_ref().unwrap()), "ac");
+    let notification = subscription.recv().await.unwrap();
+    let notified: CrdtText = serde_json::from_value(notification.value.clone().unwrap()).unwrap();
+    assert_eq!(notified.operations, base);
+
+    // concurrent insertions at the same position converge regardless of order
+    let from_x = insert(3, "x", Some(id(1, "a")), 'b');
+    let from_y = insert(3, "y", Some(id(1, "a")), 'B');
+    let mut other = CrdtText::default();
+    for operation in base.iter().chain([&from_y, &from_x]) {
+        assert!(other.apply(operation));
+    }
+    datastore
+        .apply_text_operations(&["doc"], vec![from_x.clone()])
+        .await
+        .unwrap()
+        .unwrap();
+    let value = datastore
+        .apply_text_operations(&["doc"], vec![from_y, from_x])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(text(value.value.as_ref().unwrap()), "aBbc");
+    assert_eq!(other.text(), "aBbc");
+
+    let value = datastore
+        .apply_text_operations(&["doc"], vec![TextOperation::Delete { id: id(1, "a") }])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(text(value.value.as_ref().unwrap()), "Bbc");
+
+    // operations referring to unknown characters are rejected as a whole
+    assert!(datastore
+        .apply_text_operations(
+            &["doc"],
+            vec![
+                insert(4, "a", Some(id(3, "y")), 'd'),
+                insert(5, "a", Some(id(9, "z")), 'e'),
+            ]
+        )
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        text(
+            datastore
+                .get_current(&["doc"])
+                .await
+                .unwrap()
+                .value
+                .as_ref()
+                .unwrap()
+        ),
+        "Bbc"
+    );
+
+    datastore.set(&["number"], json!(1)).await.unwrap();
+    assert!(datastore
+        .apply_text_operations(&["number"], vec![insert(1, "a", None, 'a')])
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn changes_since() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: true,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    datastore.set(&["a"], json!(1)).await.unwrap();
+    datastore.set(&["b", "c"], json!(2)).await.unwrap();
+    datastore.set(&[], json!(3)).await.unwrap();
+    datastore.delete(&["a"]).await.unwrap();
+
+    let result = datastore.changes_since(&[], 0, Some(3)).await.unwrap();
+    let changes: Vec<(Vec<String>, Option<serde_json::Value>)> = result
+        .changes
+        .iter()
+        .map(|change| (change.path.clone(), change.value.clone()))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (vec![String::from("a")], Some(json!(1))),
+            (vec![String::from("b"), String::from("c")], Some(json!(2))),
+            (vec![], Some(json!(3))),
+        ]
+    );
+
+    let result = datastore
+        .changes_since(&[], result.next_cursor, Some(3))
+        .await
+        .unwrap();
+    assert_eq!(result.changes.len(), 1);
+    assert_eq!(result.changes[0].path, vec![String::from("a")]);
+    assert_eq!(result.changes[0].value, None);
+
+    // no new changes keep the cursor
+    let cursor = result.next_cursor;
+    let result = datastore.changes_since(&[], cursor, None).await.unwrap();
+    assert!(result.changes.is_empty());
+    assert_eq!(result.next_cursor, cursor);
+
+    // only changes of the path and its subkeys are returned
+    datastore.set(&["b", "d"], json!(4)).await.unwrap();
+    datastore.set(&["a"], json!(5)).await.unwrap();
+    let result = datastore.changes_since(&["b"], cursor, None).await.unwrap();
+    assert_eq!(result.changes.len(), 1);
+    assert_eq!(result.changes[0].value, Some(json!(4)));
+    assert!(datastore
+        .changes_since(&["x"], 0, None)
+        .await
+        .unwrap()
+        .changes
+        .is_empty());
+}
+
+#[tokio::test]
+async fn set_if_current() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    // unset keys have the nil change id
+    let change_id = datastore
+        .set_if_current(&["a"], Some(json!(1)), Uuid::nil())
+        .await
+        .unwrap();
+
+    let Err(SetIfCurrentError::Conflict(current)) = datastore
+        .set_if_current(&["a"], Some(json!(2)), Uuid::nil())
+        .await
+    else {
+        panic!("Expected a conflict");
+    };
+    assert_eq!(current.change_id, change_id);
+    assert_eq!(current.value, Some(json!(1)));
+
+    datastore
+        .set_if_current(&["a"], None, change_id)
+        .await
+        .unwrap();
+    assert_eq!(datastore.get_current(&["a"]).await.unwrap().value, None);
+}
+
+#[tokio::test]
+async fn triggers() {
+    let datastore: DataStore<serde_json::Value> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            queue_depth: 1000,
+            request_timeout: 30000,
+        write_batch_max_size: 100,
+        write_batch_max_latency: 0,
+        max_claim_visibility_timeout: 43200,
+            triggers: serde_json::from_value(json!([
+                {"path": "posts/{post}/comments/*", "target": "posts/{post}/comment_count", "action": "count"},
+                {"path": "orders/*", "target": "order_total", "action": "sum", "field": ["amount"]},
+                {"path": "docs/*", "target": "docs_modified_by", "action": "copy", "field": ["author"]},
+                {"path": "docs/*", "target": "docs_last_change", "action": "last_change"},
+            ]))
+            .unwrap(),
+        },
+        None,
+    )
+    .await;
+
+    let mut subscription = datastore
+        .subscribe(&["posts", "1", "comment_count"])
+        .await
+        .unwrap();
+
+    datastore
+        .set(&["posts", "1", "comments", "a"], json!("hi"))
+        .await
+        .unwrap();
+    datastore
+        .set(&["posts", "1", "comments", "b"], json!("hey"))
+        .await
+        .unwrap();
+    datastore
+        .set(&["posts", "1", "comments", "b"], json!("hello"))
+        .await
+        .unwrap();
+    datastore
+        .set(&["posts", "2", "comments", "a"], json!("hi"))
+        .await
+        .unwrap();
+    datastore
+        .delete(&["posts", "1", "comments", "a"])
+        .await
+        .unwrap();
+    datastore
+        .delete(&["posts", "1", "comments", "c"])
+        .await
+        .unwrap();
+
+    let counts: Vec<Option<serde_json::Value>> = vec![
+        subscription.recv().await.unwrap().value.clone(),
+        subscription.recv().await.unwrap().value.clone(),
+        subscription.recv().await.unwrap().value.clone(),
+    ];
+    assert_eq!(counts, vec![Some(json!(1)), Some(json!(2)), Some(json!(1))]);
+    assert_eq!(
+        datastore
+            .get_current(&["posts", "2", "comment_count"])
+            .await
+            .unwrap()
+            .value,
+        Some(json!(1))
+    );
+
+    datastore
+        .set(&["orders", "a"], json!({"amount": 10}))
+        .await
+        .unwrap();
+    datastore
+        .set(&["orders", "b"], json!({"amount": 2.5}))
+        .await
+        .unwrap();
+    datastore
+        .set(&["orders", "a"], json!({"amount": 4}))
+        .await
+        .unwrap();
+    datastore.delete(&["orders", "b"]).await.unwrap();
+    datastore
+        .set(&["orders", "c"], json!({"other": 1}))
+        .await
+        .unwrap();
+    assert_eq!(
+        datastore.get_current(&["order_total"]).await.unwrap().value,
+        Some(json!(4.0))
+    );
+
+    datastore
+        .set(&["docs", "x"], json!({"author": "alice"}))
+        .await
+        .unwrap();
+    let change_id = datastore
+        .set(&["docs", "y"], json!({"author": "bob"}))
+        .await
+        .unwrap();
+    assert_eq!(
+        datastore
+            .get_current(&["docs_modified_by"])
+            .await
+            .unwrap()
+            .value,
+        Some(json!("bob"))
+    );
+    assert_eq!(
+        datastore
+            .get_current(&["docs_last_change"])
+            .await
+            .unwrap()
+            .value
+            .as_ref()
+            .unwrap()["change_id"],
+        json!(change_id)
+    );
+}
+
+/// Hook that only allows lowercase text and records the paths that changed
+struct LowercaseHook {
+    changed: Arc<Mutex<Vec<String>>>,
+}
+
+impl DataStoreHook<String> for LowercaseHook {
+    fn before_set(&mut self, path: &[String], value: String) -> Result<String, String> {
+        if path.first().map(|x| x.as_str()) == Some("locked") {
+            return Err(String::from("locked"));
+        }
+        Ok(value.to_lowercase())
+    }
+
+    fn after_set(&mut self, value: &Value<String>) {
+        self.changed
+            .lock()
+            .unwrap()
+            .push(format!("set {}", value.path.join("/")));
+    }
+
+    fn before_delete(&mut self, path: &[String]) -> Result<(), String> {
+        if path.first().map(|x| x.as_str()) == Some("locked") {
+            return Err(String::from("locked"));
+        }
+        Ok(())
+    }
+
+    fn after_delete(&mut self, value: &Value<String>) {
+        self.changed
+            .lock()
+            .unwrap()
+            .push(format!("delete {}", value.path.join("/")));
+    }
+}
+
+#[tokio::test]
+async fn hooks() {
+    let changed = Arc::new(Mutex::new(Vec::new()));
+    let datastore: DataStore<String> = DataStore::new_with_hooks(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+        vec![Box::new(LowercaseHook {
+            changed: Arc::clone(&changed),
+        })],
+    )
+    .await;
+
+    // writes are transformed
+    datastore.set(&["a"], String::from("Hello")).await.unwrap();
+    assert_eq!(
+        datastore.get_current(&["a"]).await.unwrap().value,
+        Some(String::from("hello"))
+    );
+    datastore
+        .update(&["a"], |value| value.map(|x| format!("{x} World")))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        datastore.get_current(&["a"]).await.unwrap().value,
+        Some(String::from("hello world"))
+    );
+    datastore.delete(&["a"]).await.unwrap();
+
+    // writes are rejected
+    assert_eq!(
+        datastore.set(&["locked", "b"], String::from("b")).await,
+        Err(DataStoreError::Rejected(String::from("locked")))
+    );
+    assert_eq!(
+        datastore.delete(&["locked", "b"]).await,
+        Err(DataStoreError::Rejected(String::from("locked")))
+    );
+    assert_eq!(
+        datastore
+            .update(&["locked"], |_| Some(String::from("b")))
+            .await
+            .unwrap_err(),
+        DataStoreError::Rejected(String::from("locked"))
+    );
+    assert_eq!(
+        datastore
+            .claim_with(&["locked"], Duration::from_secs(60), |_| {
+                Some((String::from("b"), Box::new(|_| None)))
+            })
+            .await,
+        Err(DataStoreError::Rejected(String::from("locked")))
+    );
+    assert_eq!(
+        datastore.get_current(&["locked", "b"]).await.unwrap().value,
+        None
+    );
+
+    assert_eq!(
+        *changed.lock().unwrap(),
+        vec![
+            String::from("set a"),
+            String::from("set a"),
+            String::from("delete a")
+        ]
+    );
+}
+
+/// Hook that rejects all writes while switched on
+struct SwitchHook {
+    reject: Arc<AtomicBool>,
+}
+
+impl DataStoreHook<String> for SwitchHook {
+    fn before_set(&mut self, _path: &[String], value: String) -> Result<String, String> {
+        match self.reject.load(Ordering::SeqCst) {
+            true => Err(String::from("rejected")),
+            false => Ok(value),
+        }
+    }
+}
+
+#[tokio::test]
+async fn rejected_claim_release() {
+    let reject = Arc::new(AtomicBool::new(false));
+    let datastore: DataStore<String> = DataStore::new_with_hooks(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+        vec![Box::new(SwitchHook {
+            reject: Arc::clone(&reject),
+        })],
+    )
+    .await;
+
+    datastore.set(&["queue"], String::from("ab")).await.unwrap();
+    let claim_id = datastore
+        .claim_with(&["queue"], Duration::from_millis(50), |current| {
+            let rest = String::from(&current?[1..]);
+            Some((rest, Box::new(|current| Some(format!("a{}", current?)))))
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the claimed part stays claimed while putting it back is rejected
+    reject.store(true, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    datastore.ping().await.unwrap();
+    assert_eq!(
+        datastore.get_current(&["queue"]).await.unwrap().value,
+        Some(String::from("b"))
+    );
+
+    // and is put back once the hook allows it
+    reject.store(false, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    datastore.ping().await.unwrap();
+    assert_eq!(
+        datastore.get_current(&["queue"]).await.unwrap().value,
+        Some(String::from("ab"))
+    );
+    assert!(!datastore
+        .complete_claim(&["queue"], claim_id)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn blobs() {
+    let database = DbSchema::new_memory();
+    let config = DatastoreConfig {
+        database_schema: None,
+        keep_history: false,
+        history_max_age: None,
+        history_max_entries: None,
+        indexes: HashMap::new(),
+        full_text_search: false,
+        triggers: Vec::new(),
+        queue_depth: 1000,
+        request_timeout: 30000,
+        write_batch_max_size: 100,
+        write_batch_max_latency: 0,
+        max_claim_visibility_timeout: 43200,
+    };
+    let datastore: DataStore<serde_json::Value> =
+        DataStore::new("test", config.clone(), Some(database.clone())).await;
+
+    let image = vec![0x89, 0x50, 0x4e, 0x47, 0x00, 0xff];
+    datastore
+        .set_blob(&["records", "a", "image"], "image/png", image.clone())
+        .await
+        .unwrap();
+    datastore
+        .set_blob(&["records", "b", "image"], "image/png", image.clone())
+        .await
+        .unwrap();
+
+    // the value only contains the reference
+    let value = datastore
+        .get_current(&["records", "a", "image"])
+        .await
+        .unwrap();
+    let reference = BlobReference::from_value(value.value.clone().unwrap()).unwrap();
+    assert_eq!(reference.content_type, "image/png");
+    assert_eq!(reference.size, 6);
+    assert_eq!(reference.hash.len(), 64);
+
+    let (blob_reference, data) = datastore
+        .get_blob(&["records", "b", "image"])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(blob_reference, reference);
+    assert_eq!(data, image);
+
+    datastore
+        .set(&["records", "c"], json!("text"))
+        .await
+        .unwrap();
+    assert!(datastore
+        .get_blob(&["records", "c"])
+        .await
+        .unwrap()
+        .is_none());
+    assert!(datastore
+        .get_blob(&["records", "d"])
+        .await
+        .unwrap()
+        .is_none());
+
+    // values that can't hold the reference are rejected
+    let string_datastore: DataStore<String> = DataStore::new("strings", config, None).await;
+    assert!(string_datastore
+        .set_blob(&["a"], "image/png", image)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn blob_references() {
+    let database = DbSchema::new_memory();
+    let config = DatastoreConfig {
+        database_schema: None,
+        keep_history: true,
+        history_max_age: None,
+        history_max_entries: Some(1),
+        indexes: HashMap::new(),
+        full_text_search: false,
+        triggers: Vec::new(),
+        queue_depth: 1000,
+        request_timeout: 30000,
+        write_batch_max_size: 100,
+        write_batch_max_latency: 0,
+        max_claim_visibility_timeout: 43200,
+    };
+    let datastore: DataStore<serde_json::Value> =
+        DataStore::new("test", config.clone(), Some(database.clone())).await;
+
+    datastore
+        .set_blob(&["private", "image"], "image/png", vec![1, 2, 3])
+        .await
+        .unwrap();
+    let value = datastore.get_current(&["private", "image"]).await.unwrap();
+    let reference = BlobReference::from_value(value.value.clone().unwrap()).unwrap();
+
+    // references can only be written as blobs, not copied to other paths
+    for value in [
+        reference.to_value(),
+        json!({"nested": [reference.to_value()]}),
+    ] {
+        assert!(matches!(
+            datastore.set(&["public", "image"], value).await,
+            Err(DataStoreError::InvalidData(_))
+        ));
+    }
+    assert!(datastore
+        .get_blob(&["public", "image"])
+        .await
+        .unwrap()
+        .is_none());
+
+    // blobs are kept while history entries reference them
+    let stored = || database.datastore_get_blob("test", &config, &reference.hash);
+    datastore
+        .set(&["private", "image"], json!(null))
+        .await
+        .unwrap();
+    assert!(stored().is_some());
+
+    // and deleted once the last reference is removed
+    datastore.delete(&["private", "image"]).await.unwrap();
+    assert!(stored().is_none());
+
+    // content referenced by another path is kept
+    datastore
+        .set_blob(&["a"], "image/png", vec![4, 5])
+        .await
+        .unwrap();
+    datastore
+        .set_blob(&["b"], "image/png", vec![4, 5])
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        datastore.delete(&["a"]).await.unwrap();
+    }
+    let (_, data) = datastore.get_blob(&["b"]).await.unwrap().unwrap();
+    assert_eq!(data, vec![4, 5]);
+}
+
+/// Hook that rejects writes to the "locked" key
+struct LockedHook;
+
+impl DataStoreHook<serde_json::Value> for LockedHook {
+    fn before_set(
+        &mut self,
+        path: &[String],
+        value: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        match path.first().map(|x| x.as_str()) {
+            Some("locked") => Err(String::from("locked")),
+            _ => Ok(value),
+        }
+    }
+}
+
+#[tokio::test]
+async fn rejected_blob() {
+    let database = DbSchema::new_memory();
+    let config = DatastoreConfig {
+        database_schema: None,
+        keep_history: false,
+        history_max_age: None,
+        history_max_entries: None,
+        indexes: HashMap::new(),
+        full_text_search: false,
+        triggers: Vec::new(),
+        queue_depth: 1000,
+        request_timeout: 30000,
+        write_batch_max_size: 100,
+        write_batch_max_latency: 0,
+        max_claim_visibility_timeout: 43200,
+    };
+    let datastore: DataStore<serde_json::Value> = DataStore::new_with_hooks(
+        "test",
+        config.clone(),
+        Some(database.clone()),
+        vec![Box::new(LockedHook)],
+    )
+    .await;
+
+    // the content of a rejected write isn't stored
+    assert_eq!(
+        datastore
+            .set_blob(&["locked"], "image/png", vec![7, 8])
+            .await
+            .unwrap_err(),
+        DataStoreError::Rejected(String::from("locked"))
+    );
+    let reference = BlobReference::new("image/png", &[7, 8]);
+    assert!(database
+        .datastore_get_blob("test", &config, &reference.hash)
+        .is_none());
+
+    datastore
+        .set_blob(&["open"], "image/png", vec![7, 8])
+        .await
+        .unwrap();
+    assert!(database
+        .datastore_get_blob("test", &config, &reference.hash)
+        .is_some());
+}
+
+#[tokio::test]
+async fn overload() {
+    let datastore: DataStore<String> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1,
+            request_timeout: 100,
+            write_batch_max_size: 100,
+            write_batch_max_latency: 0,
+            max_claim_visibility_timeout: 43200,
+        },
+        None,
+    )
+    .await;
+
+    // keep the data store thread busy
+    let busy = datastore.clone();
+    let task = tokio::spawn(async move {
+        busy.update(&["a"], |_| {
+            std::thread::sleep(Duration::from_millis(300));
+            Some(String::from("a"))
+        })
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // the first request waits in the queue, the second doesn't fit
+    let queued = datastore.clone();
+    let queued = tokio::spawn(async move { queued.ping().await });
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert_eq!(datastore.ping().await, Err(DataStoreError::Overloaded));
+
+    assert_eq!(queued.await.unwrap(), Err(DataStoreError::Timeout));
+    assert_eq!(task.await.unwrap().unwrap_err(), DataStoreError::Timeout);
+
+    // the update was still applied once the thread got to it
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(
+        datastore.get_current(&["a"]).await.unwrap().value,
+        Some(String::from("a"))
+    );
+}
+
+#[tokio::test]
+async fn write_batching() {
+    let database = DbSchema::new_memory();
+    let datastore: DataStore<String> = DataStore::new(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 3,
+            write_batch_max_latency: 500,
+            max_claim_visibility_timeout: 43200,
+        },
+        Some(database),
+    )
+    .await;
+
+    let mut subscription = datastore.subscribe(&["a"]).await.unwrap();
+
+    // a full batch is committed without waiting
+    let started = Instant::now();
+    let writes: Vec<_> = ["a", "b", "c"]
+        .into_iter()
+        .map(|key| {
+            let datastore = datastore.clone();
+            tokio::spawn(async move { datastore.set(&[key], String::from(key)).await })
+        })
+        .collect();
+    for write in writes {
+        write.await.unwrap().unwrap();
+    }
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert_eq!(
+        subscription.recv().await.unwrap().value,
+        Some(String::from("a"))
+    );
+
+    // a partial batch is committed after the latency
+    let started = Instant::now();
+    let change_id = datastore.set(&["a"], String::from("1")).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(500));
+    assert_eq!(subscription.recv().await.unwrap().change_id, change_id);
+
+    // other requests commit the batch first and see its writes
+    let write = {
+        let datastore = datastore.clone();
+        tokio::spawn(async move { datastore.delete(&["b"]).await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let started = Instant::now();
+    assert_eq!(datastore.list(&[]).await.unwrap(), vec!["a", "c"]);
+    assert!(started.elapsed() < Duration::from_millis(500));
+    write.await.unwrap().unwrap();
+}
+
+/// Hook that makes every write take a while
+struct SlowHook;
+
+impl DataStoreHook<String> for SlowHook {
+    fn before_set(&mut self, _path: &[String], value: String) -> Result<String, String> {
+        std::thread::sleep(Duration::from_millis(20));
+        Ok(value)
+    }
+}
+
+#[tokio::test]
+async fn write_batch_latency_under_load() {
+    let datastore: DataStore<String> = DataStore::new_with_hooks(
+        "test",
+        DatastoreConfig {
+            database_schema: None,
+            keep_history: false,
+            history_max_age: None,
+            history_max_entries: None,
+            indexes: HashMap::new(),
+            full_text_search: false,
+            triggers: Vec::new(),
+            queue_depth: 1000,
+            request_timeout: 30000,
+            write_batch_max_size: 1000,
+            write_batch_max_latency: 100,
+            max_claim_visibility_timeout: 43200,
+        },
+        Some(DbSchema::new_memory()),
+        vec![Box::new(SlowHook)],
+    )
+    .await;
+
+    // the queue never runs empty while the writes are handled, without the batch getting full
+    let started = Instant::now();
+    let writes: Vec<_> = (0..20)
+        .map(|index| {
+            let datastore = datastore.clone();
+            tokio::spawn(async move {
+                datastore.set(&["load"], index.to_string()).await.unwrap();
+                started.elapsed()
+            })
+        })
+        .collect();
+    let mut elapsed = Vec::new();
+    for write in writes {
+        elapsed.push(write.await.unwrap());
+    }
+
+    // the first writes still become visible within the latency instead of after the whole stream
+    assert!(elapsed.iter().min().unwrap() < &Duration::from_millis(300));
+    assert!(elapsed.iter().max().unwrap() >= &Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn memory_storage() {
+    let config = DatastoreConfig {
+        database_schema: None,
+        keep_history: true,
+        history_max_age: None,
+        history_max_entries: Some(1),
+        indexes: HashMap::new(),
+        full_text_search: true,
+        triggers: Vec::new(),
+        queue_depth: 1000,
+        request_timeout: 30000,
+        write_batch_max_size: 100,
+        write_batch_max_latency: 0,
+        max_claim_visibility_timeout: 43200,
+    };
+
+    // the same requests give the same results with and without a database
+    let mut outputs = Vec::new();
+    for database in [None, Some(DbSchema::new_memory())] {
+        let datastore: DataStore<serde_json::Value> =
+            DataStore::new("test", config.clone(), database).await;
+
+        let first = datastore
+            .set(
+                &["users", "a"],
+                json!({"name": "ann", "age": 30, "bio": "likes green tea"}),
+            )
+            .await
+            .unwrap();
+        datastore
+            .set(
+                &["users", "a"],
+                json!({"name": "ann", "age": 31, "bio": "likes green tea and cake"}),
+            )
+            .await
+            .unwrap();
+        datastore
+            .set(
+                &["users", "a"],
+                json!({"name": "ann", "age": 32, "bio": "tea"}),
+            )
+            .await
+            .unwrap();
+        datastore
+            .set(&["users", "b"], json!({"name": "bob", "age": 25, "tags": ["admin"], "bio": "drinks tea & <i>coffee</i>"}))
+            .await
+            .unwrap();
+        datastore
+            .set(&["users", "c"], json!({"name": "cat", "bio": null}))
+            .await
+            .unwrap();
+        datastore.set(&["users", "d", "x"], json!(1)).await.unwrap();
+        datastore.set(&["users", "e"], json!(1)).await.unwrap();
+        datastore.delete(&["users", "e"]).await.unwrap();
+
+        let history: Vec<serde_json::Value> = datastore
+            .get_all(&["users", "a"], None)
+            .await
+            .unwrap()
+            .iter()
+            .map(|x| x.value.clone().unwrap())
+            .collect();
+        let history_after = datastore
+            .get_all(&["users", "a"], Some(first))
+            .await
+            .unwrap()
+            .len();
+
+        let list = datastore.list(&["users"]).await.unwrap();
+
+        let query = |filters: Vec<DatastoreQueryFilter>, sort: Option<DatastoreQuerySort>| {
+            let datastore = datastore.clone();
+            async move {
+                let result = datastore
+                    .query(&["users"], &filters, sort, Some(2), Some(0))
+                    .await
+                    .unwrap();
+                let keys: Vec<String> = result
+                    .values
+                    .iter()
+                    .map(|x| x.path.last().unwrap().clone())
+                    .collect();
+                (keys, result.next_cursor)
+            }
+        };
+        let filter = |field: &str, operation| DatastoreQueryFilter {
+            field: vec![String::from(field)],
+            operation,
+        };
+        let queries = vec![
+            query(Vec::new(), None).await,
+            query(
+                Vec::new(),
+                Some(DatastoreQuerySort {
+                    field: vec![String::from("age")],
+                    descending: true,
+                }),
+            )
+            .await,
+            query(
+                Vec::new(),
+                Some(DatastoreQuerySort {
+                    field: vec![String::from("age")],
+                    descending: false,
+                }),
+            )
+            .await,
+            query(
+                vec![filter("age", DatastoreQueryOperation::Ge(json!(30)))],
+                None,
+            )
+            .await,
+            query(
+                vec![filter("bio", DatastoreQueryOperation::Eq(json!(null)))],
+                None,
+            )
+            .await,
+            query(
+                vec![filter(
+                    "tags",
+                    DatastoreQueryOperation::Contains(json!("admin")),
+                )],
+                None,
+            )
+            .await,
+            query(
+                vec![filter(
+                    "bio",
+                    DatastoreQueryOperation::Contains(json!("tea")),
+                )],
+                None,
+            )
+            .await,
+        ];
+
+        let changes = datastore.changes_since(&["users"], 0, None).await.unwrap();
+        let changes: Vec<(Vec<String>, Option<serde_json::Value>)> = changes
+            .changes
+            .iter()
+            .map(|x| (x.path.clone(), x.value.clone()))
+            .collect();
+
+        let search: Vec<(Vec<String>, String)> = datastore
+            .search(&["users"], "tea")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.path, x.snippet))
+            .collect();
+        let prefix_search = datastore.search(&[], "cof*").await.unwrap().len();
+
+        outputs.push((
+            history,
+            history_after,
+            list,
+            queries,
+            changes,
+            search,
+            prefix_search,
+        ));
+    }
+
+    assert_eq!(outputs[0], outputs[1]);
+    let (history, history_after, list, _, changes, search, prefix_search) = &outputs[0];
+    assert_eq!(history.len(), 2);
+    assert_eq!(*history_after, 2);
+    assert_eq!(list, &vec!["a", "b", "c", "d"]);
+    assert_eq!(changes.len(), 7);
+    assert_eq!(search[0].0, vec!["users", "a"]);
+    assert_eq!(search[0].1, "<mark>tea</mark> ann");
+    assert_eq!(*prefix_search, 1);
+}
+
+#[tokio::test]
+async fn export_import() {
+    let config = DatastoreConfig {
+        database_schema: None,
+        keep_history: true,
+        history_max_age: None,
+        history_max_entries: None,
+        indexes: HashMap::new(),
+        full_text_search: false,
+        triggers: Vec::new(),
+        queue_depth: 1000,
+        request_timeout: 30000,
+        write_batch_max_size: 100,
+        write_batch_max_latency: 0,
+        max_claim_visibility_timeout: 43200,
+    };
+
+    let source: DataStore<serde_json::Value> =
+        DataStore::new("source", config.clone(), Some(DbSchema::new_memory())).await;
+    source.set(&["users", "a"], json!("ann")).await.unwrap();
+    let first_b = source.set(&["users", "b"], json!("bob")).await.unwrap();
+    source.set(&["users", "b"], json!("ben")).await.unwrap();
+    source.set(&["users", "c"], json!("cid")).await.unwrap();
+    source.delete(&["users", "c"]).await.unwrap();
+    source.set(&["other"], json!(1)).await.unwrap();
+
+    // without history, only the set current values of the subtree are exported
+    let current = source.export(&["users"], false).await.unwrap();
+    let records: Vec<serde_json::Value> = current
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["path"], json!(["users", "a"]));
+    assert_eq!(records[1]["value"], json!("ben"));
+
+    let history = source.export(&["users"], true).await.unwrap();
+    assert_eq!(history.lines().count(), 5);
+
+    // imported values keep their change ids, and the last record is the current value
+    for database in [None, Some(DbSchema::new_memory())] {
+        let target: DataStore<serde_json::Value> =
+            DataStore::new("target", config.clone(), database).await;
+        assert_eq!(target.import(history.as_bytes()).await.unwrap(), 5);
+        assert_eq!(target.export(&["users"], true).await.unwrap(), history);
+        assert_eq!(
+            target.get_current(&["users", "b"]).await.unwrap().value,
+            Some(json!("ben"))
+        );
+        assert!(target
+            .get_all(&["users", "b"], None)
+            .await
+            .unwrap()
+            .iter()
+            .any(|value| value.change_id == first_b));
+        assert_eq!(
+            target.get_current(&["users", "c"]).await.unwrap().value,
+            None
+        );
+
+        // records that are already stored are skipped
+        assert_eq!(target.import(current.as_bytes()).await.unwrap(), 0);
+    }
+
+    // nothing is imported if a record is invalid
+    let target: DataStore<serde_json::Value> = DataStore::new("target", config, None).await;
+    let invalid = format!("{}\n{{\"path\": 1}}\n", current.lines().next().unwrap());
+    assert!(matches!(
+        target.import(invalid.as_bytes()).await,
+        Err(DataStoreError::InvalidData(reason)) if reason.starts_with("Line 2:")
+    ));
+    assert_eq!(target.export(&[], true).await.unwrap(), "");
+}
@@ -0,0 +1,82 @@
+use serde_json::json;
+
+use crate::{
+    auth::AuthIdentity,
+    config::DataAccessRuleConfig,
+    datastore::{access::DataAccessRules, patterns::PathPattern},
+};
+
+fn identity(username: Option<&str>, roles: &[&str]) -> AuthIdentity {
+    AuthIdentity {
+        username: username.map(String::from),
+        roles: roles.iter().map(|x| String::from(*x)).collect(),
+        second_factor: false,
+    }
+}
+
+fn rules() -> DataAccessRules {
+    let config: Vec<DataAccessRuleConfig> = serde_json::from_value(json!([
+        {"path": "users/{uid}/**", "read": true, "write": {"any": [{"user": "{uid}"}, ["admin"]]}},
+        {"path": "rooms/{id}", "read": ["member_{id}"]},
+        {"path": "rooms/*/secret", "read": false, "write": false},
+        {"path": "**", "write": ["editor"]},
+    ]))
+    .unwrap();
+    DataAccessRules::new(&config)
+}
+
+#[test]
+fn user_paths() {
+    let rules = rules();
+    let alice = identity(Some("alice"), &[]);
+    let admin = identity(Some("bob"), &["admin"]);
+
+    assert!(rules.can_write(&["users", "alice"], &alice));
+    assert!(rules.can_write(&["users", "alice", "profile", "name"], &alice));
+    assert!(!rules.can_write(&["users", "bob"], &alice));
+    assert!(rules.can_write(&["users", "alice"], &admin));
+    assert!(rules.can_read(&["users", "bob"], &alice));
+
+    // placeholders in keys are not expanded again
+    assert!(!rules.can_write(&["users", "{uid}"], &identity(Some("{uid}x"), &[])));
+}
+
+#[test]
+fn captured_roles() {
+    let rules = rules();
+    let member = identity(Some("alice"), &["member_1"]);
+
+    assert!(rules.can_read(&["rooms", "1"], &member));
+    assert!(!rules.can_read(&["rooms", "2"], &member));
+    assert!(!rules.can_read(&["rooms", "1"], &AuthIdentity::default()));
+
+    // rules without a condition for the access type are skipped
+    assert!(!rules.can_write(&["rooms", "1"], &member));
+    assert!(rules.can_write(&["rooms", "1"], &identity(Some("bob"), &["editor"])));
+
+    assert!(!rules.can_read(&["rooms", "1", "secret"], &member));
+    assert!(!rules.can_write(&["rooms", "1", "secret"], &identity(None, &["editor"])));
+}
+
+#[test]
+fn unmatched_paths() {
+    let rules = DataAccessRules::new(&[]);
+    assert!(rules.can_read(&["a"], &AuthIdentity::default()));
+    assert!(rules.can_write(&[], &AuthIdentity::default()));
+
+    // nested rules don't match their parents
+    let rules = self::rules();
+    assert!(rules.can_read(&["users"], &AuthIdentity::default()));
+    assert!(rules.can_read(&["rooms", "1", "other"], &AuthIdentity::default()));
+}
+
+#[test]
+fn invalid_patterns() {
+    // "**" would match any keys after it
+    assert!(PathPattern::new("a/**/b").is_err());
+    assert!(PathPattern::new("a/**").is_ok());
+    assert!(serde_json::from_value::<Vec<DataAccessRuleConfig>>(json!([
+        {"path": "users/**/private", "read": false},
+    ]))
+    .is_err());
+}
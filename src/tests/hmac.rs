@@ -0,0 +1,47 @@
+use crate::helpers::hmac::{constant_time_eq, hmac_sha1, hmac_sha256};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn rfc4231_vectors() {
+    assert_eq!(
+        hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+
+    // keys longer than a block are hashed
+    assert_eq!(
+        hex(&hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        )),
+        "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+    );
+}
+
+#[test]
+fn rfc2202_vectors() {
+    assert_eq!(
+        hex(&hmac_sha1(b"Jefe", b"what do ya want for nothing?")),
+        "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+    );
+
+    // keys longer than a block are hashed
+    assert_eq!(
+        hex(&hmac_sha1(
+            &[0xaa; 80],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        )),
+        "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+    );
+}
+
+#[test]
+fn constant_time_comparison() {
+    assert!(constant_time_eq(b"123456", b"123456"));
+    assert!(!constant_time_eq(b"123456", b"123457"));
+    assert!(!constant_time_eq(b"123456", b"12345"));
+    assert!(constant_time_eq(b"", b""));
+}
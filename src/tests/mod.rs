@@ -1,4 +1,9 @@
 //! Tests
 
+pub mod application;
+pub mod auth;
 pub mod datastore;
+pub mod datastore_access;
+pub mod hmac;
 pub mod tlru_cache;
+pub mod totp;
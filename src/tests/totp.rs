@@ -0,0 +1,389 @@
+use chrono::Utc;
+use serde_json::json;
+use tokio::{net::TcpListener, sync::oneshot};
+
+use super::application::http_request;
+use crate::{
+    application::Application,
+    auth::{admin::AuthAdminError, totp::totp_code, Auth, LoginStep},
+    config::{AuthenticationConfig, Config},
+    database::DbSchema,
+};
+
+fn auth(database: &DbSchema) -> Auth {
+    let config: AuthenticationConfig = serde_json::from_value(json!({
+        "database_schema": "auth",
+        "defaults": {
+            "roles": [],
+            "users": {"alice": {"default_password": "secret", "roles": []}},
+        },
+    }))
+    .unwrap();
+    Auth::new(&config, database.clone())
+}
+
+/// Decodes an unpadded base32 secret as shown to users
+fn base32_decode(encoded: &str) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for char in encoded.bytes() {
+        let value = match char {
+            b'A'..=b'Z' => char - b'A',
+            b'2'..=b'7' => char - b'2' + 26,
+            _ => panic!("invalid base32"),
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    decoded
+}
+
+/// Gets the TOTP code of a secret for a time step relative to the current one
+fn code(secret: &[u8], offset: i64) -> String {
+    totp_code(secret, Utc::now().timestamp() / 30 + offset)
+}
+
+#[test]
+fn rfc6238_vectors() {
+    let secret = b"12345678901234567890";
+    // the reference values have 8 digits, codes are the last 6
+    assert_eq!(totp_code(secret, 59 / 30), "287082");
+    assert_eq!(totp_code(secret, 1111111109 / 30), "081804");
+    assert_eq!(totp_code(secret, 1234567890 / 30), "005924");
+    assert_eq!(totp_code(secret, 2000000000 / 30), "279037");
+}
+
+#[tokio::test]
+async fn second_factor_login() {
+    let database = DbSchema::new_memory();
+    let auth = auth(&database);
+
+    let enrollment = auth.start_totp_enrollment("alice").await.unwrap();
+    assert_eq!(enrollment.secret.len(), 32);
+    assert!(enrollment.uri.starts_with(&format!(
+        "otpauth://totp/GarnetDG:alice?secret={}&issuer=GarnetDG",
+        enrollment.secret
+    )));
+    let secret = base32_decode(&enrollment.secret);
+
+    // unconfirmed secrets aren't required at login
+    assert!(matches!(
+        auth.login("alice", "secret").await,
+        Some(LoginStep::Session(_))
+    ));
+    assert_eq!(
+        auth.confirm_totp_enrollment("alice", "000000")
+            .await
+            .unwrap_err(),
+        AuthAdminError::InvalidCode
+    );
+    // the previous time step is accepted for clock drift
+    let recovery_codes = auth
+        .confirm_totp_enrollment("alice", &code(&secret, -1))
+        .await
+        .unwrap();
+    assert_eq!(recovery_codes.len(), 10);
+    assert!(auth.get_user("alice").await.unwrap().second_factor);
+    assert_eq!(
+        auth.start_totp_enrollment("alice").await.unwrap_err(),
+        AuthAdminError::AlreadyExists
+    );
+
+    // the password alone only gets a challenge
+    let Some(LoginStep::SecondFactor(challenge)) = auth.login("alice", "secret").await else {
+        panic!("second factor not required");
+    };
+    // codes can't be used twice
+    assert!(auth
+        .confirm_login(&challenge, &code(&secret, -1))
+        .await
+        .is_none());
+    let token = auth
+        .confirm_login(&challenge, &code(&secret, 0))
+        .await
+        .unwrap();
+    assert!(auth.identify(&token).await.unwrap().second_factor);
+    assert!(auth
+        .confirm_login(&challenge, &code(&secret, 1))
+        .await
+        .is_none());
+
+    // recovery codes work once, with or without the dash
+    let Some(LoginStep::SecondFactor(challenge)) = auth.login("alice", "secret").await else {
+        panic!("second factor not required");
+    };
+    let recovery_code = recovery_codes[0].replace('-', "");
+    assert!(auth
+        .confirm_login(&challenge, &recovery_code)
+        .await
+        .is_some());
+    let Some(LoginStep::SecondFactor(challenge)) = auth.login("alice", "secret").await else {
+        panic!("second factor not required");
+    };
+    assert!(auth
+        .confirm_login(&challenge, &recovery_codes[0])
+        .await
+        .is_none());
+
+    // challenges are discarded after too many wrong codes
+    for _ in 0..4 {
+        assert!(auth.confirm_login(&challenge, "000000").await.is_none());
+    }
+    assert!(auth
+        .confirm_login(&challenge, &recovery_codes[1])
+        .await
+        .is_none());
+
+    let new_codes = auth
+        .regenerate_recovery_codes("alice", &code(&secret, 1))
+        .await
+        .unwrap();
+    let Some(LoginStep::SecondFactor(challenge)) = auth.login("alice", "secret").await else {
+        panic!("second factor not required");
+    };
+    assert!(auth
+        .confirm_login(&challenge, &recovery_codes[2])
+        .await
+        .is_none());
+    assert!(auth
+        .confirm_login(&challenge, &new_codes[0])
+        .await
+        .is_some());
+
+    // administrators can remove second factors that were lost
+    auth.reset_second_factor("alice").await.unwrap();
+    assert_eq!(
+        auth.reset_second_factor("alice").await.unwrap_err(),
+        AuthAdminError::SecondFactorNotFound
+    );
+    assert!(!auth.get_user("alice").await.unwrap().second_factor);
+    assert!(matches!(
+        auth.login("alice", "secret").await,
+        Some(LoginStep::Session(_))
+    ));
+
+    // users can remove their second factor with a code
+    let secret = base32_decode(&auth.start_totp_enrollment("alice").await.unwrap().secret);
+    let recovery_codes = auth
+        .confirm_totp_enrollment("alice", &code(&secret, 0))
+        .await
+        .unwrap();
+    assert_eq!(
+        auth.disable_totp("alice", "000000").await.unwrap_err(),
+        AuthAdminError::InvalidCode
+    );
+    auth.disable_totp("alice", &recovery_codes[0])
+        .await
+        .unwrap();
+    assert!(matches!(
+        auth.login("alice", "secret").await,
+        Some(LoginStep::Session(_))
+    ));
+}
+
+#[tokio::test]
+async fn second_factor_lockout() {
+    let database = DbSchema::new_memory();
+    let auth = auth(&database);
+
+    let secret = base32_decode(&auth.start_totp_enrollment("alice").await.unwrap().secret);
+    // secrets are stored encrypted
+    let user_id = database.auth_get_user("alice").unwrap().id;
+    let stored_secret = database.auth_get_totp(user_id).unwrap().secret;
+    let secret_hex: String = secret.iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert!(!stored_secret.contains(&secret_hex));
+    let recovery_codes = auth
+        .confirm_totp_enrollment("alice", &code(&secret, -1))
+        .await
+        .unwrap();
+
+    // wrong codes are counted across challenges, so new challenges don't allow more guesses
+    for _ in 0..2 {
+        let Some(LoginStep::SecondFactor(challenge)) = auth.login("alice", "secret").await else {
+            panic!("second factor not required");
+        };
+        for _ in 0..5 {
+            assert!(auth.confirm_login(&challenge, "000000").await.is_none());
+        }
+    }
+    let Some(LoginStep::SecondFactor(challenge)) = auth.login("alice", "secret").await else {
+        panic!("second factor not required");
+    };
+    assert!(auth
+        .confirm_login(&challenge, &code(&secret, 0))
+        .await
+        .is_none());
+    assert!(auth
+        .confirm_login(&challenge, &recovery_codes[0])
+        .await
+        .is_none());
+    assert_eq!(
+        auth.regenerate_recovery_codes("alice", &code(&secret, 1))
+            .await
+            .unwrap_err(),
+        AuthAdminError::InvalidCode
+    );
+
+    // administrators can let locked out users enroll again
+    auth.reset_second_factor("alice").await.unwrap();
+    let secret = base32_decode(&auth.start_totp_enrollment("alice").await.unwrap().secret);
+    auth.confirm_totp_enrollment("alice", &code(&secret, 0))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn admin_second_factor() {
+    let config: Config = serde_json::from_value(json!({
+        "server": {"host": "127.0.0.1", "port": 8080},
+        "databases": {
+            "connections": {"main": {"driver": "SQLite3", "database": ":memory:"}},
+            "schemas": {"main": {"connection": "main", "table_prefix": null}},
+        },
+        "authentication": {
+            "database_schema": "main",
+            "defaults": {
+                "roles": ["admin"],
+                "users": {"alice": {"default_password": "alice", "roles": ["admin"]}},
+            },
+        },
+        "routes": {
+            "/auth": {"handler": "Auth"},
+            "/admin": {"handler": "AuthAdmin", "permissions": {"read": ["admin"], "write": ["admin"]}},
+        },
+    }))
+    .unwrap();
+
+    let application = Application::build(&config).await;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (signal_tx, signal_rx) = oneshot::channel::<()>();
+
+    let client = async {
+        let post = |path: &'static str, authorization: Option<String>, body: serde_json::Value| async move {
+            let mut headers = vec![("Content-Type", "application/json")];
+            if let Some(authorization) = &authorization {
+                headers.push(("Authorization", authorization));
+            }
+            let (status, body) =
+                http_request(address, "POST", path, &headers, &body.to_string()).await;
+            (
+                status,
+                serde_json::from_str::<serde_json::Value>(&body).ok(),
+            )
+        };
+        let get = |path: &'static str, authorization: String| async move {
+            http_request(
+                address,
+                "GET",
+                path,
+                &[("Authorization", &authorization)],
+                "",
+            )
+            .await
+            .0
+        };
+
+        let (status, body) = post(
+            "/auth/login",
+            None,
+            json!({"username": "alice", "password": "alice"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let session = format!("Bearer {}", body.unwrap()["token"].as_str().unwrap());
+
+        // administration needs a second factor
+        assert_eq!(get("/admin/users", session.clone()).await, 403);
+
+        let (status, body) = post("/auth/totp", Some(session.clone()), json!({})).await;
+        assert_eq!(status, 200);
+        let secret = base32_decode(body.unwrap()["secret"].as_str().unwrap());
+        let (status, body) = post(
+            "/auth/totp/confirm",
+            Some(session.clone()),
+            json!({"code": code(&secret, 0)}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body.unwrap()["recovery_codes"].as_array().unwrap().len(),
+            10
+        );
+
+        // the session isn't upgraded, the user has to log in again
+        assert_eq!(get("/admin/users", session.clone()).await, 403);
+
+        let (status, body) = post(
+            "/auth/login",
+            None,
+            json!({"username": "alice", "password": "alice"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let body = body.unwrap();
+        assert_eq!(body["second_factor_required"], true);
+        assert!(body.get("token").is_none());
+        let challenge = String::from(body["challenge"].as_str().unwrap());
+
+        let (status, _) = post(
+            "/auth/login/second-factor",
+            None,
+            json!({"challenge": challenge, "code": "000000"}),
+        )
+        .await;
+        assert_eq!(status, 401);
+        let (status, body) = post(
+            "/auth/login/second-factor",
+            None,
+            json!({"challenge": challenge, "code": code(&secret, 1)}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        let confirmed = format!("Bearer {}", body.unwrap()["token"].as_str().unwrap());
+
+        assert_eq!(get("/admin/users", confirmed.clone()).await, 200);
+        let (status, body) = http_request(
+            address,
+            "GET",
+            "/auth/me",
+            &[("Authorization", &confirmed)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.contains("\"second_factor\":true"));
+
+        let (status, _) = http_request(
+            address,
+            "DELETE",
+            "/admin/users/alice/second-factor",
+            &[("Authorization", &confirmed)],
+            "",
+        )
+        .await;
+        assert_eq!(status, 204);
+        let (status, body) = post(
+            "/auth/login",
+            None,
+            json!({"username": "alice", "password": "alice"}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert!(body.unwrap().get("token").is_some());
+
+        signal_tx.send(()).unwrap();
+    };
+    let server = application.serve(listener, async {
+        signal_rx.await.ok();
+    });
+
+    let (result, _) = tokio::join!(server, client);
+    result.unwrap();
+    application.stop().await;
+}